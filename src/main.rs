@@ -1,16 +1,27 @@
 #[macro_use] extern crate actix_web;
 
 use std::env;
+use std::path::PathBuf;
+use std::process;
 
 use clap::Clap;
 
+use crate::waitmate::agent;
+use crate::waitmate::alerts;
+use crate::waitmate::api::Level;
 use crate::waitmate::app::App;
+use crate::waitmate::error::{Error, Result};
+use crate::waitmate::offsets;
+use crate::waitmate::query::EventQuery;
+use crate::waitmate::silences;
+use crate::waitmate::silences::NewSilence;
 
 mod waitmate;
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "mark@markriley.net")]
 struct Opts {
+    /// A config file read over the default ones, and again on every reload
     #[clap(short, long)]
     config: Option<String>,
 
@@ -27,13 +38,65 @@ enum SubCommand {
     Client(ClientOpts),
 
     #[clap(version = "1.0", author = "mark@markriley.net")]
-    Dump,
+    Dump(DumpOpts),
+
+    /// Write events as newline delimited JSON
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Export(ExportOpts),
+
+    /// Read events written by export, skipping those already in the log
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Import(ImportOpts),
+
+    /// Snapshot the log, including cursor offsets, to a directory without stopping the server
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Backup(BackupOpts),
+
+    /// Replace the local log with a backup while the server is stopped
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Restore(RestoreOpts),
+
+    /// List, move or delete the positions of named consumers such as notifiers
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Offsets(OffsetsOpts),
+
+    /// Search event descriptions, e.g. `order 12345`, `pay*` or `"card declined"`
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Search(SearchOpts),
+
+    /// List the clients known to a server
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Agents(AgentsOpts),
+
+    /// List the alerts opened by events, with their state
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Alerts(AlertsOpts),
+
+    /// Acknowledge an open alert by id or fingerprint
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Ack(AlertOpts),
+
+    /// Resolve an alert by id or fingerprint
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Resolve(AlertOpts),
+
+    /// List, create or delete the silences that keep notifiers quiet about matching events
+    #[clap(version = "1.0", author = "mark@markriley.net")]
+    Silences(SilencesOpts),
 }
 
 #[derive(Clap)]
 struct ServerOpts {
     #[clap(short, long, default_value = "tcp://*:12345")]
-    listen: String
+    listen: String,
+
+    /// Relay matching events to the server at this address
+    #[clap(short, long)]
+    upstream: Option<String>,
+
+    /// Filter expression selecting the events relayed upstream, e.g. 'level >= ERROR && labels.env == "prod"'
+    #[clap(long, default_value = "level >= WARN")]
+    relay_filter: String
 }
 
 #[derive(Clap)]
@@ -42,6 +105,209 @@ struct ClientOpts {
     connect: String
 }
 
+#[derive(Clap)]
+struct DumpOpts {
+    /// Only dump events at or above this level
+    #[clap(long)]
+    min_level: Option<String>,
+
+    /// Only events matching this expression, e.g. 'level >= WARN && labels.env == "prod"'
+    #[clap(short, long)]
+    filter: Option<String>,
+
+    /// Only the newest N events
+    #[clap(short, long)]
+    last: Option<usize>
+}
+
+#[derive(Clap)]
+struct ExportOpts {
+    /// Write to this file instead of stdout
+    #[clap(short, long)]
+    output: Option<String>,
+
+    /// Compress the output with gzip, implied by an output file ending in .gz
+    #[clap(short, long)]
+    gzip: bool,
+
+    /// Only events at or after this time, in microseconds since the epoch
+    #[clap(long)]
+    from: Option<u64>,
+
+    /// Only events before this time, in microseconds since the epoch
+    #[clap(long)]
+    to: Option<u64>,
+
+    #[clap(long)]
+    min_level: Option<String>,
+
+    #[clap(long)]
+    category: Option<String>,
+
+    /// Comma separated key:value labels the events must have
+    #[clap(long)]
+    label: Option<String>,
+
+    /// Only events matching this expression, e.g. 'category =~ "db.*"'
+    #[clap(short, long)]
+    filter: Option<String>
+}
+
+#[derive(Clap)]
+struct ImportOpts {
+    /// The file to read, plain or gzip compressed, or - for stdin
+    input: String
+}
+
+#[derive(Clap)]
+struct BackupOpts {
+    /// The directory to create, under the server's admin.backup_root when --server is given
+    dir: String,
+
+    /// Ask a running server to take the backup, e.g. http://127.0.0.1:12346, sending $WAITMATE_TOKEN
+    #[clap(short, long)]
+    server: Option<String>
+}
+
+#[derive(Clap)]
+struct RestoreOpts {
+    /// A directory or file written by backup
+    backup: String,
+
+    /// Replace an existing log
+    #[clap(short, long)]
+    force: bool
+}
+
+#[derive(Clap)]
+struct OffsetsOpts {
+    /// Manage the offsets of a running server, e.g. http://127.0.0.1:12346, sending $WAITMATE_TOKEN
+    #[clap(short, long)]
+    server: Option<String>,
+
+    #[clap(subcommand)]
+    command: OffsetsCommand
+}
+
+#[derive(Clap)]
+enum OffsetsCommand {
+    /// Show each consumer's last key and how many events it is behind
+    List,
+    /// Move a consumer to a key, a timestamp, `earliest` or `latest`
+    Set(SetOffsetOpts),
+    /// Move a consumer back to the start of the log
+    Reset(OffsetNameOpts),
+    /// Forget a consumer
+    Delete(OffsetNameOpts),
+}
+
+#[derive(Clap)]
+struct SetOffsetOpts {
+    name: String,
+    position: String
+}
+
+#[derive(Clap)]
+struct OffsetNameOpts {
+    name: String
+}
+
+#[derive(Clap)]
+struct SearchOpts {
+    query: String,
+
+    /// Only events at or after this time, in microseconds since the epoch
+    #[clap(long)]
+    from: Option<u64>,
+
+    /// Only events before this time, in microseconds since the epoch
+    #[clap(long)]
+    to: Option<u64>,
+
+    #[clap(short, long, default_value = "100")]
+    limit: usize,
+
+    /// Index events written before the search index existed first
+    #[clap(long)]
+    reindex: bool
+}
+
+#[derive(Clap)]
+struct AgentsOpts {
+    #[clap(short, long, default_value = "http://127.0.0.1:12346")]
+    server: String
+}
+
+#[derive(Clap)]
+struct AlertsOpts {
+    /// List the alerts of a running server, e.g. http://127.0.0.1:12346
+    #[clap(short, long)]
+    server: Option<String>
+}
+
+#[derive(Clap)]
+struct AlertOpts {
+    id: String,
+
+    /// Who is handling it, by default $USER
+    #[clap(short, long)]
+    by: Option<String>,
+
+    /// Change the alert on a running server, e.g. http://127.0.0.1:12346, sending $WAITMATE_TOKEN
+    #[clap(short, long)]
+    server: Option<String>
+}
+
+#[derive(Clap)]
+struct SilencesOpts {
+    /// Manage the silences of a running server, e.g. http://127.0.0.1:12346, sending $WAITMATE_TOKEN
+    #[clap(short, long)]
+    server: Option<String>,
+
+    #[clap(subcommand)]
+    command: SilencesCommand
+}
+
+#[derive(Clap)]
+enum SilencesCommand {
+    /// Show every silence that has not expired
+    List,
+    /// Silence the events matching a filter expression, e.g. `category == web`
+    Add(AddSilenceOpts),
+    /// Lift a silence before it ends
+    Delete(SilenceIdOpts),
+}
+
+#[derive(Clap)]
+struct AddSilenceOpts {
+    matcher: String,
+
+    /// When it starts, in seconds, milliseconds or microseconds since the epoch, by default now
+    #[clap(long)]
+    starts: Option<String>,
+
+    /// When it ends, in seconds, milliseconds or microseconds since the epoch
+    #[clap(long)]
+    ends: Option<String>,
+
+    /// How long it lasts instead, e.g. 30m or 2h
+    #[clap(short, long)]
+    duration: Option<String>,
+
+    /// Who is asking, by default $USER
+    #[clap(short, long)]
+    by: Option<String>,
+
+    /// Why, e.g. the deploy it covers
+    #[clap(short, long)]
+    comment: Option<String>
+}
+
+#[derive(Clap)]
+struct SilenceIdOpts {
+    id: String
+}
+
 fn main() {
     env_logger::init();
 
@@ -49,9 +315,119 @@ fn main() {
     let program = args[0].clone();
     let opts: Opts = Opts::parse();
 
-    match opts.sub_command {
-        SubCommand::Client(a) => App::new(true).run_client(a.connect.as_str()),
-        SubCommand::Server(a) => App::new(false).run_server(a.listen.as_str()),
-        SubCommand::Dump => App::new(false).dump(),
+    match run(opts) {
+        Ok(()) => {}
+        Err(e) => {
+            eprintln!("{}: {}", program, e);
+            process::exit(1);
+        }
     }
-}
\ No newline at end of file
+}
+
+fn parse_level(level: Option<String>) -> Result<Option<Level>> {
+    return match level {
+        Some(l) => l.parse().map(Some).map_err(Error::Invalid),
+        None => Ok(None)
+    };
+}
+
+/// Apply an alert command locally or through the server at `server`
+fn manage_alerts(config: Option<PathBuf>, server: Option<String>, command: alerts::Command) -> Result<()> {
+    return match server {
+        Some(server) => {
+            alerts::print_alerts(alerts::apply_remote(server.as_str(), &command)?);
+            Ok(())
+        }
+        None => App::new_config(false, config)?.manage_alerts(&command)
+    };
+}
+
+/// Apply a silence command locally or through the server at `server`
+fn manage_silences(config: Option<PathBuf>, server: Option<String>, command: silences::Command) -> Result<()> {
+    return match server {
+        Some(server) => {
+            silences::print_silences(silences::apply_remote(server.as_str(), &command)?);
+            Ok(())
+        }
+        None => App::new_config(false, config)?.manage_silences(&command)
+    };
+}
+
+fn user(by: Option<String>) -> String {
+    return by.or_else(|| env::var("USER").ok()).unwrap_or(String::from("unknown"));
+}
+
+fn run(opts: Opts) -> Result<()> {
+    let config = opts.config.map(PathBuf::from);
+    let app = |temp: bool| App::new_config(temp, config.clone());
+    return match opts.sub_command {
+        SubCommand::Client(a) => app(true)?.run_client(a.connect.as_str()),
+        SubCommand::Server(a) => app(false)?.run_server(a.listen.as_str(),
+                                                   a.upstream.as_deref(),
+                                                   a.relay_filter.as_str()),
+        SubCommand::Dump(a) => {
+            let mut query = EventQuery::default();
+            query.min_level = parse_level(a.min_level)?;
+            query.filter = a.filter.map(|f| f.parse()).transpose()?;
+            query.last = a.last;
+            app(false)?.dump(&query)
+        }
+        SubCommand::Export(a) => {
+            let mut query = EventQuery::default();
+            query.from = a.from;
+            query.to = a.to;
+            query.min_level = parse_level(a.min_level)?;
+            query.category = a.category;
+            query.label = a.label;
+            query.filter = a.filter.map(|f| f.parse()).transpose()?;
+            let gzip = a.gzip || a.output.as_ref().map_or(false, |o| o.ends_with(".gz"));
+            app(false)?.export(&query, a.output.as_deref(), gzip)
+        }
+        SubCommand::Import(a) => app(false)?.import(a.input.as_str()),
+        SubCommand::Backup(a) => match a.server {
+            Some(server) => App::backup_server(server.as_str(), a.dir.as_str()),
+            None => app(false)?.backup(a.dir.as_str())
+        },
+        SubCommand::Restore(a) => App::restore(config.clone(), a.backup.as_str(), a.force),
+        SubCommand::Offsets(a) => {
+            let command = match a.command {
+                OffsetsCommand::List => offsets::Command::List,
+                OffsetsCommand::Set(o) => offsets::Command::Set(o.name, o.position),
+                OffsetsCommand::Reset(o) => offsets::Command::Set(o.name, String::from("earliest")),
+                OffsetsCommand::Delete(o) => offsets::Command::Delete(o.name),
+            };
+            match a.server {
+                Some(server) => {
+                    offsets::print_offsets(offsets::apply_remote(server.as_str(), &command)?);
+                    Ok(())
+                }
+                None => app(false)?.offsets(&command)
+            }
+        }
+        SubCommand::Search(a) => app(false)?.search(a.query.as_str(),
+                                                    a.from.map(u128::from),
+                                                    a.to.map(u128::from),
+                                                    a.limit,
+                                                    a.reindex),
+        SubCommand::Agents(a) => agent::print_agents(a.server.as_str()),
+        SubCommand::Alerts(a) => manage_alerts(config.clone(), a.server, alerts::Command::List),
+        SubCommand::Ack(a) => manage_alerts(config.clone(), a.server, alerts::Command::Ack(a.id, user(a.by))),
+        SubCommand::Resolve(a) => manage_alerts(config.clone(), a.server, alerts::Command::Resolve(a.id, user(a.by))),
+        SubCommand::Silences(a) => {
+            let command = match a.command {
+                SilencesCommand::List => silences::Command::List,
+                SilencesCommand::Add(o) => silences::Command::Add(NewSilence {
+                    matcher: o.matcher,
+                    starts_at: o.starts.map(|t| silences::parse_time(t.as_str())).transpose()?,
+                    ends_at: o.ends.map(|t| silences::parse_time(t.as_str())).transpose()?,
+                    duration_secs: o.duration.map(|d| silences::parse_duration(d.as_str()).map(|d| d.as_secs()))
+                        .transpose()?,
+                    created_by: Some(user(o.by)),
+                    comment: o.comment
+                }),
+                SilencesCommand::Delete(o) => silences::Command::Delete(o.id),
+            };
+            manage_silences(config.clone(), a.server, command)
+        }
+    };
+}
@@ -0,0 +1,203 @@
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::waitmate::api::{Event, EventBus, Level, Named, now, Waiter};
+use crate::waitmate::error::Result;
+use crate::waitmate::http;
+use crate::waitmate::log::EventLog;
+use crate::waitmate::net::Heartbeat;
+
+/// What the server knows about a client
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AgentRecord {
+    pub host: String,
+    pub pid: u32,
+    pub version: String,
+    pub spool_depth: u64,
+    pub first_seen: u128,
+    pub last_seen: u128,
+    pub silent: bool,
+}
+impl AgentRecord {
+    pub fn key(&self) -> String {
+        return format!("{}:{}", self.host, self.pid);
+    }
+}
+
+/// The connected clients, kept in the `agents` column family of the event log
+pub struct Registry {
+    event_log: Arc<EventLog>,
+    lock: Mutex<()>
+}
+impl Registry {
+    const CF: &'static str = "agents";
+
+    pub fn new(event_log: Arc<EventLog>) -> Self {
+        return Registry {
+            event_log,
+            lock: Mutex::new(())
+        }
+    }
+    pub fn list(&self) -> Result<Vec<AgentRecord>> {
+        return Ok(self.event_log.entries(Registry::CF)?
+            .iter()
+            .filter_map(|(_, v)| serde_json::from_slice(v).ok())
+            .collect());
+    }
+    pub fn get(&self, key: &str) -> Result<Option<AgentRecord>> {
+        return Ok(self.event_log.get_entry(Registry::CF, key.as_bytes())?
+            .and_then(|v| serde_json::from_slice(&v).ok()));
+    }
+    fn put(&self, record: &AgentRecord) -> Result<()> {
+        return self.event_log.put_entry(Registry::CF,
+                                        record.key().as_bytes(),
+                                        &serde_json::to_vec(record)?);
+    }
+    /// Record a heartbeat, announcing agents that come back after going silent
+    pub fn heartbeat(&self, heartbeat: &Heartbeat, source: &dyn Named, bus: &dyn EventBus) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let time = now();
+        let key = format!("{}:{}", heartbeat.host, heartbeat.pid);
+        let previous = self.get(key.as_str())?;
+        let record = AgentRecord {
+            host: heartbeat.host.clone(),
+            pid: heartbeat.pid,
+            version: heartbeat.version.clone(),
+            spool_depth: heartbeat.spool_depth,
+            first_seen: previous.as_ref().map_or(time, |p| p.first_seen),
+            last_seen: time,
+            silent: false
+        };
+        if previous.map_or(false, |p| p.silent) {
+            bus.publish(Event::builder("Agent returned")
+                .source(source)
+                .description(format!("Agent {} is sending heartbeats again", key).as_str())
+                .category("agent")
+                .level(Level::INFO)
+                .host(heartbeat.host.as_str())
+                .label("agent", key.as_str())
+                .build());
+        }
+        return self.put(&record);
+    }
+    /// Mark agents that have not been heard from within `timeout` as silent, publishing
+    /// an error for each one newly marked
+    pub fn check(&self, timeout: Duration, source: &dyn Named, bus: &dyn EventBus) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let deadline = now().saturating_sub(timeout.as_micros());
+        for mut record in self.list()? {
+            if !record.silent && record.last_seen < deadline {
+                record.silent = true;
+                self.put(&record)?;
+                bus.publish(Event::builder("Agent silent")
+                    .source(source)
+                    .description(format!("Agent {} (version {}) has missed its heartbeats for {}s with {} events spooled",
+                                         record.key(), record.version, timeout.as_secs(), record.spool_depth).as_str())
+                    .category("agent")
+                    .level(Level::ERROR)
+                    .host(record.host.as_str())
+                    .label("agent", record.key().as_str())
+                    .build());
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Periodically checks the registry for agents that have gone silent
+pub struct AgentMonitor {
+    registry: Arc<Registry>,
+    timeout: Duration
+}
+impl AgentMonitor {
+    const NAME: &'static str = "AgentMonitor";
+    pub fn new(registry: Arc<Registry>, timeout: Duration) -> Self {
+        return AgentMonitor {
+            registry,
+            timeout
+        }
+    }
+}
+impl Named for AgentMonitor {
+    fn name(&self) -> &str {
+        return AgentMonitor::NAME;
+    }
+}
+impl Waiter for AgentMonitor {
+    fn wait(&self, bus: &dyn EventBus) -> Result<()> {
+        let interval = (self.timeout / 4).max(Duration::from_millis(100));
+        let mut last_check = Instant::now();
+        while !bus.stopping() {
+            sleep(Duration::from_millis(100));
+            if last_check.elapsed() >= interval {
+                self.registry.check(self.timeout, self, bus)?;
+                last_check = Instant::now();
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Print the agents known to the server at `server`
+pub fn print_agents(server: &str) -> Result<()> {
+    let url = format!("{}/api/v1/agents", server.trim_end_matches('/'));
+    let records: Vec<AgentRecord> = serde_json::from_value(http::get_json(url.as_str())?)?;
+    for r in records {
+        println!("{:<30} {:>8} {:<10} {:>8} {:>20} {}",
+                 r.host, r.pid, r.version, r.spool_depth, r.last_seen,
+                 if r.silent { "SILENT" } else { "OK" });
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use crate::waitmate::agent::Registry;
+    use crate::waitmate::api::{EmptyEventBus, EmptyNamed, Level};
+    use crate::waitmate::log::EventLog;
+    use crate::waitmate::net::Heartbeat;
+    use crate::waitmate::thread::EventChannel;
+
+    #[test]
+    fn test_registry_silence() {
+        let dir = tempdir().unwrap().into_path().join("t.rdb");
+        let registry = Registry::new(Arc::new(EventLog::new(dir.as_path()).unwrap()));
+        let source = EmptyNamed {};
+        let heartbeat = Heartbeat {
+            host: String::from("h"),
+            pid: 1,
+            version: String::from("0.1.0"),
+            spool_depth: 3
+        };
+        let (bus, receiver) = EventChannel::new();
+
+        registry.heartbeat(&heartbeat, &source, &EmptyEventBus {}).unwrap();
+        registry.check(Duration::from_secs(60), &source, &bus).unwrap();
+        assert!(receiver.is_empty());
+
+        std::thread::sleep(Duration::from_millis(10));
+        registry.check(Duration::from_millis(1), &source, &bus).unwrap();
+        let e = receiver.try_recv().unwrap().unwrap();
+        assert_eq!(Level::ERROR, e.level);
+        assert!(registry.get("h:1").unwrap().unwrap().silent);
+
+        registry.check(Duration::from_millis(1), &source, &bus).unwrap();
+        assert!(receiver.is_empty());
+
+        registry.heartbeat(&heartbeat, &source, &bus).unwrap();
+        let e = receiver.try_recv().unwrap().unwrap();
+        assert_eq!(Level::INFO, e.level);
+        let agents = registry.list().unwrap();
+        assert_eq!(1, agents.len());
+        assert!(!agents[0].silent);
+        assert_eq!(3, agents[0].spool_depth);
+    }
+}
@@ -0,0 +1,398 @@
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+use actix_web::http::Method;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::waitmate::api::{Event, Level, now};
+use crate::waitmate::error::{Error, INTERNAL, Result};
+use crate::waitmate::filter::{Field, Filter};
+use crate::waitmate::http;
+use crate::waitmate::log::EventLog;
+
+/// Labels every transition event with the id of its alert.  Such events never open alerts.
+pub const ALERT: &'static str = "alert";
+
+lazy_static! {
+    /// Transitions read and write the same entry from the app, the HTTP API and the CLI
+    static ref LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Open,
+    Acknowledged,
+    Resolved
+}
+impl Display for AlertState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        return match self {
+            AlertState::Open => write!(f, "open"),
+            AlertState::Acknowledged => write!(f, "acknowledged"),
+            AlertState::Resolved => write!(f, "resolved")
+        };
+    }
+}
+
+/// An issue someone should handle, opened by the event `event_id` and counting the events
+/// with its fingerprint since
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Alert {
+    pub id: Uuid,
+    pub fingerprint: String,
+    pub state: AlertState,
+    pub name: String,
+    pub category: String,
+    pub level: Level,
+    pub host: String,
+    pub source: String,
+    pub event_id: Uuid,
+    pub count: u64,
+    pub opened_at: u128,
+    pub updated_at: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_by: Option<String>,
+}
+
+/// How many alerts of a category are open and acknowledged
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+struct Counts {
+    open: u64,
+    acknowledged: u64,
+}
+impl Counts {
+    fn count(&mut self, state: AlertState, by: i64) {
+        let count = match state {
+            AlertState::Open => &mut self.open,
+            AlertState::Acknowledged => &mut self.acknowledged,
+            AlertState::Resolved => return
+        };
+        *count = (*count as i64 + by).max(0) as u64;
+    }
+}
+
+/// The alerts by fingerprint, kept in the `alerts` column family of the event log.  An
+/// event's fingerprint is always taken from `fields`, never from the fingerprint dedup or the
+/// sender gave it.  Events matching `open` open an alert unless one with their fingerprint is
+/// still unresolved, and
+/// events matching `resolve` resolve it.  Every transition is logged as an event labelled
+/// `alert` and `alert_state`, with the `open` and `acknowledged` counts of its category.
+/// Those counts and the fingerprint of each alert id are kept up to date beside the alerts
+/// so that no transition has to read them all.
+pub struct Alerts {
+    event_log: Arc<EventLog>,
+    fields: Vec<Field>,
+    open: Option<Filter>,
+    resolve: Option<Filter>
+}
+impl Alerts {
+    const CF: &'static str = "alerts";
+    /// The fingerprint of each alert by id
+    const IDS: &'static str = "alert_ids";
+    /// The `Counts` of each category
+    const COUNTS: &'static str = "alert_counts";
+
+    /// The stored alerts, without opening or resolving any from events
+    pub fn new(event_log: Arc<EventLog>) -> Alerts {
+        return Alerts {
+            event_log,
+            fields: Vec::new(),
+            open: None,
+            resolve: None
+        };
+    }
+    /// Open and resolve alerts from events, fingerprinting them by `fields`
+    pub fn watching(event_log: Arc<EventLog>, fields: &[String], open: Filter, resolve: Filter) -> Result<Alerts> {
+        if fields.is_empty() {
+            return Err(Error::Config(String::from("alerts need at least one field")));
+        }
+        let fields = fields.iter()
+            .map(|f| Field::parse(f.as_str()).map_err(|e| Error::Config(e.to_string())))
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Alerts {
+            event_log,
+            fields,
+            open: Some(open),
+            resolve: Some(resolve)
+        });
+    }
+    /// The event's fields joined by `|`
+    pub fn fingerprint(&self, event: &Event) -> String {
+        return self.fields.iter()
+            .map(|f| f.text(event).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("|");
+    }
+    /// Every alert, oldest first
+    pub fn list(&self) -> Result<Vec<Alert>> {
+        let mut alerts = self.event_log.entries(Alerts::CF)?
+            .iter()
+            .filter_map(|(_, v)| serde_json::from_slice(v).ok())
+            .collect::<Vec<Alert>>();
+        alerts.sort_by_key(|a| a.opened_at);
+        return Ok(alerts);
+    }
+    /// The alert with `id` or, failing that, with the fingerprint `id`
+    pub fn find(&self, id: &str) -> Result<Alert> {
+        let by_id = match self.event_log.get_entry(Alerts::IDS, id.as_bytes())? {
+            Some(fingerprint) => self.get(String::from_utf8_lossy(&fingerprint).as_ref())?
+                .filter(|a| a.id.to_string() == id),
+            None => None
+        };
+        return match by_id {
+            Some(a) => Ok(a),
+            None => self.get(id)?.ok_or(Error::Invalid(format!("No alert {}", id)))
+        };
+    }
+    fn get(&self, fingerprint: &str) -> Result<Option<Alert>> {
+        return Ok(self.event_log.get_entry(Alerts::CF, fingerprint.as_bytes())?
+            .and_then(|v| serde_json::from_slice(&v).ok()));
+    }
+    /// Store `alert` in place of `previous`, the alert with its fingerprint before, moving
+    /// the counts of its category from the previous state to the new one
+    fn put(&self, alert: &Alert, previous: Option<&Alert>) -> Result<()> {
+        let mut counts = self.counts(alert.category.as_str())?;
+        match previous {
+            Some(p) if p.id != alert.id => {
+                self.event_log.delete_entry(Alerts::IDS, p.id.to_string().as_bytes())?;
+                counts.count(p.state, -1);
+            }
+            Some(p) => counts.count(p.state, -1),
+            None => {}
+        }
+        counts.count(alert.state, 1);
+        self.event_log.put_entry(Alerts::COUNTS, alert.category.as_bytes(), &serde_json::to_vec(&counts)?)?;
+        self.event_log.put_entry(Alerts::IDS, alert.id.to_string().as_bytes(), alert.fingerprint.as_bytes())?;
+        return self.event_log.put_entry(Alerts::CF, alert.fingerprint.as_bytes(), &serde_json::to_vec(alert)?);
+    }
+    fn counts(&self, category: &str) -> Result<Counts> {
+        return Ok(self.event_log.get_entry(Alerts::COUNTS, category.as_bytes())?
+            .and_then(|v| serde_json::from_slice(&v).ok())
+            .unwrap_or_default());
+    }
+    /// Open, count or resolve the alert `event` belongs to, returning the transition events
+    pub fn observe(&self, event: &Event) -> Result<Vec<Event>> {
+        if event.labels.contains_key(ALERT) {
+            return Ok(Vec::new());
+        }
+        let resolves = self.resolve.as_ref().map_or(false, |f| f.matches(event));
+        let opens = self.open.as_ref().map_or(false, |f| f.matches(event));
+        if !resolves && !opens {
+            return Ok(Vec::new());
+        }
+        let _guard = LOCK.lock().unwrap();
+        let fingerprint = self.fingerprint(event);
+        let previous = self.get(fingerprint.as_str())?;
+        let mut alert = match previous.clone() {
+            Some(a) if a.state != AlertState::Resolved => a,
+            _ if resolves => return Ok(Vec::new()),
+            _ => {
+                let alert = Alert {
+                    id: Uuid::new_v4(),
+                    fingerprint,
+                    state: AlertState::Open,
+                    name: event.name.clone(),
+                    category: event.category.clone(),
+                    level: event.level,
+                    host: event.host.clone(),
+                    source: event.source.clone(),
+                    event_id: event.id,
+                    count: 1,
+                    opened_at: event.time,
+                    updated_at: event.time,
+                    acknowledged_by: None,
+                    resolved_by: None
+                };
+                self.put(&alert, previous.as_ref())?;
+                return Ok(vec![self.transition(&alert)?]);
+            }
+        };
+        alert.updated_at = event.time;
+        if resolves {
+            alert.state = AlertState::Resolved;
+            alert.resolved_by = Some(format!("event {}", event.id));
+            self.put(&alert, previous.as_ref())?;
+            return Ok(vec![self.transition(&alert)?]);
+        }
+        alert.count += 1;
+        self.put(&alert, previous.as_ref())?;
+        return Ok(Vec::new());
+    }
+    /// Acknowledge an open alert on behalf of `by`, returning it with the transition for the
+    /// caller to publish
+    pub fn ack(&self, id: &str, by: &str) -> Result<(Alert, Event)> {
+        return self.change(id, AlertState::Acknowledged, by);
+    }
+    /// Resolve an unresolved alert on behalf of `by`, returning it with the transition for the
+    /// caller to publish
+    pub fn resolve(&self, id: &str, by: &str) -> Result<(Alert, Event)> {
+        return self.change(id, AlertState::Resolved, by);
+    }
+    fn change(&self, id: &str, state: AlertState, by: &str) -> Result<(Alert, Event)> {
+        let _guard = LOCK.lock().unwrap();
+        let previous = self.find(id)?;
+        let mut alert = previous.clone();
+        if alert.state == AlertState::Resolved || alert.state == state {
+            return Err(Error::Invalid(format!("Alert {} is already {}", alert.id, alert.state)));
+        }
+        alert.state = state;
+        alert.updated_at = now();
+        match state {
+            AlertState::Acknowledged => alert.acknowledged_by = Some(String::from(by)),
+            _ => alert.resolved_by = Some(String::from(by))
+        }
+        self.put(&alert, Some(&previous))?;
+        let transition = self.transition(&alert)?;
+        return Ok((alert, transition));
+    }
+    /// The event announcing that `alert` entered its state
+    fn transition(&self, alert: &Alert) -> Result<Event> {
+        let counts = self.counts(alert.category.as_str())?;
+        let (name, description, level) = match alert.state {
+            AlertState::Open => ("Alert opened", format!("{} opened", alert.name), alert.level),
+            AlertState::Acknowledged => ("Alert acknowledged", format!("{} acknowledged by {}", alert.name,
+                                                                       alert.acknowledged_by.as_deref().unwrap_or_default()), Level::INFO),
+            AlertState::Resolved => ("Alert resolved", format!("{} resolved by {}", alert.name,
+                                                               alert.resolved_by.as_deref().unwrap_or_default()), Level::INFO)
+        };
+        let mut event = Event::builder(name)
+            .description(description.as_str())
+            .category(alert.category.as_str())
+            .level(level)
+            .host(alert.host.as_str())
+            .correlation_id(alert.id.to_string().as_str())
+            .parent_id(alert.event_id)
+            .label(ALERT, alert.id.to_string().as_str())
+            .label("alert_state", alert.state.to_string().as_str())
+            .label("fingerprint", alert.fingerprint.as_str())
+            .label("open", counts.open.to_string().as_str())
+            .label("acknowledged", counts.acknowledged.to_string().as_str())
+            .build();
+        event.source = String::from(INTERNAL);
+        return Ok(event);
+    }
+}
+
+pub enum Command {
+    List,
+    Ack(String, String),
+    Resolve(String, String),
+}
+
+/// Apply `command` to the local log, returning the alerts it concerns and the transitions
+/// to publish
+pub fn apply(event_log: Arc<EventLog>, command: &Command) -> Result<(Vec<Alert>, Vec<Event>)> {
+    let alerts = Alerts::new(event_log);
+    return match command {
+        Command::List => alerts.list().map(|a| (a, Vec::new())),
+        Command::Ack(id, by) => alerts.ack(id.as_str(), by.as_str()).map(|(a, e)| (vec![a], vec![e])),
+        Command::Resolve(id, by) => alerts.resolve(id.as_str(), by.as_str()).map(|(a, e)| (vec![a], vec![e]))
+    };
+}
+
+/// Apply `command` through the API of the server at `server`
+pub fn apply_remote(server: &str, command: &Command) -> Result<Vec<Alert>> {
+    let url = format!("{}/api/v1/alerts", server.trim_end_matches('/'));
+    return match command {
+        Command::List => Ok(serde_json::from_value(http::get_json(url.as_str())?)?),
+        Command::Ack(id, by) => Ok(vec![serde_json::from_value(
+            http::send_json(Method::POST, format!("{}/{}/ack", url, id).as_str(), &json!({"by": by}))?)?]),
+        Command::Resolve(id, by) => Ok(vec![serde_json::from_value(
+            http::send_json(Method::POST, format!("{}/{}/resolve", url, id).as_str(), &json!({"by": by}))?)?])
+    };
+}
+
+pub fn print_alerts(alerts: Vec<Alert>) {
+    for a in alerts {
+        println!("{} {:<12} {:<8} {:>6} {:<20} {}", a.id, a.state.to_string(), a.level.to_string(), a.count, a.category, a.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::waitmate::alerts::{ALERT, Alerts, AlertState};
+    use crate::waitmate::api::{EmptyNamed, Event, Level};
+    use crate::waitmate::log::EventLog;
+
+    fn alerts(event_log: &Arc<EventLog>) -> Alerts {
+        let fields = vec![String::from("host"), String::from("labels.service")];
+        return Alerts::watching(event_log.clone(), &fields, "level >= ERROR".parse().unwrap(),
+                                "labels.state == \"recovered\"".parse().unwrap()).unwrap();
+    }
+
+    fn crashed(service: &str) -> Event {
+        let source = EmptyNamed {};
+        return Event::new(&source, "Crashed", "exit 1", "service", Level::ERROR)
+            .label("service", service);
+    }
+
+    #[test]
+    fn test_alert_lifecycle() {
+        let event_log = Arc::new(EventLog::memory());
+        let alerts = alerts(&event_log);
+        let opened = alerts.observe(&crashed("orders")).unwrap();
+        assert_eq!(1, opened.len());
+        assert_eq!("Alert opened", opened[0].name);
+        assert_eq!(Level::ERROR, opened[0].level);
+        assert_eq!("service", opened[0].category);
+        assert_eq!(Some(&String::from("1")), opened[0].labels.get("open"));
+        assert!(alerts.observe(&opened[0]).unwrap().is_empty());
+        assert!(alerts.observe(&crashed("orders")).unwrap().is_empty());
+        assert_eq!(1, alerts.observe(&crashed("billing")).unwrap().len());
+        // whatever fingerprint dedup gave it
+        let mut fingerprinted = crashed("orders");
+        fingerprinted.fingerprint = Some(String::from("Crashed|orders|ERROR"));
+        assert!(alerts.observe(&fingerprinted).unwrap().is_empty());
+
+        let alert = &alerts.find(alerts.fingerprint(&crashed("orders")).as_str()).unwrap();
+        assert_eq!(3, alert.count);
+        assert_eq!(Some(&alert.id.to_string()), opened[0].labels.get(ALERT));
+        let (acked, transition) = alerts.ack(alert.id.to_string().as_str(), "alice").unwrap();
+        assert_eq!(AlertState::Acknowledged, acked.state);
+        assert_eq!(Some(String::from("alice")), acked.acknowledged_by);
+        assert!(alerts.ack(alert.id.to_string().as_str(), "bob").is_err());
+        assert_eq!("Crashed acknowledged by alice", transition.description);
+        assert_eq!(Some(&String::from("1")), transition.labels.get("open"));
+        assert_eq!(Some(&String::from("1")), transition.labels.get("acknowledged"));
+        // the caller publishes transitions, nothing is logged behind its back
+        assert_eq!(0, event_log.build_cursor().build().unwrap().count());
+
+        let recovered = crashed("orders").label("state", "recovered");
+        let resolved = alerts.observe(&recovered).unwrap();
+        assert_eq!(1, resolved.len());
+        assert_eq!(Some(&String::from("resolved")), resolved[0].labels.get("alert_state"));
+        assert_eq!(AlertState::Resolved, alerts.find(alert.fingerprint.as_str()).unwrap().state);
+        assert!(alerts.observe(&recovered).unwrap().is_empty());
+        assert!(alerts.resolve(alert.id.to_string().as_str(), "alice").is_err());
+
+        // a resolved alert is replaced by the next one with its fingerprint
+        let reopened = alerts.observe(&crashed("orders")).unwrap();
+        assert_eq!(Some(&String::from("2")), reopened[0].labels.get("open"));
+        assert_eq!(Some(&String::from("0")), reopened[0].labels.get("acknowledged"));
+        let replacement = alerts.find(alert.fingerprint.as_str()).unwrap();
+        assert_ne!(alert.id, replacement.id);
+        assert_eq!(replacement.id, alerts.find(replacement.id.to_string().as_str()).unwrap().id);
+        assert!(alerts.find(alert.id.to_string().as_str()).is_err());
+        let (billing, transition) = alerts.resolve(alerts.fingerprint(&crashed("billing")).as_str(), "bob").unwrap();
+        assert_eq!(Some(String::from("bob")), billing.resolved_by);
+        assert_eq!(Some(&String::from("1")), transition.labels.get("open"));
+        assert!(alerts.find("nothing").is_err());
+    }
+
+    #[test]
+    fn test_stored_alerts_only() {
+        let event_log = Arc::new(EventLog::memory());
+        let alerts = Alerts::new(event_log.clone());
+        assert!(alerts.observe(&crashed("orders")).unwrap().is_empty());
+        assert!(Alerts::watching(event_log, &[], "level >= ERROR".parse().unwrap(),
+                                 "level < ERROR".parse().unwrap()).is_err());
+    }
+}
@@ -1,22 +1,98 @@
 use core::fmt;
-use std::fmt::Display;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use std::time::SystemTime;
 
 use config::Config;
-use serde::{Deserialize, Serialize};
-use serde::export::Formatter;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::{self, Visitor};
+use serde_json::Value;
 use uuid::Uuid;
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+use crate::waitmate::error;
+
+/// Event severity, ordered from least to most severe
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Level {
     TRACE,
     DEBUG,
     INFO,
     WARN,
-    ERROR
+    ERROR,
+    CRITICAL,
+    FATAL
+}
+impl Level {
+    /// Map a numeric level.  0-7 are syslog severities, anything from 10 up is read in
+    /// steps of ten the way python and bunyan number their levels.
+    pub fn from_number(n: u64) -> Level {
+        return match n {
+            0 | 1 => Level::FATAL,
+            2 => Level::CRITICAL,
+            3 => Level::ERROR,
+            4 => Level::WARN,
+            5 | 6 => Level::INFO,
+            7 => Level::DEBUG,
+            8 ..= 9 => Level::TRACE,
+            10 ..= 19 => Level::DEBUG,
+            20 ..= 29 => Level::INFO,
+            30 ..= 39 => Level::WARN,
+            40 ..= 49 => Level::ERROR,
+            50 ..= 59 => Level::CRITICAL,
+            _ => Level::FATAL
+        };
+    }
+}
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
+impl FromStr for Level {
+    type Err = String;
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+    /// Lenient parsing of level names as they show up in log text, e.g. `warning`, `err`
+    /// or `crit`, as well as numeric levels
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        return match name.as_str() {
+            "trace" | "trc" | "finest" | "finer" => Ok(Level::TRACE),
+            "debug" | "dbg" | "fine" => Ok(Level::DEBUG),
+            "info" | "inf" | "information" | "informational" | "notice" => Ok(Level::INFO),
+            "warn" | "wrn" | "warning" => Ok(Level::WARN),
+            "error" | "err" | "severe" => Ok(Level::ERROR),
+            "critical" | "crit" => Ok(Level::CRITICAL),
+            "fatal" | "ftl" | "alert" | "emerg" | "emergency" | "panic" => Ok(Level::FATAL),
+            _ => name.parse::<u64>()
+                .map(Level::from_number)
+                .map_err(|_| format!("Unknown level {}", s))
+        };
+    }
+}
+impl<'de> Deserialize<'de> for Level {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        struct LevelVisitor;
+        impl<'de> Visitor<'de> for LevelVisitor {
+            type Value = Level;
+            fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+                f.write_str("a level name or number")
+            }
+            fn visit_str<E>(self, v: &str) -> Result<Level, E> where E: de::Error {
+                return v.parse().map_err(E::custom);
+            }
+            fn visit_u64<E>(self, v: u64) -> Result<Level, E> where E: de::Error {
+                return Ok(Level::from_number(v));
+            }
+            fn visit_i64<E>(self, v: i64) -> Result<Level, E> where E: de::Error {
+                return Ok(Level::from_number(v.max(0) as u64));
+            }
+        }
+        return deserializer.deserialize_any(LevelVisitor);
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Event {
     pub id: Uuid,
     pub time: u128,
@@ -25,18 +101,40 @@ pub struct Event {
     pub category: String,
     pub level: Level,
     pub source: String,
+    /// When the event actually happened, if that differs from `time`, the time it was
+    /// ingested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occurred_at: Option<u128>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relay_path: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub fields: Value,
 }
 impl Event {
     pub fn new(source: &dyn Named, name: &str, description: &str, category: &str, level: Level) -> Self {
-        Event {
-            id: Uuid::new_v4(),
-            time: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_micros(),
-            name: String::from(name),
-            description: String::from(description),
-            category: String::from(category),
-            level,
-            source: String::from(source.name())
-        }
+        return EventBuilder::new(name)
+            .source(source)
+            .description(description)
+            .category(category)
+            .level(level)
+            .build();
+    }
+    pub fn builder(name: &str) -> EventBuilder {
+        return EventBuilder::new(name);
+    }
+    pub fn label(mut self, key: &str, value: &str) -> Self {
+        self.labels.insert(String::from(key), String::from(value));
+        return self;
     }
 }
 impl Display for Event {
@@ -45,20 +143,124 @@ impl Display for Event {
     }
 }
 
+/// Builds events stamped with the current time.  The host defaults to `WAITMATE_HOST` or
+/// the host name and the source to `WAITMATE_SOURCE` or the program name.
+pub struct EventBuilder {
+    event: Event
+}
+impl EventBuilder {
+    pub fn new(name: &str) -> Self {
+        let host = std::env::var("WAITMATE_HOST").unwrap_or_else(|_| hostname());
+        let source = std::env::var("WAITMATE_SOURCE").ok()
+            .or_else(|| std::env::current_exe().ok()
+                .and_then(|p| p.file_stem().and_then(|s| s.to_str()).map(String::from)))
+            .unwrap_or_else(|| String::from("waitmate"));
+        return EventBuilder {
+            event: Event {
+                id: Uuid::new_v4(),
+                time: now(),
+                name: String::from(name),
+                description: String::new(),
+                category: String::new(),
+                level: Level::INFO,
+                source,
+                occurred_at: None,
+                host,
+                correlation_id: None,
+                parent_id: None,
+                fingerprint: None,
+                relay_path: Vec::new(),
+                labels: BTreeMap::new(),
+                fields: Value::Null
+            }
+        };
+    }
+    pub fn source(mut self, source: &dyn Named) -> Self {
+        self.event.source = String::from(source.name());
+        return self;
+    }
+    pub fn description(mut self, description: &str) -> Self {
+        self.event.description = String::from(description);
+        return self;
+    }
+    pub fn category(mut self, category: &str) -> Self {
+        self.event.category = String::from(category);
+        return self;
+    }
+    pub fn level(mut self, level: Level) -> Self {
+        self.event.level = level;
+        return self;
+    }
+    pub fn occurred_at(mut self, time: u128) -> Self {
+        self.event.occurred_at = Some(time);
+        return self;
+    }
+    pub fn host(mut self, host: &str) -> Self {
+        self.event.host = String::from(host);
+        return self;
+    }
+    pub fn correlation_id(mut self, correlation_id: &str) -> Self {
+        self.event.correlation_id = Some(String::from(correlation_id));
+        return self;
+    }
+    pub fn parent_id(mut self, parent_id: Uuid) -> Self {
+        self.event.parent_id = Some(parent_id);
+        return self;
+    }
+    pub fn fingerprint(mut self, fingerprint: &str) -> Self {
+        self.event.fingerprint = Some(String::from(fingerprint));
+        return self;
+    }
+    pub fn label(mut self, key: &str, value: &str) -> Self {
+        self.event.labels.insert(String::from(key), String::from(value));
+        return self;
+    }
+    pub fn labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.event.labels.extend(labels);
+        return self;
+    }
+    pub fn fields(mut self, fields: Value) -> Self {
+        self.event.fields = fields;
+        return self;
+    }
+    pub fn build(self) -> Event {
+        return self.event;
+    }
+}
+
+/// Microseconds since the epoch, the resolution of event times
+pub fn now() -> u128 {
+    return SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_micros();
+}
+
+/// The name of this host, used to identify the node an event passed through
+pub fn hostname() -> String {
+    return std::env::var("HOSTNAME").ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| String::from(h.trim()))
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| String::from("localhost"));
+}
+
 pub trait Named {
     fn name(&self) -> &str;
 }
 
 pub trait EventBus: Send {
     fn publish(&self, event: Event);
+    /// Whether publishers should finish up because the app is shutting down
+    fn stopping(&self) -> bool {
+        return false;
+    }
 }
 
 pub trait Notifier: Send + Named {
-    fn notify(&self, event: Event, event_bus: &dyn EventBus);
+    fn notify(&self, event: Event, event_bus: &dyn EventBus) -> error::Result<()>;
 }
 
 pub trait Waiter: Send + Named {
-    fn wait(&self, bus: &dyn EventBus);
+    /// Publish events until there are no more, or fail
+    fn wait(&self, bus: &dyn EventBus) -> error::Result<()>;
 }
 
 pub struct EmptyNamed {
@@ -81,6 +283,8 @@ mod tests {
     use std::thread::sleep;
     use std::time::{Duration, SystemTime};
 
+    use uuid::Uuid;
+
     use crate::waitmate::api::{EmptyNamed, Event, Level};
 
     #[test]
@@ -97,4 +301,74 @@ mod tests {
         let e2 = Event::new(&source, "a", "b", "c", Level::WARN);
         assert!(e2.time > e.time);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn event_without_labels() {
+        let json = r#"{"id":"5b8a4f8e-4f5e-4a8c-9d2b-9f4c3e1a2b3c","time":1,"name":"a",
+            "description":"b","category":"c","level":"WARN","source":"s"}"#;
+        let e: Event = serde_json::from_str(json).unwrap();
+        assert!(e.labels.is_empty());
+        assert!(e.fields.is_null());
+
+        let e = e.label("host", "h1");
+        let json = serde_json::to_string(&e).unwrap();
+        let e2: Event = serde_json::from_str(json.as_str()).unwrap();
+        assert_eq!(Some(&String::from("h1")), e2.labels.get("host"));
+        assert!(!json.contains("fields"));
+    }
+
+    #[test]
+    fn level_order_and_parse() {
+        assert!(Level::TRACE < Level::DEBUG);
+        assert!(Level::WARN < Level::ERROR);
+        assert!(Level::ERROR < Level::CRITICAL);
+        assert!(Level::CRITICAL < Level::FATAL);
+        assert_eq!(Ok(Level::WARN), "warning".parse());
+        assert_eq!(Ok(Level::ERROR), "ERR".parse());
+        assert_eq!(Ok(Level::CRITICAL), " crit ".parse());
+        assert_eq!(Ok(Level::FATAL), "emerg".parse());
+        assert_eq!(Ok(Level::INFO), "notice".parse());
+        assert_eq!(Ok(Level::WARN), "4".parse());
+        assert_eq!(Ok(Level::ERROR), "40".parse());
+        assert_eq!(Ok(Level::FATAL), "60".parse());
+        assert!("loud".parse::<Level>().is_err());
+
+        let levels: Vec<Level> = serde_json::from_str(r#"["WARN", "warning", 3, "fatal"]"#).unwrap();
+        assert_eq!(vec![Level::WARN, Level::WARN, Level::ERROR, Level::FATAL], levels);
+        assert_eq!("\"CRITICAL\"", serde_json::to_string(&Level::CRITICAL).unwrap());
+    }
+
+    #[test]
+    fn event_builder() {
+        let source = EmptyNamed {};
+        let parent = Uuid::new_v4();
+        let e = Event::builder("a")
+            .source(&source)
+            .description("b")
+            .category("c")
+            .level(Level::ERROR)
+            .occurred_at(123)
+            .host("h1")
+            .correlation_id("req-1")
+            .parent_id(parent)
+            .fingerprint("fp")
+            .label("env", "prod")
+            .build();
+        assert_eq!("NAMED", e.source);
+        assert_eq!(Some(123), e.occurred_at);
+        assert!(e.time > 123);
+        assert_eq!("h1", e.host);
+        assert_eq!(Some(String::from("req-1")), e.correlation_id);
+        assert_eq!(Some(parent), e.parent_id);
+        assert_eq!(Some(String::from("fp")), e.fingerprint);
+        assert_eq!("prod", e.labels["env"]);
+
+        let e2: Event = serde_json::from_str(serde_json::to_string(&e).unwrap().as_str()).unwrap();
+        assert_eq!(e, e2);
+
+        let e3 = Event::builder("a").build();
+        assert!(!e3.host.is_empty());
+        assert!(!e3.source.is_empty());
+        assert_eq!(None, e3.occurred_at);
+    }
+}
@@ -1,162 +1,1065 @@
-use std::borrow::Borrow;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
+use actix_web::http::Method;
 use config::{Config, FileFormat};
-use crossbeam::channel::{Receiver, Select, unbounded};
-use log::info;
+use crossbeam::channel::{Receiver, Select, Sender, TryRecvError, unbounded};
+use log::{error, info, warn};
 use signal_hook::{iterator::Signals, SIGINT, SIGTERM, SIGQUIT, SIGHUP};
 
-use crate::waitmate::api::{Event, Notifier, Waiter};
+use crate::waitmate::agent::{AgentMonitor, Registry};
+use crate::waitmate::alerts;
+use crate::waitmate::alerts::Alerts;
+use crate::waitmate::export::{export, export_gzip, import};
+use crate::waitmate::api::{Event, hostname, Level, Named, Notifier, now, Waiter};
+use crate::waitmate::error::{Error, INTERNAL, Result};
+use crate::waitmate::dedup::Dedup;
+use crate::waitmate::http;
+use crate::waitmate::offsets;
 use crate::waitmate::http::Server as HttpServer;
 use crate::waitmate::log::EventLog;
-use crate::waitmate::net::{Client, Server};
+use crate::waitmate::metrics::Metrics;
+use crate::waitmate::query::EventQuery;
+use crate::waitmate::search::print_search;
+use crate::waitmate::silences;
+use crate::waitmate::silences::Silences;
+use crate::waitmate::net::{Client, Heartbeater, Relay, Server};
+use crate::waitmate::pipeline::{build_notifier, build_waiter, Control, Declared, diff, ReloadSummary};
+use crate::waitmate::rules::Rules;
+use crate::waitmate::store::restore;
 use crate::waitmate::std::{SleepyWaiter, StdinWaiter, StdoutNotifier};
-use crate::waitmate::thread::{NotifierThread, Producer, WaiterThread};
+use crate::waitmate::thread::{Backoff, ChannelPolicy, NotifierThread, Producer, Route, WaiterThread};
+
+/// What makes events duplicates of each other unless `dedup.fields` says otherwise
+const DEDUP_FIELDS: [&'static str; 5] = ["host", "source", "name", "category", "level"];
+/// What makes events belong to the same alert unless `alerts.fields` says otherwise
+const ALERT_FIELDS: [&'static str; 4] = ["host", "source", "category", "labels.group"];
+/// Which events open alerts unless `alerts.open` says otherwise
+const ALERT_OPEN: &'static str = "level >= ERROR || labels.state == \"firing\"";
+/// Which events resolve alerts unless `alerts.resolve` says otherwise
+const ALERT_RESOLVE: &'static str = "labels.state == \"recovered\"";
+/// How long stopped waiters get to hand over what they have already read
+const WAITER_GRACE: Duration = Duration::from_secs(1);
+/// How often events dropped by full waiter channels are reported, dedup windows closed,
+/// rules ticked and silences expired
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct App {
     config: Config,
-    event_log: Arc<EventLog>
+    config_file: Option<PathBuf>,
+    event_log: Arc<EventLog>,
+    control: (Sender<Control>, Receiver<Control>),
+    metrics: Arc<Metrics>,
+    dedup: Option<Dedup>,
+    rules: Mutex<Rules>,
+    alerts: Option<Alerts>,
+    silences: Silences
+}
+
+/// The threads of a running pipeline by component name, and the declarations they follow
+#[derive(Default)]
+struct Running {
+    waiters: BTreeMap<String, WaiterThread>,
+    notifiers: BTreeMap<String, (Route, NotifierThread)>,
+    declared: Declared
+}
+impl Running {
+    fn start_waiter(&mut self, waiter: Box<dyn Waiter>, backoff: Backoff, channel: ChannelPolicy) {
+        let name = String::from(waiter.name());
+        self.waiters.insert(name, WaiterThread::new(waiter, backoff, channel));
+    }
+    fn start_notifier(&mut self, notifier: Box<dyn Notifier>, route: Route, event_log: &Arc<EventLog>,
+                      backoff: Backoff) {
+        let name = String::from(notifier.name());
+        let thread = NotifierThread::new(notifier, event_log.clone(), route.clone(), backoff);
+        self.notifiers.insert(name, (route, thread));
+    }
+    /// Whether `name` was started by the app rather than declared in the config
+    fn builtin(&self, name: &str) -> bool {
+        return (self.waiters.contains_key(name) && !self.declared.waiters.contains_key(name))
+            || (self.notifiers.contains_key(name) && !self.declared.notifiers.contains_key(name));
+    }
+    fn waiter_channels(&self) -> Vec<&dyn Producer> {
+        return self.waiters.values().map(|w| w as &dyn Producer).collect();
+    }
+    fn notifier_channels(&self) -> Vec<&dyn Producer> {
+        return self.notifiers.values().map(|(_, n)| n as &dyn Producer).collect();
+    }
+    fn notifier_threads(&self) -> Vec<&NotifierThread> {
+        return self.notifiers.values().map(|(_, n)| n).collect();
+    }
+    fn tickle(&self) {
+        self.notifiers.values().for_each(|(_, n)| n.tickle());
+    }
+    /// Join the waiters marked `finished`, in the order of `waiter_channels`
+    fn join_waiters(&mut self, finished: &[bool]) {
+        let names = self.waiters.keys()
+            .zip(finished)
+            .filter(|(_, done)| **done)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in names {
+            match self.waiters.remove(&name) {
+                Some(w) => w.join(),
+                None => {}
+            }
+        }
+    }
 }
 impl App {
-    pub fn new(temp: bool) -> Self {
-        return App::new_config(temp, None);
+    /// An app configured by the default files, then `config_file` when given, then the
+    /// environment.  Reloads read the same files again.
+    pub fn new_config(temp: bool, config_file: Option<PathBuf>) -> Result<Self> {
+        let config = App::load_config(config_file.clone())?;
+        let event_log = Arc::new(App::create_event_log(&config, temp)?);
+        let dedup = App::dedup(&config, &event_log)?;
+        let mut rules = Rules::default();
+        rules.replace(Rules::load(&config, &event_log)?, &event_log, now());
+        let alerts = App::alerts(&config, &event_log)?;
+        let silences = Silences::new(event_log.clone());
+        return Ok(App {
+            config,
+            config_file,
+            event_log,
+            control: unbounded(),
+            metrics: Arc::new(Metrics::new()),
+            dedup,
+            rules: Mutex::new(rules),
+            alerts,
+            silences
+        });
     }
-    pub fn new_config(temp: bool, config_file: Option<PathBuf>) -> Self {
-        let config_base = dirs::config_dir().unwrap();
+    fn load_config_file(&self) -> Result<Config> {
+        return App::load_config(self.config_file.clone());
+    }
+    fn load_config(config_file: Option<PathBuf>) -> Result<Config> {
+        let config_base = dirs::config_dir()
+            .ok_or(Error::Config(String::from("No configuration directory")))?;
         let local_config = config_base
             .join("waitmate.yaml");
         let mut config = Config::new();
         config
-            .merge(config::File::from(local_config).required(false)).unwrap()
-            .merge(config::File::new("waitmate", FileFormat::Yaml).required(false)).unwrap();
-        if config_file.is_some() {
-            config.merge(config::File::from(config_file.unwrap()).required(false)).unwrap();
-        }
-        config.merge(config::Environment::with_prefix("WAITMATE")).unwrap();
-
-        let event_log = Arc::new(App::create_event_log(temp));
-        return App {
-            config,
-            event_log
+            .merge(config::File::from(local_config).required(false))?
+            .merge(config::File::new("waitmate", FileFormat::Yaml).required(false))?;
+        match config_file {
+            Some(f) => { config.merge(config::File::from(f))?; }
+            None => {}
         }
+        config.merge(config::Environment::with_prefix("WAITMATE"))?;
+        return Ok(config);
     }
     pub fn dump_config(&self) {
         println!("{:?}", self.config);
     }
-    pub fn dump(&self) {
-        let event_log = App::create_event_log(false);
-        let cursor = event_log.build_cursor().build();
-        for (key, event) in cursor {
-            println!("{} {}", key, event);
+    pub fn dump(&self, query: &EventQuery) -> Result<()> {
+        if query.last.is_some() {
+            for (key, event) in query.select(&self.event_log)? {
+                println!("{} {}", key, event);
+            }
+            return Ok(());
         }
+        let cursor = query.cursor(&self.event_log).build()?;
+        for item in cursor {
+            match item {
+                Ok((key, event)) => {
+                    if query.matches(&event) {
+                        println!("{} {}", key, event);
+                    }
+                }
+                Err(Error::Decode(e)) => eprintln!("{}", e),
+                Err(e) => return Err(e)
+            }
+        }
+        return Ok(());
     }
 
-    pub fn run_client(&self, address: &str) {
-        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(Client::new(address))];
-        let waiters: Vec<Box<dyn Waiter>> = vec![Box::new(StdinWaiter::new()), Box::new(SleepyWaiter::new())];
-        self._run(notifiers, waiters)
+    pub fn export(&self, query: &EventQuery, output: Option<&str>, gzip: bool) -> Result<()> {
+        let stdout = io::stdout();
+        let mut out: Box<dyn Write> = match output {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(stdout.lock())
+        };
+        let count = if gzip {
+            export_gzip(&self.event_log, query, &mut out)?
+        } else {
+            export(&self.event_log, query, &mut out)?
+        };
+        info!("Exported {} events", count);
+        return Ok(());
     }
 
-    pub fn run_server(&self, address: &str) {
-        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(StdoutNotifier::new())];
-        let waiters: Vec<Box<dyn Waiter>> = vec![Box::new(Server::new(address)),
-                                                 Box::new(HttpServer::new("0.0.0.0:12346",
-                                                                          self.event_log.clone()))];
-        self._run(notifiers, waiters)
+    pub fn import(&self, input: &str) -> Result<()> {
+        let reader: Box<dyn Read> = if input == "-" {
+            Box::new(io::stdin())
+        } else {
+            Box::new(File::open(input)?)
+        };
+        let (imported, skipped) = import(&self.event_log, reader)?;
+        println!("Imported {} events, skipped {}", imported, skipped);
+        return Ok(());
     }
 
-    pub fn run(&self) {
-        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(StdoutNotifier::new())];
-        let waiters: Vec<Box<dyn Waiter>> = vec![Box::new(StdinWaiter::new()), Box::new(SleepyWaiter::new())];
-        self._run(notifiers, waiters)
+    /// Snapshot the local log to `dir`
+    pub fn backup(&self, dir: &str) -> Result<()> {
+        self.event_log.backup(Path::new(dir))?;
+        println!("Backed up to {}", dir);
+        return Ok(());
     }
 
-    fn _run(&self, notifiers: Vec<Box<dyn Notifier>>, waiters: Vec<Box<dyn Waiter>>) {
-        let local_event_log: &EventLog = self.event_log.borrow();
+    /// Ask a running server to snapshot its log to `dir` on the server's machine
+    pub fn backup_server(server: &str, dir: &str) -> Result<()> {
+        let url = format!("{}/api/v1/admin/backup", server);
+        http::send_json(Method::POST, url.as_str(), &serde_json::json!({"dir": dir}))?;
+        println!("Backed up {} to {}", server, dir);
+        return Ok(());
+    }
 
-        let mut receivers: Vec<&Receiver<Option<Event>>> = Vec::with_capacity(waiters.len() + notifiers.len());
-        let mut selector = Select::new();
-        let mut waiters_pending = waiters.len();
+    /// List, acknowledge or resolve the alerts of the local log
+    pub fn manage_alerts(&self, command: &alerts::Command) -> Result<()> {
+        let (alerts, transitions) = alerts::apply(self.event_log.clone(), command)?;
+        self.publish(transitions);
+        alerts::print_alerts(alerts);
+        return Ok(());
+    }
 
-        let notifier_threads = notifiers
-            .into_iter()
-            .map(|n| NotifierThread::new(n, self.event_log.clone()))
-            .collect::<Vec<_>>();
-        let waiter_threads = waiters
-            .into_iter()
-            .map(|n| WaiterThread::new(n))
-            .collect::<Vec<_>>();
+    /// List, create or delete the silences of the local log
+    pub fn manage_silences(&self, command: &silences::Command) -> Result<()> {
+        let (silences, events) = silences::apply(self.event_log.clone(), command)?;
+        self.publish(events);
+        silences::print_silences(silences);
+        return Ok(());
+    }
+
+    /// Manage the named cursors of the local log
+    pub fn offsets(&self, command: &offsets::Command) -> Result<()> {
+        offsets::print_offsets(offsets::apply(&self.event_log, command)?);
+        return Ok(());
+    }
+
+    /// Replace the local log with a backup.  The server must be stopped, and an existing log
+    /// is only removed when `force` is set.
+    pub fn restore(config_file: Option<PathBuf>, backup: &str, force: bool) -> Result<()> {
+        let config = App::load_config(config_file)?;
+        let path = match App::event_log_path(&config, false)? {
+            Some(p) => p,
+            None => return Err(Error::Config(String::from("Nothing to restore for storage in memory")))
+        };
+        if force && path.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else if force && path.exists() {
+            fs::remove_file(&path)?;
+        }
+        restore(Path::new(backup), path.as_path())?;
+        println!("Restored {} to {}", backup, path.display());
+        return Ok(());
+    }
+
+    pub fn search(&self, q: &str, from: Option<u128>, to: Option<u128>, limit: usize, reindex: bool) -> Result<()> {
+        if reindex {
+            info!("Indexed {} events", self.event_log.reindex()?);
+        }
+        return print_search(&self.event_log, q, from, to, limit);
+    }
+
+    pub fn run_client(&mut self, address: &str) -> Result<()> {
+        let client = Client::new(address)?;
+        let interval = self.config.get_int("agents.heartbeat").unwrap_or(10);
+        if interval < 1 {
+            return Err(Error::Config(format!("agents.heartbeat must be at least 1 second, not {}", interval)));
+        }
+        let heartbeater = Heartbeater::new(address, self.event_log.clone(), client.name(),
+                                           Duration::from_secs(interval as u64))?;
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(client)];
+        let waiters: Vec<Box<dyn Waiter>> = vec![Box::new(StdinWaiter::new()), Box::new(SleepyWaiter::new()),
+                                                 Box::new(heartbeater)];
+        return self._run(notifiers, waiters);
+    }
+
+    pub fn run_server(&mut self, address: &str, upstream: Option<&str>, relay_filter: &str) -> Result<()> {
+        let node = self.node();
+        let mut notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(StdoutNotifier::new())];
+        match upstream {
+            Some(upstream) => {
+                let filter = relay_filter.parse().map_err(|e: Error| Error::Config(e.to_string()))?;
+                notifiers.push(Box::new(Relay::new(upstream, node.as_str(), filter)?));
+            }
+            None => {}
+        }
+        let registry = Arc::new(Registry::new(self.event_log.clone()));
+        let timeout = self.config.get_int("agents.timeout").unwrap_or(60) as u64;
+        let waiters: Vec<Box<dyn Waiter>> = vec![Box::new(Server::new(address)?
+                                                     .node(node.as_str())
+                                                     .registry(registry.clone())),
+                                                 Box::new(AgentMonitor::new(registry, Duration::from_secs(timeout))),
+                                                 Box::new(self.http_server("0.0.0.0:12346"))];
+        return self._run(notifiers, waiters);
+    }
+
+    /// The web UI and API.  The admin API and changes to alerts and silences take `admin.token`
+    /// as a bearer token, or only local callers without one, and backups go to directories
+    /// under `admin.backup_root`.
+    fn http_server(&self, address: &str) -> HttpServer {
+        let mut server = HttpServer::new(address, self.event_log.clone())
+            .control(self.control.0.clone())
+            .metrics(self.metrics.clone());
+        match self.config.get_str("admin.token") {
+            Ok(token) => server = server.admin_token(token.as_str()),
+            Err(_) => {}
+        }
+        match self.config.get_str("admin.backup_root") {
+            Ok(root) => server = server.backup_root(Path::new(root.as_str())),
+            Err(_) => {}
+        }
+        return server;
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(StdoutNotifier::new())];
+        let waiters: Vec<Box<dyn Waiter>> = vec![Box::new(StdinWaiter::new()), Box::new(SleepyWaiter::new())];
+        return self._run(notifiers, waiters);
+    }
 
-        notifier_threads
-            .iter()
-            .map(|m| m.channel())
-            .for_each(|r| receivers.push(r));
-        waiter_threads
-            .iter()
-            .map(|m| m.channel())
-            .for_each(|r| receivers.push(r));
-
-        let (sig_tx, sig_rx) = unbounded();
-        let sig_id = selector.recv(&sig_rx);
+    /// Run the given waiters and notifiers along with those declared in the config until the
+    /// waiters finish or a stop signal arrives.  SIGHUP and the admin API reload the config.
+    fn _run(&mut self, notifiers: Vec<Box<dyn Notifier>>, waiters: Vec<Box<dyn Waiter>>) -> Result<()> {
+        let backoff = self.backoff();
+        let channel = App::channel(&self.config)?;
+        let mut running = Running::default();
+        for n in notifiers {
+            let route = App::route(&self.config, n.name())?;
+            running.start_notifier(n, route, &self.event_log, backoff);
+        }
+        for w in waiters {
+            running.start_waiter(w, backoff, channel);
+        }
+        self.apply(&mut running, self.config.clone())?;
+
+        let control = self.control.0.clone();
+        let signals = Signals::new(&[SIGINT, SIGTERM, SIGQUIT, SIGHUP])?;
         std::thread::spawn(move || {
-            let signals = Signals::new(&[SIGINT, SIGTERM, SIGQUIT, SIGHUP]).unwrap();
             for sig in signals.forever() {
-                sig_tx.send(sig).unwrap_or(());
+                let message = if sig == SIGHUP { Control::Reload(None) } else { Control::Stop };
+                control.send(message).unwrap_or(());
             }
         });
 
-        receivers
-            .iter()
-            .for_each(|r| { selector.recv(*r); });
+        while !running.waiters.is_empty() {
+            let (finished, message) = self.pump(&running.waiter_channels(), &running.notifier_channels(),
+                                                &running.notifier_threads(), true, None);
+            running.join_waiters(&finished);
+            match message {
+                Some(Control::Reload(reply)) => {
+                    let result = self.load_config_file().and_then(|config| self.apply(&mut running, config));
+                    let mut event = match result.as_ref() {
+                        Ok(summary) => Event::builder("Configuration reloaded")
+                            .description(format!("Stopped {:?}, started {:?}", summary.stopped, summary.started).as_str())
+                            .category(INTERNAL)
+                            .level(Level::INFO)
+                            .build(),
+                        Err(e) => {
+                            error!("Keeping the running configuration: {}", e);
+                            e.event(INTERNAL, "Configuration rejected")
+                        }
+                    };
+                    event.source = String::from(INTERNAL);
+                    self.add(&event);
+                    running.tickle();
+                    match reply {
+                        Some(reply) => reply.send(result).unwrap_or(()),
+                        None => {}
+                    }
+                }
+                Some(Control::Publish(events)) => {
+                    self.publish(events);
+                    running.tickle();
+                }
+                Some(Control::Stop) => break,
+                None => {}
+            }
+        }
+
+        // stop taking input, keeping what the waiters have already published
+        let deadline = Instant::now() + self.shutdown_timeout();
+        info!("Shutting down");
+        let names = running.waiters.keys().cloned().collect::<Vec<_>>();
+        self.stop_waiters(&mut running, &names, deadline.min(Instant::now() + WAITER_GRACE));
+        loop {
+            match self.control.1.try_recv() {
+                Ok(Control::Publish(events)) => self.publish(events),
+                Ok(_) => {}
+                Err(_) => break
+            }
+        }
 
-        while waiters_pending > 0 {
-            info!("{} Waiters pending", waiters_pending);
-            let op = selector.select();
-            let index = op.index();
+        // let the notifiers catch up with the log
+        let names = running.notifiers.keys().cloned().collect::<Vec<_>>();
+        let (_, undelivered) = self.stop_notifiers(&mut running, &names, deadline);
+        if !undelivered.is_empty() {
+            return Err(Error::Shutdown(format!("{} did not finish delivering", undelivered.join(", "))));
+        }
+        info!("Exiting");
+        return Ok(());
+    }
+
+    /// Bring the declared part of `running` in line with `config`, stopping and starting only
+    /// what changed.  Nothing is touched when the config is rejected.  A new default channel
+    /// policy only applies to the waiters started afterwards.
+    fn apply(&mut self, running: &mut Running, config: Config) -> Result<ReloadSummary> {
+        let declared = Declared::load(&config)?;
+        let dedup = App::dedup(&config, &self.event_log)?;
+        let rules = Rules::load(&config, &self.event_log)?;
+        let alerts = App::alerts(&config, &self.event_log)?;
+        for name in declared.waiters.keys().chain(declared.notifiers.keys()) {
+            if running.builtin(name) {
+                return Err(Error::Config(format!("{} is already running", name)));
+            }
+        }
+        let mut routes = BTreeMap::new();
+        for name in running.notifiers.keys().chain(declared.notifiers.keys()) {
+            let mut route = App::route(&config, name)?;
+            match declared.notifiers.get(name) {
+                Some(spec) => {
+                    route.min_level = spec.min_level()?.unwrap_or(route.min_level);
+                    route.filter = spec.route()?.or(route.filter);
+                }
+                None => {}
+            }
+            routes.insert(name.clone(), route);
+        }
+
+        let backoff = self.backoff();
+        let channel = App::channel(&config)?;
+        let node = config.get_str("node").unwrap_or_else(|_| hostname());
+        let (stop_waiters, start_waiters) = diff(&running.declared.waiters, &declared.waiters);
+        let (mut stop_notifiers, start_notifiers) = diff(&running.declared.notifiers, &declared.notifiers);
+        let reroute = running.notifiers.iter()
+            .filter(|(name, (route, _))| !stop_notifiers.contains(name) && routes.get(*name) != Some(route))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        stop_notifiers.extend(reroute.iter().cloned());
 
-            if index == sig_id {
-                let sig = op.recv(&sig_rx).unwrap_or(SIGHUP);
-                if sig == SIGHUP {
-                    continue;
+        let mut summary = ReloadSummary::default();
+        let deadline = Instant::now() + self.shutdown_timeout();
+        summary.stopped.extend(self.stop_waiters(running, &stop_waiters, Instant::now() + WAITER_GRACE));
+        let (stopped, stuck) = self.stop_notifiers(running, &stop_notifiers, deadline);
+        for (name, notifier) in stopped {
+            if reroute.contains(&name) {
+                running.start_notifier(notifier, routes[&name].clone(), &self.event_log, backoff);
+                summary.started.push(name.clone());
+            }
+            summary.stopped.push(name);
+        }
+
+        for name in start_waiters {
+            let spec = &declared.waiters[&name];
+            match build_waiter(name.as_str(), spec, node.as_str()).and_then(|w| Ok((w, spec.channel(channel)?))) {
+                Ok((w, channel)) => {
+                    running.start_waiter(w, backoff, channel);
+                    summary.started.push(name);
+                }
+                Err(e) => {
+                    error!("Could not start {}: {}", name, e);
+                    self.add(&e.event(name.as_str(), "Waiter failed to start"));
+                }
+            }
+        }
+        for name in start_notifiers {
+            if stuck.contains(&name) {
+                // the old one is still delivering under the same offset
+                self.add(&Error::Config(String::from("the previous one is still delivering"))
+                    .event(name.as_str(), "Notifier not restarted"));
+                continue;
+            }
+            match build_notifier(name.as_str(), &declared.notifiers[&name], node.as_str()) {
+                Ok(n) => {
+                    running.start_notifier(n, routes[&name].clone(), &self.event_log, backoff);
+                    summary.started.push(name);
                 }
-                break;
+                Err(e) => {
+                    error!("Could not start {}: {}", name, e);
+                    self.add(&e.event(name.as_str(), "Notifier failed to start"));
+                }
+            }
+        }
+        running.declared = declared;
+        self.dedup = dedup;
+        self.rules.lock().unwrap().replace(rules, &self.event_log, now());
+        self.alerts = alerts;
+        self.config = config;
+        return Ok(summary);
+    }
+
+    /// Stop the named waiters, keeping what they have already published, and join those that
+    /// finish by `deadline`.  Waiters blocked on input are left behind.
+    fn stop_waiters(&self, running: &mut Running, names: &[String], deadline: Instant) -> Vec<String> {
+        let stopping = names.iter()
+            .filter_map(|n| running.waiters.remove(n).map(|w| (n.clone(), w)))
+            .collect::<Vec<_>>();
+        stopping.iter().for_each(|(_, w)| w.stop());
+        let channels = stopping.iter().map(|(_, w)| w as &dyn Producer).collect::<Vec<_>>();
+        let mut also = running.waiter_channels();
+        also.extend(running.notifier_channels());
+        let (finished, _) = self.pump(&channels, &also, &running.notifier_threads(), false, Some(deadline));
+        drop(channels);
+        let mut stopped = Vec::new();
+        for ((name, w), done) in stopping.into_iter().zip(finished) {
+            if done {
+                w.join();
             } else {
-                let rec = receivers[index - 1];
-                match op.recv(rec) {
-                    Ok(e) => {
-                        match e {
-                            Some(event) => {
-                                local_event_log.add(&event);
-                                for x in &notifier_threads {
-                                    x.tickle();
+                warn!("{} did not stop, leaving it behind", name);
+            }
+            stopped.push(name);
+        }
+        return stopped;
+    }
+
+    /// Let the named notifiers deliver what is in the log, then stop them.  Returns those
+    /// that stopped, with their notifiers, and the names of those still delivering at
+    /// `deadline`.
+    fn stop_notifiers(&self, running: &mut Running, names: &[String], deadline: Instant)
+            -> (Vec<(String, Box<dyn Notifier>)>, Vec<String>) {
+        let stopping = names.iter()
+            .filter_map(|n| running.notifiers.remove(n).map(|(_, t)| (n.clone(), t)))
+            .collect::<Vec<_>>();
+        stopping.iter().for_each(|(_, n)| n.stop());
+        let channels = stopping.iter().map(|(_, n)| n as &dyn Producer).collect::<Vec<_>>();
+        let mut also = running.waiter_channels();
+        also.extend(running.notifier_channels());
+        let (finished, _) = self.pump(&channels, &also, &running.notifier_threads(), false, Some(deadline));
+        drop(channels);
+        let mut stopped = Vec::new();
+        let mut stuck = Vec::new();
+        for ((name, n), done) in stopping.into_iter().zip(finished) {
+            if !done {
+                warn!("{} did not finish delivering", name);
+                stuck.push(name);
+                continue;
+            }
+            match n.join() {
+                Some(notifier) => stopped.push((name, notifier)),
+                None => warn!("{} could not be joined", name)
+            }
+        }
+        return (stopped, stuck);
+    }
+
+    /// Add what the producers publish to the log, writing whatever is ready at once and
+    /// tickling `notifiers` after each write, until every one of `wait_for` has finished, a
+    /// control message arrives when `controlled` or `deadline` passes.  Returns whether each
+    /// of `wait_for` finished and the message.  Housekeeping runs every
+    /// `HOUSEKEEPING_INTERVAL` and on return.
+    fn pump(&self, wait_for: &[&dyn Producer], also: &[&dyn Producer], notifiers: &[&NotifierThread],
+            controlled: bool, deadline: Option<Instant>) -> (Vec<bool>, Option<Control>) {
+        let channels = wait_for.iter().chain(also.iter()).map(|p| p.channel()).collect::<Vec<_>>();
+        let mut finished = vec![false; channels.len()];
+        let mut selector = Select::new();
+        channels.iter().for_each(|r| { selector.recv(*r); });
+        let control_id = if controlled { Some(selector.recv(&self.control.1)) } else { None };
+        let producers = wait_for.iter().chain(also.iter()).cloned().collect::<Vec<_>>();
+        let batch_size = self.batch_size();
+        let mut housekeeping_at = Instant::now() + HOUSEKEEPING_INTERVAL;
+
+        while finished[..wait_for.len()].contains(&false) {
+            if Instant::now() >= housekeeping_at {
+                self.housekeeping(&producers, notifiers);
+                housekeeping_at = Instant::now() + HOUSEKEEPING_INTERVAL;
+            }
+            let until = deadline.map_or(housekeeping_at, |d| d.min(housekeeping_at));
+            let op = match selector.select_timeout(until.saturating_duration_since(Instant::now())) {
+                Ok(op) => op,
+                Err(_) if deadline.map_or(false, |d| Instant::now() >= d) => break,
+                Err(_) => continue
+            };
+            let index = op.index();
+            if Some(index) == control_id {
+                match op.recv(&self.control.1) {
+                    Ok(message) => {
+                        self.housekeeping(&producers, notifiers);
+                        finished.truncate(wait_for.len());
+                        return (finished, Some(message));
+                    }
+                    Err(_) => {
+                        selector.remove(index);
+                        continue;
+                    }
+                }
+            }
+            match op.recv(channels[index]) {
+                Ok(Some(event)) => {
+                    let mut batch = vec![event];
+                    // take whatever else is ready so that it goes to the log in one write
+                    for (i, channel) in channels.iter().enumerate() {
+                        while !finished[i] && batch.len() < batch_size {
+                            match channel.try_recv() {
+                                Ok(Some(event)) => batch.push(event),
+                                Ok(None) => {}
+                                Err(TryRecvError::Empty) => break,
+                                Err(TryRecvError::Disconnected) => {
+                                    selector.remove(i);
+                                    finished[i] = true;
                                 }
                             }
-                            None => {}
                         }
                     }
-                    Err(_) => {
-                        selector.remove(index);
-                        waiters_pending -= 1;
+                    self.add_all(batch);
+                    notifiers.iter().for_each(|n| n.tickle());
+                }
+                Ok(None) => {}
+                Err(_) => {
+                    selector.remove(index);
+                    finished[index] = true;
+                }
+            }
+        }
+        self.housekeeping(&producers, notifiers);
+        finished.truncate(wait_for.len());
+        return (finished, None);
+    }
+
+    fn housekeeping(&self, producers: &[&dyn Producer], notifiers: &[&NotifierThread]) {
+        self.report_dropped(producers, notifiers);
+        self.expire_dedup(notifiers);
+        self.tick_rules(notifiers);
+        self.expire_silences(notifiers);
+    }
+
+    /// Delete the silences that have ended, announcing each
+    fn expire_silences(&self, notifiers: &[&NotifierThread]) {
+        match self.silences.expire(now()) {
+            Ok(expired) if expired.is_empty() => {}
+            Ok(expired) => {
+                self.add_all(expired);
+                notifiers.iter().for_each(|n| n.tickle());
+            }
+            Err(e) => error!("Could not expire silences: {}", e)
+        }
+    }
+
+    /// Add the events of rule windows and deadlines that have ended
+    fn tick_rules(&self, notifiers: &[&NotifierThread]) {
+        let derived = self.rules.lock().unwrap().tick(now());
+        if derived.is_empty() {
+            return;
+        }
+        self.add_all(derived);
+        notifiers.iter().for_each(|n| n.tickle());
+    }
+
+    /// Add the summaries of the dedup windows that have ended
+    fn expire_dedup(&self, notifiers: &[&NotifierThread]) {
+        let dedup = match self.dedup.as_ref() {
+            Some(d) => d,
+            None => return
+        };
+        match dedup.expire(now()) {
+            Ok(summaries) if summaries.is_empty() => {}
+            Ok(summaries) => {
+                self.write(summaries);
+                notifiers.iter().for_each(|n| n.tickle());
+            }
+            Err(e) => error!("Could not close dedup windows: {}", e)
+        }
+    }
+
+    /// Log, count and publish the events each producer dropped since it was last asked
+    fn report_dropped(&self, producers: &[&dyn Producer], notifiers: &[&NotifierThread]) {
+        for p in producers {
+            let dropped = p.take_dropped();
+            if dropped == 0 {
+                continue;
+            }
+            warn!("{} dropped {} events", p.name(), dropped);
+            self.metrics.add(format!("dropped.{}", p.name()).as_str(), dropped);
+            let mut event = Event::builder("Events dropped")
+                .description(format!("{} dropped {} events its channel had no room for", p.name(), dropped).as_str())
+                .category(INTERNAL)
+                .level(Level::WARN)
+                .label("component", p.name())
+                .label("dropped", dropped.to_string().as_str())
+                .build();
+            event.source = String::from(p.name());
+            self.add(&event);
+            notifiers.iter().for_each(|n| n.tickle());
+        }
+    }
+
+    fn add(&self, event: &Event) {
+        match self.event_log.add(event) {
+            Ok(()) => {}
+            Err(e) => error!("Dropped event {}: {}", event.id, e)
+        }
+    }
+
+    /// Add `events` in one write, marking duplicates first when dedup is on.  Every event,
+    /// duplicates included, is shown to the rules and what they derive is added after it,
+    /// followed by the alert transitions of both.  Whatever a silence covers is marked.
+    fn add_all(&self, events: Vec<Event>) {
+        self.add_observed(events, self.dedup.as_ref());
+    }
+
+    /// Add the events announcing a change made outside the pump, such as an alert acknowledged
+    /// through the API, as `add_all` does but without dedup: no announcement repeats another
+    fn publish(&self, events: Vec<Event>) {
+        if events.is_empty() {
+            return;
+        }
+        // the change may have been to a silence
+        self.silences.reload();
+        self.add_observed(events, None);
+    }
+
+    fn add_observed(&self, events: Vec<Event>, dedup: Option<&Dedup>) {
+        let mut derived = {
+            let mut rules = self.rules.lock().unwrap();
+            events.iter().flat_map(|e| rules.observe(e)).collect::<Vec<_>>()
+        };
+        match self.alerts.as_ref() {
+            Some(alerts) => {
+                let mut transitions = Vec::new();
+                for event in events.iter().chain(derived.iter()) {
+                    match alerts.observe(event) {
+                        Ok(t) => transitions.extend(t),
+                        Err(e) => error!("Could not update the alert of event {}: {}", event.id, e)
                     }
                 }
+                derived.extend(transitions);
             }
+            None => {}
         }
+        let mut events = match dedup {
+            Some(dedup) => {
+                let mut checked = Vec::with_capacity(events.len());
+                for mut event in events {
+                    match dedup.check(&mut event) {
+                        Ok(Some(summary)) => checked.push(summary),
+                        Ok(None) => {}
+                        Err(e) => error!("Could not dedup event {}: {}", event.id, e)
+                    }
+                    checked.push(event);
+                }
+                checked
+            }
+            None => events
+        };
+        events.extend(derived);
+        self.write(events);
+    }
 
-        info!("Exiting");
+    /// Mark whatever a silence covers in `events` and add them in one write
+    fn write(&self, mut events: Vec<Event>) {
+        match self.silences.mark(&mut events) {
+            Ok(()) => {}
+            Err(e) => error!("Could not check silences: {}", e)
+        }
+        match self.event_log.add_all(&events) {
+            Ok(()) => {}
+            Err(e) => error!("Dropped {} events: {}", events.len(), e)
+        }
+    }
+
+    /// Which events a notifier is given: the lowest level from `min_levels.<notifier name>` or
+    /// `min_level`, and the filter expression in `routes.<notifier name>`
+    fn route(config: &Config, name: &str) -> Result<Route> {
+        let setting = |table: &str| config.get_table(table).ok()
+            .and_then(|t| t.get(name).and_then(|v| v.clone().into_str().ok()));
+        let min_level = match setting("min_levels").or_else(|| config.get_str("min_level").ok()) {
+            Some(l) => l.parse().map_err(Error::Config)?,
+            None => Level::TRACE
+        };
+        let filter = match setting("routes") {
+            Some(r) => Some(r.parse().map_err(|e: Error| Error::Config(e.to_string()))?),
+            None => None
+        };
+        return Ok(Route {
+            min_level,
+            filter
+        });
+    }
+
+    /// How crashed waiters and notifiers are restarted, from `restart.backoff_ms`,
+    /// `restart.max_backoff_ms` and `restart.max_restarts`
+    fn backoff(&self) -> Backoff {
+        let default = Backoff::default();
+        return Backoff {
+            initial: self.config.get_int("restart.backoff_ms").ok()
+                .map_or(default.initial, |ms| Duration::from_millis(ms as u64)),
+            max: self.config.get_int("restart.max_backoff_ms").ok()
+                .map_or(default.max, |ms| Duration::from_millis(ms as u64)),
+            max_restarts: self.config.get_int("restart.max_restarts").ok()
+                .map_or(default.max_restarts, |n| n as u32)
+        };
+    }
+
+    /// How waiters queue events for the log, from `channels.capacity` and `channels.overflow`
+    /// unless a declared waiter sets its own `capacity` and `overflow`
+    fn channel(config: &Config) -> Result<ChannelPolicy> {
+        let default = ChannelPolicy::default();
+        let capacity = config.get_int("channels.capacity").ok().map_or(default.capacity, |n| n.max(1) as usize);
+        let overflow = match config.get_str("channels.overflow") {
+            Ok(o) => o.parse()?,
+            Err(_) => default.overflow
+        };
+        return Ok(ChannelPolicy {
+            capacity,
+            overflow
+        });
+    }
+
+    /// The most events written to the log at once, from `log.batch_size`
+    fn batch_size(&self) -> usize {
+        return self.config.get_int("log.batch_size").unwrap_or(1000).max(1) as usize;
+    }
+
+    /// Dedup events by the fields in `dedup.fields` within `dedup.window_secs`, when set
+    fn dedup(config: &Config, event_log: &Arc<EventLog>) -> Result<Option<Dedup>> {
+        let window = match config.get_int("dedup.window_secs") {
+            Ok(secs) if secs > 0 => Duration::from_secs(secs as u64),
+            _ => return Ok(None)
+        };
+        let fields = config.get::<Vec<String>>("dedup.fields")
+            .unwrap_or_else(|_| DEDUP_FIELDS.iter().map(|f| String::from(*f)).collect());
+        return Dedup::new(event_log.clone(), &fields, window).map(Some);
+    }
+
+    /// Open and resolve alerts from events when `alerts.enabled` is set, using `alerts.open`,
+    /// `alerts.resolve` and `alerts.fields`
+    fn alerts(config: &Config, event_log: &Arc<EventLog>) -> Result<Option<Alerts>> {
+        if !config.get_bool("alerts.enabled").unwrap_or(false) {
+            return Ok(None);
+        }
+        let filter = |key: &str, default: &str| config.get_str(key).unwrap_or(String::from(default)).parse()
+            .map_err(|e: Error| Error::Config(e.to_string()));
+        let fields = config.get::<Vec<String>>("alerts.fields")
+            .unwrap_or_else(|_| ALERT_FIELDS.iter().map(|f| String::from(*f)).collect());
+        return Alerts::watching(event_log.clone(), &fields, filter("alerts.open", ALERT_OPEN)?,
+                                filter("alerts.resolve", ALERT_RESOLVE)?).map(Some);
+    }
+
+    /// How long shutdown waits for the notifiers to drain, from `shutdown.timeout_ms`
+    fn shutdown_timeout(&self) -> Duration {
+        return Duration::from_millis(self.config.get_int("shutdown.timeout_ms").unwrap_or(10_000) as u64);
+    }
+
+    /// The identity of this server when relaying events upstream
+    fn node(&self) -> String {
+        return self.config.get_str("node").unwrap_or_else(|_| hostname());
+    }
+
+    /// Open the log with the backend named by `storage`: `rocksdb` (the default), `sqlite`
+    /// or `memory`.  Temporary logs are kept in memory unless configured otherwise.
+    fn create_event_log(config: &Config, temp: bool) -> Result<EventLog> {
+        let path = match App::event_log_path(config, temp)? {
+            Some(p) => p,
+            None => return Ok(EventLog::memory())
+        };
+        return match App::storage(config, temp).as_str() {
+            #[cfg(feature = "sqlite")]
+            "sqlite" => EventLog::sqlite(path.as_path()),
+            _ if temp => EventLog::temporary(path.as_path()),
+            _ => EventLog::new(path.as_path())
+        };
     }
 
-    fn create_event_log(temp: bool) -> EventLog {
+    fn storage(config: &Config, temp: bool) -> String {
+        let default_storage = if temp { "memory" } else { "rocksdb" };
+        return config.get_str("storage").unwrap_or(String::from(default_storage));
+    }
+
+    /// Where the log lives on disk, or None when it is kept in memory
+    fn event_log_path(config: &Config, temp: bool) -> Result<Option<PathBuf>> {
+        let storage = App::storage(config, temp);
+        if storage == "memory" {
+            return Ok(None);
+        }
+
         let (base_dir, pid) = if temp {
-            (dirs::runtime_dir().unwrap(), process::id())
+            (dirs::runtime_dir(), process::id())
         } else {
-            (dirs::data_local_dir().unwrap(), 0)
+            (dirs::data_local_dir(), 0)
         };
+        let base_dir = base_dir
+            .ok_or(Error::Config(String::from("No data directory for the event log")))?;
         let event_log_dir = base_dir
-            .join("waitmate")
-            .join(format!("event_log.{}.rdb", pid));
-        return EventLog::new(event_log_dir.as_path());
+            .join("waitmate");
+        return match storage.as_str() {
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(Some(event_log_dir.join(format!("event_log.{}.sqlite", pid)))),
+            "rocksdb" => Ok(Some(event_log_dir.join(format!("event_log.{}.rdb", pid)))),
+            _ => Err(Error::Config(format!("Unsupported storage {}", storage)))
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    use config::Config;
+    use crossbeam::channel::unbounded;
+    use tempfile::tempdir;
+
+    use crate::waitmate::api::{Event, EventBus, Level, Named, now, Waiter};
+    use crate::waitmate::app::App;
+    use crate::waitmate::dedup::DUPLICATE_OF;
+    use crate::waitmate::error::Result;
+    use crate::waitmate::log::EventLog;
+    use crate::waitmate::metrics::Metrics;
+    use crate::waitmate::rules::Rules;
+    use crate::waitmate::silences::{NewSilence, SILENCED_BY, Silences};
+    use crate::waitmate::thread::{Backoff, ChannelPolicy, Producer, WaiterThread};
+
+    struct BurstWaiter {
+        name: String,
+        count: usize
+    }
+    impl Named for BurstWaiter {
+        fn name(&self) -> &str {
+            return self.name.as_str();
+        }
+    }
+    impl Waiter for BurstWaiter {
+        fn wait(&self, bus: &dyn EventBus) -> Result<()> {
+            for i in 0..self.count {
+                bus.publish(Event::builder("bench")
+                    .source(self)
+                    .description(format!("Something happened {}", i).as_str())
+                    .level(Level::WARN)
+                    .build());
+            }
+            return Ok(());
+        }
+    }
+
+    /// An app built from `config` over `event_log`, without reading any config files
+    fn app(config: Config, event_log: Arc<EventLog>) -> App {
+        return App {
+            dedup: App::dedup(&config, &event_log).unwrap(),
+            rules: Mutex::new(Rules::load(&config, &event_log).unwrap()),
+            alerts: App::alerts(&config, &event_log).unwrap(),
+            silences: Silences::new(event_log.clone()),
+            config,
+            config_file: None,
+            event_log,
+            control: unbounded(),
+            metrics: Arc::new(Metrics::new())
+        };
+    }
+
+    /// Events per second written by `pump` from `waiters` concurrent waiters
+    fn pump_rate(batch_size: i64, waiters: usize, count: usize) -> f64 {
+        let path = tempdir().unwrap().into_path().join("bench.rdb");
+        let mut config = Config::new();
+        config.set("log.batch_size", batch_size).unwrap();
+        let app = app(config, Arc::new(EventLog::new(path.as_path()).unwrap()));
+        let threads = (0..waiters)
+            .map(|i| WaiterThread::new(Box::new(BurstWaiter { name: format!("burst{}", i), count }),
+                                       Backoff::default(), ChannelPolicy::default()))
+            .collect::<Vec<_>>();
+        let channels = threads.iter().map(|t| t as &dyn Producer).collect::<Vec<_>>();
+        let start = Instant::now();
+        app.pump(&channels, &[], &[], false, None);
+        let elapsed = start.elapsed();
+        assert_eq!(waiters * count, app.event_log.build_cursor().build().unwrap().count());
+        return (waiters * count) as f64 / elapsed.as_secs_f64();
+    }
+
+    #[test]
+    fn test_alerts_with_dedup() {
+        let mut config = Config::new();
+        config.set("dedup.window_secs", 60).unwrap();
+        config.set("alerts.enabled", true).unwrap();
+        let app = app(config, Arc::new(EventLog::memory()));
+        let crashed = || Event::builder("Crashed").category("orders").level(Level::ERROR).build();
+        app.add_all(vec![crashed(), crashed()]);
+        app.add_all(vec![crashed()]);
+
+        let alerts = app.alerts.as_ref().unwrap();
+        let listed = alerts.list().unwrap();
+        assert_eq!(1, listed.len());
+        assert_eq!(3, listed[0].count);
+        assert_eq!(alerts.fingerprint(&crashed()), listed[0].fingerprint);
+        let logged = app.event_log.build_cursor().build().unwrap().map(|i| i.unwrap().1).collect::<Vec<_>>();
+        assert_eq!(2, logged.iter().filter(|e| e.labels.contains_key(DUPLICATE_OF)).count());
+        assert_eq!(1, logged.iter().filter(|e| e.name == "Alert opened").count());
+    }
+
+    #[test]
+    fn test_publish_transitions() {
+        let mut config = Config::new();
+        config.set("dedup.window_secs", 60).unwrap();
+        config.set("dedup.fields", vec!["name"]).unwrap();
+        config.set("alerts.enabled", true).unwrap();
+        let app = app(config, Arc::new(EventLog::memory()));
+        let (silence, _) = app.silences.add(&NewSilence {
+            matcher: String::from("category == billing"),
+            duration_secs: Some(60),
+            ..NewSilence::default()
+        }).unwrap();
+        let crashed = |category| Event::builder("Crashed").category(category).level(Level::ERROR).build();
+        app.add_all(vec![crashed("orders"), crashed("billing")]);
+
+        let alerts = app.alerts.as_ref().unwrap();
+        let transitions = alerts.list().unwrap().iter()
+            .map(|a| alerts.ack(a.id.to_string().as_str(), "alice").unwrap().1)
+            .collect::<Vec<_>>();
+        app.publish(transitions);
+        let logged = app.event_log.build_cursor().build().unwrap().map(|i| i.unwrap().1).collect::<Vec<_>>();
+        let acked = logged.iter().filter(|e| e.name == "Alert acknowledged").collect::<Vec<_>>();
+        assert_eq!(2, acked.len());
+        assert!(acked.iter().all(|e| !e.labels.contains_key(DUPLICATE_OF)));
+        assert_eq!(1, acked.iter().filter(|e| e.labels.get(SILENCED_BY) == Some(&silence.id.to_string())).count());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_silenced_dedup_summary() {
+        let mut config = Config::new();
+        config.set("dedup.window_secs", 1).unwrap();
+        let app = app(config, Arc::new(EventLog::memory()));
+        let (silence, _) = app.silences.add(&NewSilence {
+            matcher: String::from("category == orders"),
+            duration_secs: Some(60),
+            ..NewSilence::default()
+        }).unwrap();
+        let crashed = || {
+            let mut event = Event::builder("Crashed").category("orders").level(Level::ERROR).build();
+            event.time = now() - 2_000_000;
+            return event;
+        };
+        app.add_all(vec![crashed(), crashed()]);
+        app.expire_dedup(&[]);
+
+        let logged = app.event_log.build_cursor().build().unwrap().map(|i| i.unwrap().1).collect::<Vec<_>>();
+        let summary = logged.iter().find(|e| e.description.contains("repeated")).unwrap();
+        assert_eq!(Some(&silence.id.to_string()), summary.labels.get(SILENCED_BY));
+    }
+
+    #[test]
+    fn test_config_file() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("site.yaml");
+        fs::write(&file, "node: from-file\n").unwrap();
+        assert_eq!("from-file", App::load_config(Some(file)).unwrap().get_str("node").unwrap());
+        assert!(App::load_config(Some(dir.path().join("missing.yaml"))).is_err());
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_batched_writes() {
+        for waiters in &[1, 4, 8] {
+            let single = pump_rate(1, *waiters, 100_000 / waiters);
+            let batched = pump_rate(1000, *waiters, 100_000 / waiters);
+            println!("{} waiters: {:.0} events/s one at a time, {:.0} events/s batched ({:.1}x)",
+                     waiters, single, batched, batched / single);
+        }
+    }
+}
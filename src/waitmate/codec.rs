@@ -0,0 +1,116 @@
+use std::fmt;
+use std::fmt::Display;
+
+use crate::waitmate::api::Event;
+use crate::waitmate::error::{Error, Result as WaitmateResult};
+
+/// Leading byte of an event stored as MessagePack
+pub const MSGPACK_V1: u8 = 1;
+/// Leading byte of the JSON records written before the binary encoding existed
+const JSON_OBJECT: u8 = b'{';
+
+/// A record in the log that could not be read back as an event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub key: String,
+    pub reason: String,
+}
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not decode {}: {}", self.key, self.reason)
+    }
+}
+
+/// Encode an event for storage as a version byte followed by the encoded event
+pub fn encode(event: &Event) -> WaitmateResult<Vec<u8>> {
+    let mut data = vec![MSGPACK_V1];
+    rmp_serde::encode::write_named(&mut data, event)
+        .map_err(|e| Error::Invalid(format!("Could not encode event {}: {}", event.id, e)))?;
+    return Ok(data);
+}
+
+/// Decode a stored event, accepting both versioned records and plain JSON
+pub fn decode(data: &[u8]) -> Result<Event, String> {
+    return match data.first() {
+        Some(&MSGPACK_V1) => rmp_serde::from_slice(&data[1..])
+            .map_err(|e| e.to_string()),
+        Some(&JSON_OBJECT) => serde_json::from_slice(data)
+            .map_err(|e| e.to_string()),
+        Some(v) => Err(format!("Unknown encoding version {}", v)),
+        None => Err(String::from("Empty record"))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use serde_json::json;
+
+    use crate::waitmate::api::{EmptyNamed, Event, Level};
+    use crate::waitmate::codec::{decode, encode, MSGPACK_V1};
+
+    #[test]
+    fn test_round_trip() {
+        let source = EmptyNamed {};
+        let e = Event::builder("a")
+            .source(&source)
+            .level(Level::CRITICAL)
+            .occurred_at(42)
+            .label("env", "prod")
+            .fields(json!({"attempt": 3, "tags": ["x", "y"]}))
+            .build();
+        let data = encode(&e).unwrap();
+        assert_eq!(MSGPACK_V1, data[0]);
+        assert_eq!(e, decode(&data).unwrap());
+
+        let legacy = serde_json::to_vec(&e).unwrap();
+        assert_eq!(e, decode(&legacy).unwrap());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[99, 1, 2]).is_err());
+        assert!(decode(&[MSGPACK_V1, 0xc1]).is_err());
+        assert!(decode(b"{\"id\":").is_err());
+    }
+
+    /// cargo test --release -- --ignored --nocapture bench_encoding
+    #[test]
+    #[ignore]
+    fn bench_encoding() {
+        let source = EmptyNamed {};
+        let events: Vec<Event> = (0..100_000)
+            .map(|i| Event::builder("bench")
+                .source(&source)
+                .description(format!("Something happened {}", i).as_str())
+                .category("bench")
+                .level(Level::WARN)
+                .label("host", "web1")
+                .build())
+            .collect();
+
+        let start = Instant::now();
+        let json: Vec<Vec<u8>> = events.iter().map(|e| serde_json::to_vec(e).unwrap()).collect();
+        let json_encode = start.elapsed();
+        let start = Instant::now();
+        for d in &json {
+            decode(d).unwrap();
+        }
+        let json_decode = start.elapsed();
+
+        let start = Instant::now();
+        let binary: Vec<Vec<u8>> = events.iter().map(|e| encode(e).unwrap()).collect();
+        let binary_encode = start.elapsed();
+        let start = Instant::now();
+        for d in &binary {
+            decode(d).unwrap();
+        }
+        let binary_decode = start.elapsed();
+
+        let size = |v: &Vec<Vec<u8>>| v.iter().map(|d| d.len()).sum::<usize>() / v.len();
+        println!("json:    encode {:?} decode {:?} {} bytes/event", json_encode, json_decode, size(&json));
+        println!("msgpack: encode {:?} decode {:?} {} bytes/event", binary_encode, binary_decode, size(&binary));
+    }
+}
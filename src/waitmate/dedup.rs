@@ -0,0 +1,227 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::waitmate::api::Event;
+use crate::waitmate::error::{Error, Result};
+use crate::waitmate::filter::Field;
+use crate::waitmate::log::EventLog;
+
+/// Marks an event that was not notified because another with its fingerprint just was
+pub const DUPLICATE_OF: &'static str = "duplicate_of";
+/// Marks the summary of a window, with how many duplicates it suppressed
+pub const REPEATED: &'static str = "repeated";
+
+/// The open window of a fingerprint: the event that was let through and the duplicates since
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+struct DedupWindow {
+    key: String,
+    start: u128,
+    count: u64,
+}
+
+/// Lets the first event with a fingerprint through and marks the rest within `window` as
+/// duplicates, summarising them once the window ends.  Open windows are kept in the
+/// `dedup` column family of the event log so a restart does not alert again.
+pub struct Dedup {
+    event_log: Arc<EventLog>,
+    fields: Vec<Field>,
+    window: Duration
+}
+impl Dedup {
+    const CF: &'static str = "dedup";
+
+    /// Fingerprint events without one by `fields`, e.g. `["host", "source", "name", "labels.service"]`
+    pub fn new(event_log: Arc<EventLog>, fields: &[String], window: Duration) -> Result<Dedup> {
+        if fields.is_empty() {
+            return Err(Error::Config(String::from("dedup needs at least one field")));
+        }
+        let fields = fields.iter()
+            .map(|f| Field::parse(f.as_str()).map_err(|e| Error::Config(e.to_string())))
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Dedup {
+            event_log,
+            fields,
+            window
+        });
+    }
+    /// The event's own fingerprint, or its fields joined by `|`
+    pub fn fingerprint(&self, event: &Event) -> String {
+        return match event.fingerprint.as_ref() {
+            Some(f) => f.clone(),
+            None => self.fields.iter()
+                .map(|f| f.text(event).unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+    }
+    fn get(&self, fingerprint: &str) -> Result<Option<DedupWindow>> {
+        return Ok(self.event_log.get_entry(Dedup::CF, fingerprint.as_bytes())?
+            .and_then(|v| serde_json::from_slice(&v).ok()));
+    }
+    fn put(&self, fingerprint: &str, window: &DedupWindow) -> Result<()> {
+        return self.event_log.put_entry(Dedup::CF, fingerprint.as_bytes(), &serde_json::to_vec(window)?);
+    }
+    /// Fingerprint `event` and mark it a duplicate when one with the same fingerprint was let
+    /// through within the window.  Returns the summary of a window it replaces.
+    pub fn check(&self, event: &mut Event) -> Result<Option<Event>> {
+        if event.labels.contains_key(REPEATED) {
+            return Ok(None);
+        }
+        let fingerprint = self.fingerprint(event);
+        event.fingerprint = Some(fingerprint.clone());
+        let previous = self.get(fingerprint.as_str())?;
+        match previous {
+            Some(mut open) if event.time < open.start + self.window.as_micros() => {
+                open.count += 1;
+                self.put(fingerprint.as_str(), &open)?;
+                let (_, id) = EventLog::parse_key(open.key.as_bytes())?;
+                event.labels.insert(String::from(DUPLICATE_OF), id.to_string());
+                return Ok(None);
+            }
+            _ => {}
+        }
+        self.put(fingerprint.as_str(), &DedupWindow {
+            key: String::from_utf8_lossy(&EventLog::create_key(&event.time, &event.id)).into_owned(),
+            start: event.time,
+            count: 0
+        })?;
+        return match previous {
+            Some(ended) => self.summary(fingerprint.as_str(), &ended),
+            None => Ok(None)
+        };
+    }
+    /// Close the windows that ended by `now`, returning the summaries of those with duplicates
+    pub fn expire(&self, now: u128) -> Result<Vec<Event>> {
+        let mut summaries = Vec::new();
+        for (fingerprint, value) in self.event_log.entries(Dedup::CF)? {
+            let fingerprint = String::from_utf8_lossy(&fingerprint).into_owned();
+            let window: DedupWindow = match serde_json::from_slice(&value) {
+                Ok(w) => w,
+                Err(_) => {
+                    self.event_log.delete_entry(Dedup::CF, fingerprint.as_bytes())?;
+                    continue;
+                }
+            };
+            if now < window.start + self.window.as_micros() {
+                continue;
+            }
+            self.event_log.delete_entry(Dedup::CF, fingerprint.as_bytes())?;
+            match self.summary(fingerprint.as_str(), &window)? {
+                Some(s) => summaries.push(s),
+                None => {}
+            }
+        }
+        return Ok(summaries);
+    }
+    /// "repeated N times", like the event that opened `window`
+    fn summary(&self, fingerprint: &str, window: &DedupWindow) -> Result<Option<Event>> {
+        if window.count == 0 {
+            return Ok(None);
+        }
+        let (time, id) = EventLog::parse_key(window.key.as_bytes())?;
+        let first = match self.event_log.get(&time, &id)? {
+            Some(e) => e,
+            None => return Ok(None)
+        };
+        let mut summary = Event::builder(first.name.as_str())
+            .description(format!("{} repeated {} times in {}s", first.name, window.count,
+                                 self.window.as_secs()).as_str())
+            .category(first.category.as_str())
+            .level(first.level)
+            .host(first.host.as_str())
+            .fingerprint(fingerprint)
+            .parent_id(first.id)
+            .labels(first.labels)
+            .label(REPEATED, window.count.to_string().as_str())
+            .build();
+        summary.source = first.source;
+        return Ok(Some(summary));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use crate::waitmate::api::{EmptyNamed, Event, Level};
+    use crate::waitmate::dedup::{Dedup, DUPLICATE_OF, REPEATED};
+    use crate::waitmate::log::EventLog;
+
+    #[test]
+    fn test_dedup() {
+        let event_log = Arc::new(EventLog::memory());
+        let fields = vec![String::from("name"), String::from("labels.service")];
+        let dedup = Dedup::new(event_log.clone(), &fields, Duration::from_secs(60)).unwrap();
+        let source = EmptyNamed {};
+        let crashed = || Event::new(&source, "Crashed", "exit 1", "service", Level::ERROR)
+            .label("service", "orders");
+
+        let mut first = crashed();
+        assert!(dedup.check(&mut first).unwrap().is_none());
+        assert_eq!(Some(String::from("Crashed|orders")), first.fingerprint);
+        assert!(!first.labels.contains_key(DUPLICATE_OF));
+        event_log.add(&first).unwrap();
+        for _ in 0..3 {
+            let mut again = crashed();
+            assert!(dedup.check(&mut again).unwrap().is_none());
+            assert_eq!(Some(&first.id.to_string()), again.labels.get(DUPLICATE_OF));
+        }
+        let mut other = crashed().label("service", "billing");
+        dedup.check(&mut other).unwrap();
+        assert!(!other.labels.contains_key(DUPLICATE_OF));
+
+        // the window outlives the dedup that opened it
+        let dedup = Dedup::new(event_log.clone(), &fields, Duration::from_secs(60)).unwrap();
+        let mut again = crashed();
+        dedup.check(&mut again).unwrap();
+        assert!(again.labels.contains_key(DUPLICATE_OF));
+        assert!(dedup.expire(first.time + 1).unwrap().is_empty());
+
+        let summaries = dedup.expire(first.time + 60_000_000).unwrap();
+        assert_eq!(1, summaries.len());
+        assert_eq!("Crashed repeated 4 times in 60s", summaries[0].description);
+        assert_eq!(Some(&String::from("4")), summaries[0].labels.get(REPEATED));
+        assert_eq!(Some(first.id), summaries[0].parent_id);
+        let mut after = crashed();
+        dedup.check(&mut after).unwrap();
+        assert!(!after.labels.contains_key(DUPLICATE_OF));
+    }
+
+    #[test]
+    fn test_dedup_restart() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("t.rdb");
+        let fields = vec![String::from("name")];
+        let source = EmptyNamed {};
+        let crashed = || Event::new(&source, "Crashed", "exit 1", "service", Level::ERROR);
+
+        let event_log = Arc::new(EventLog::new(path.as_path()).unwrap());
+        let dedup = Dedup::new(event_log.clone(), &fields, Duration::from_secs(60)).unwrap();
+        let mut first = crashed();
+        dedup.check(&mut first).unwrap();
+        event_log.add(&first).unwrap();
+        drop(dedup);
+        drop(event_log);
+
+        let event_log = Arc::new(EventLog::new(path.as_path()).unwrap());
+        let dedup = Dedup::new(event_log, &fields, Duration::from_secs(60)).unwrap();
+        let mut again = crashed();
+        dedup.check(&mut again).unwrap();
+        assert_eq!(Some(&first.id.to_string()), again.labels.get(DUPLICATE_OF));
+        let summaries = dedup.expire(first.time + 60_000_000).unwrap();
+        assert_eq!(1, summaries.len());
+        assert_eq!(Some(first.id), summaries[0].parent_id);
+    }
+
+    #[test]
+    fn test_dedup_fields() {
+        let event_log = Arc::new(EventLog::memory());
+        assert!(Dedup::new(event_log.clone(), &[], Duration::from_secs(1)).is_err());
+        assert!(Dedup::new(event_log, &[String::from("colour")], Duration::from_secs(1)).is_err());
+    }
+}
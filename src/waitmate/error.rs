@@ -0,0 +1,94 @@
+use std::fmt;
+use std::fmt::Display;
+use std::io;
+
+use crate::waitmate::api::{Event, Level};
+use crate::waitmate::codec::DecodeError;
+
+/// The category of the events waitmate publishes about its own failures
+pub const INTERNAL: &'static str = "waitmate";
+
+#[derive(Debug)]
+pub enum Error {
+    /// The storage backend failed
+    Store(String),
+    /// A stored event could not be read back
+    Decode(DecodeError),
+    /// A key, message or argument was malformed
+    Invalid(String),
+    /// A socket failed
+    Net(String),
+    /// A request to another server failed
+    Http(String),
+    /// The configuration is missing or has a bad value
+    Config(String),
+    /// Shutdown ran out of time
+    Shutdown(String),
+    Io(io::Error),
+}
+impl Error {
+    /// An internal ERROR event reporting this failure on behalf of the named `source`
+    pub fn event(&self, source: &str, name: &str) -> Event {
+        let mut event = Event::builder(name)
+            .description(self.to_string().as_str())
+            .category(INTERNAL)
+            .level(Level::ERROR)
+            .build();
+        event.source = String::from(source);
+        return event;
+    }
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Store(e) => write!(f, "Storage failed: {}", e),
+            Error::Decode(e) => write!(f, "{}", e),
+            Error::Invalid(e) => write!(f, "{}", e),
+            Error::Net(e) => write!(f, "Network failed: {}", e),
+            Error::Http(e) => write!(f, "Request failed: {}", e),
+            Error::Config(e) => write!(f, "Bad configuration: {}", e),
+            Error::Shutdown(e) => write!(f, "Shutdown incomplete: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl std::error::Error for Error {}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        return Error::Decode(e);
+    }
+}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        return Error::Io(e);
+    }
+}
+impl From<rocksdb::Error> for Error {
+    fn from(e: rocksdb::Error) -> Self {
+        return Error::Store(e.to_string());
+    }
+}
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        return Error::Store(e.to_string());
+    }
+}
+impl From<zmq::Error> for Error {
+    fn from(e: zmq::Error) -> Self {
+        return Error::Net(e.to_string());
+    }
+}
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        return Error::Invalid(e.to_string());
+    }
+}
+impl From<config::ConfigError> for Error {
+    fn from(e: config::ConfigError) -> Self {
+        return Error::Config(e.to_string());
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,132 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use log::warn;
+
+use crate::waitmate::api::Event;
+use crate::waitmate::error::{Error, Result};
+use crate::waitmate::log::EventLog;
+use crate::waitmate::query::EventQuery;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Write the events matching `query` as one JSON object per line, returning how many
+pub fn export(event_log: &EventLog, query: &EventQuery, out: &mut dyn Write) -> Result<u64> {
+    let mut count = 0;
+    for item in query.cursor(event_log).build()? {
+        match item {
+            Ok((_, event)) => {
+                if query.matches(&event) {
+                    serde_json::to_writer(&mut *out, &event)?;
+                    out.write_all(b"\n")?;
+                    count += 1;
+                }
+            }
+            Err(Error::Decode(e)) => warn!("Not exporting {}", e),
+            Err(e) => return Err(e)
+        }
+    }
+    out.flush()?;
+    return Ok(count);
+}
+
+/// Like `export` but gzip compressed
+pub fn export_gzip(event_log: &EventLog, query: &EventQuery, out: &mut dyn Write) -> Result<u64> {
+    let mut encoder = GzEncoder::new(out, Compression::default());
+    let count = export(event_log, query, &mut encoder)?;
+    encoder.finish()?;
+    return Ok(count);
+}
+
+/// Read events written by `export`, plain or gzip compressed, into the log keeping their
+/// ids and times.  Events already in the log are skipped.  Returns the number imported and
+/// the number skipped.  A corrupt record with the same key is replaced.
+pub fn import(event_log: &EventLog, input: Box<dyn Read>) -> Result<(u64, u64)> {
+    let mut reader = BufReader::new(input);
+    let gzipped = reader.fill_buf()?.starts_with(&GZIP_MAGIC);
+    let lines: Box<dyn BufRead> = if gzipped {
+        Box::new(BufReader::new(GzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+
+    let mut imported = 0;
+    let mut skipped = 0;
+    for (n, line) in lines.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: Event = match serde_json::from_str(line.as_str()) {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("Skipping line {}: {}", n + 1, e);
+                skipped += 1;
+                continue;
+            }
+        };
+        match event_log.get(&event.time, &event.id) {
+            Ok(Some(_)) => skipped += 1,
+            Ok(None) | Err(Error::Decode(_)) => {
+                event_log.add(&event)?;
+                imported += 1;
+            }
+            Err(e) => return Err(e)
+        }
+    }
+    return Ok((imported, skipped));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::waitmate::api::{EmptyNamed, Event, Level};
+    use crate::waitmate::export::{export, export_gzip, import};
+    use crate::waitmate::log::EventLog;
+    use crate::waitmate::query::EventQuery;
+
+    fn events(event_log: &EventLog) -> Vec<Event> {
+        return event_log.build_cursor().build().unwrap()
+            .map(|i| i.unwrap().1)
+            .collect();
+    }
+
+    #[test]
+    fn test_export_import() {
+        let source = EmptyNamed {};
+        let from = EventLog::memory();
+        from.add(&Event::new(&source, "a", "b", "c", Level::INFO)).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        from.add(&Event::new(&source, "a", "b", "c", Level::ERROR).label("env", "prod")).unwrap();
+
+        let mut query = EventQuery::default();
+        query.min_level = Some(Level::WARN);
+        let mut out = Vec::new();
+        assert_eq!(1, export(&from, &query, &mut out).unwrap());
+        assert_eq!(1, String::from_utf8(out).unwrap().lines().count());
+
+        let mut out = Vec::new();
+        assert_eq!(2, export(&from, &EventQuery::default(), &mut out).unwrap());
+        let to = EventLog::memory();
+        assert_eq!((2, 0), import(&to, Box::new(Cursor::new(out.clone()))).unwrap());
+        assert_eq!(events(&from), events(&to));
+        assert_eq!((0, 2), import(&to, Box::new(Cursor::new(out))).unwrap());
+    }
+
+    #[test]
+    fn test_gzip() {
+        let source = EmptyNamed {};
+        let from = EventLog::memory();
+        from.add(&Event::new(&source, "a", "b", "c", Level::INFO)).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(1, export_gzip(&from, &EventQuery::default(), &mut out).unwrap());
+        assert_eq!(0x1f, out[0]);
+        let to = EventLog::memory();
+        assert_eq!((1, 0), import(&to, Box::new(Cursor::new(out))).unwrap());
+        assert_eq!(events(&from), events(&to));
+    }
+}
@@ -0,0 +1,432 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use serde::de;
+use serde_json::Value;
+
+use crate::waitmate::api::{Event, Level};
+use crate::waitmate::error::{Error, Result};
+use crate::waitmate::std::parse_epoch;
+
+/// An event field a filter can test
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Name,
+    Description,
+    Category,
+    Source,
+    Host,
+    Level,
+    Time,
+    CorrelationId,
+    Fingerprint,
+    Label(String),
+    /// A path into the structured fields, e.g. `fields.http.status`
+    Path(Vec<String>),
+}
+impl Field {
+    pub fn parse(s: &str) -> Result<Field> {
+        return match s {
+            "name" => Ok(Field::Name),
+            "description" => Ok(Field::Description),
+            "category" => Ok(Field::Category),
+            "source" => Ok(Field::Source),
+            "host" => Ok(Field::Host),
+            "level" => Ok(Field::Level),
+            "time" => Ok(Field::Time),
+            "correlation_id" => Ok(Field::CorrelationId),
+            "fingerprint" => Ok(Field::Fingerprint),
+            s if s.starts_with("labels.") && s.len() > "labels.".len() =>
+                Ok(Field::Label(String::from(&s["labels.".len()..]))),
+            s if s.starts_with("fields.") && s.len() > "fields.".len() =>
+                Ok(Field::Path(s["fields.".len()..].split('.').map(String::from).collect())),
+            s => Err(Error::Invalid(format!("Unknown field {}", s)))
+        };
+    }
+    /// The field as text, or None when the event does not have it
+    pub fn text(&self, event: &Event) -> Option<String> {
+        let present = |s: &String| if s.is_empty() { None } else { Some(s.clone()) };
+        return match self {
+            Field::Name => present(&event.name),
+            Field::Description => present(&event.description),
+            Field::Category => present(&event.category),
+            Field::Source => present(&event.source),
+            Field::Host => present(&event.host),
+            Field::Level => Some(event.level.to_string()),
+            Field::Time => Some(event.time.to_string()),
+            Field::CorrelationId => event.correlation_id.clone(),
+            Field::Fingerprint => event.fingerprint.clone(),
+            Field::Label(key) => event.labels.get(key).cloned(),
+            Field::Path(path) => {
+                let mut value = &event.fields;
+                for key in path {
+                    value = value.get(key.as_str())?;
+                }
+                match value {
+                    Value::Null => None,
+                    Value::String(s) => Some(s.clone()),
+                    v => Some(v.to_string())
+                }
+            }
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Match,
+    NotMatch,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+impl Op {
+    fn holds(&self, ordering: Ordering) -> bool {
+        return match self {
+            Op::Eq | Op::Match => ordering == Ordering::Equal,
+            Op::Ne | Op::NotMatch => ordering != Ordering::Equal,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Text(String),
+    Level(Level),
+    Time(u128),
+    Pattern(Regex),
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    /// A bare field, true when the event has it
+    Present(Field),
+    Compare(Field, Op, Operand),
+}
+impl Node {
+    fn matches(&self, event: &Event) -> bool {
+        return match self {
+            Node::And(a, b) => a.matches(event) && b.matches(event),
+            Node::Or(a, b) => a.matches(event) || b.matches(event),
+            Node::Not(a) => !a.matches(event),
+            Node::Present(field) => field.text(event).is_some(),
+            Node::Compare(field, op, operand) => {
+                let ordering = match (field, operand) {
+                    (Field::Level, Operand::Level(level)) => event.level.cmp(level),
+                    (Field::Time, Operand::Time(time)) => event.time.cmp(time),
+                    (field, operand) => {
+                        let text = match field.text(event) {
+                            Some(t) => t,
+                            // a missing field differs from everything
+                            None => return *op == Op::Ne || *op == Op::NotMatch
+                        };
+                        match operand {
+                            Operand::Pattern(pattern) if pattern.is_match(text.as_str()) => Ordering::Equal,
+                            Operand::Pattern(_) => Ordering::Less,
+                            Operand::Text(value) => compare_text(text.as_str(), value.as_str()),
+                            _ => return false
+                        }
+                    }
+                };
+                op.holds(ordering)
+            }
+        };
+    }
+}
+
+/// Plain decimal numbers compare as numbers and anything else, `inf` and `1e3` included,
+/// as text
+fn compare_text(a: &str, b: &str) -> Ordering {
+    return match (decimal(a), decimal(b)) {
+        (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        _ => a.cmp(b)
+    };
+}
+
+/// `text` as a number when it is digits with an optional sign and fraction, e.g. `-2.5`
+fn decimal(text: &str) -> Option<f64> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let (whole, fraction) = match digits.find('.') {
+        Some(i) => (&digits[..i], &digits[i + 1..]),
+        None => (digits, "0")
+    };
+    if whole.is_empty() || fraction.is_empty() || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    return text.parse().ok();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Op(Op),
+    Word(String),
+    Quoted(String),
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next = chars.peek().cloned();
+        let token = match (c, next) {
+            (c, _) if c.is_whitespace() => continue,
+            ('&', Some('&')) => Token::And,
+            ('|', Some('|')) => Token::Or,
+            ('=', Some('=')) => Token::Op(Op::Eq),
+            ('!', Some('=')) => Token::Op(Op::Ne),
+            ('=', Some('~')) => Token::Op(Op::Match),
+            ('!', Some('~')) => Token::Op(Op::NotMatch),
+            ('<', Some('=')) => Token::Op(Op::Le),
+            ('>', Some('=')) => Token::Op(Op::Ge),
+            ('<', _) => Token::Op(Op::Lt),
+            ('>', _) => Token::Op(Op::Gt),
+            ('!', _) => Token::Not,
+            ('(', _) => Token::Open,
+            (')', _) => Token::Close,
+            ('"', _) => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // only quotes and backslashes are escaped, so patterns keep theirs
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => text.push(c),
+                            Some(c) => {
+                                text.push('\\');
+                                text.push(c);
+                            }
+                            None => return Err(Error::Invalid(String::from("Unterminated string")))
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err(Error::Invalid(String::from("Unterminated string")))
+                    }
+                }
+                tokens.push(Token::Quoted(text));
+                continue;
+            }
+            (c, _) if is_word(c) => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.peek().cloned().filter(|c| is_word(*c)) {
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+                continue;
+            }
+            (c, _) => return Err(Error::Invalid(format!("Unexpected {}", c)))
+        };
+        match token {
+            Token::And | Token::Or | Token::Op(Op::Eq) | Token::Op(Op::Ne) | Token::Op(Op::Match)
+            | Token::Op(Op::NotMatch) | Token::Op(Op::Le) | Token::Op(Op::Ge) => { chars.next(); }
+            _ => {}
+        }
+        tokens.push(token);
+    }
+    return Ok(tokens);
+}
+
+fn is_word(c: char) -> bool {
+    return !c.is_whitespace() && !"()!=<>&|\"~".contains(c);
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize
+}
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        return token;
+    }
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.pos);
+    }
+    fn or(&mut self) -> Result<Node> {
+        let mut node = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        return Ok(node);
+    }
+    fn and(&mut self) -> Result<Node> {
+        let mut node = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        return Ok(node);
+    }
+    fn unary(&mut self) -> Result<Node> {
+        return match self.next() {
+            Some(Token::Not) => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let node = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(node),
+                    _ => Err(Error::Invalid(String::from("Expected )")))
+                }
+            }
+            Some(Token::Word(field)) => self.comparison(Field::parse(field.as_str())?),
+            Some(t) => Err(Error::Invalid(format!("Expected a field, not {:?}", t))),
+            None => Err(Error::Invalid(String::from("Expected a field")))
+        };
+    }
+    fn comparison(&mut self, field: Field) -> Result<Node> {
+        let op = match self.peek() {
+            Some(Token::Op(op)) => *op,
+            _ => return Ok(Node::Present(field))
+        };
+        self.pos += 1;
+        let value = match self.next() {
+            Some(Token::Word(v)) | Some(Token::Quoted(v)) => v,
+            _ => return Err(Error::Invalid(format!("Expected a value after {:?}", op)))
+        };
+        let operand = match (&field, op) {
+            (_, Op::Match) | (_, Op::NotMatch) => Operand::Pattern(
+                Regex::new(format!("^(?:{})$", value).as_str()).map_err(|e| Error::Invalid(e.to_string()))?),
+            (Field::Level, _) => Operand::Level(value.parse().map_err(Error::Invalid)?),
+            (Field::Time, _) => Operand::Time(parse_epoch(value.as_str())
+                .ok_or_else(|| Error::Invalid(format!("Expected a timestamp, not {}", value)))?),
+            _ => Operand::Text(value)
+        };
+        return Ok(Node::Compare(field, op, operand));
+    }
+}
+
+/// A condition on events, e.g. `level >= WARN && category =~ "db.*" && labels.env == "prod"`.
+/// Fields are compared with `==`, `!=`, `<`, `<=`, `>`, `>=` and matched in full against
+/// regular expressions with `=~` and `!~`.  Conditions combine with `&&`, `||`, `!` and
+/// parentheses, and a bare field is true when the event has it.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: String,
+    root: Node
+}
+impl Filter {
+    pub fn matches(&self, event: &Event) -> bool {
+        return self.root.matches(event);
+    }
+}
+impl FromStr for Filter {
+    type Err = Error;
+    fn from_str(expr: &str) -> Result<Self> {
+        let invalid = |e: Error| Error::Invalid(format!("{} in filter {}", e, expr));
+        let mut parser = Parser {
+            tokens: tokenize(expr).map_err(invalid)?,
+            pos: 0
+        };
+        let root = parser.or().map_err(invalid)?;
+        if parser.pos < parser.tokens.len() {
+            return Err(invalid(Error::Invalid(format!("Unexpected {:?}", parser.tokens[parser.pos]))));
+        }
+        return Ok(Filter {
+            expr: String::from(expr),
+            root
+        });
+    }
+}
+impl PartialEq for Filter {
+    fn eq(&self, other: &Self) -> bool {
+        return self.expr == other.expr;
+    }
+}
+impl Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+impl<'de> Deserialize<'de> for Filter {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        let expr = String::deserialize(deserializer)?;
+        return expr.parse().map_err(de::Error::custom);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::waitmate::api::{EmptyNamed, Event, Level};
+    use crate::waitmate::filter::Filter;
+
+    fn event() -> Event {
+        let source = EmptyNamed {};
+        return Event::new(&source, "Slow query", "select took 3s", "db.postgres", Level::WARN)
+            .label("env", "prod");
+    }
+
+    fn matches(expr: &str, event: &Event) -> bool {
+        return expr.parse::<Filter>().unwrap().matches(event);
+    }
+
+    #[test]
+    fn test_filter() {
+        let e = event();
+        assert!(matches(r#"level >= WARN && category =~ "db.*" && labels.env == "prod""#, &e));
+        assert!(!matches("level > warning", &e));
+        assert!(matches("level < ERROR", &e));
+        assert!(!matches("category =~ db", &e));
+        assert!(matches("category !~ db", &e));
+        assert!(matches(r#"category =~ "db\.post.*""#, &e));
+        assert!(matches(r#"name == "Slow query" || labels.env == dev"#, &e));
+        assert!(!matches("!(labels.env == prod) || level == INFO", &e));
+        assert!(matches("labels.env && !labels.team", &e));
+        assert!(matches("labels.team != ops", &e));
+        assert!(!matches("labels.team == ops", &e));
+        assert!(matches(format!("time >= {}", e.time).as_str(), &e));
+        assert!(!matches(format!("time > {}", e.time).as_str(), &e));
+
+        let mut e = e;
+        e.fields = json!({"http": {"status": 503, "path": "/orders"}});
+        assert!(matches("fields.http.status >= 500 && fields.http.path =~ \"/orders.*\"", &e));
+        assert!(!matches("fields.http.status < 50", &e));
+        assert!(!matches("fields.http.method", &e));
+    }
+
+    #[test]
+    fn test_numbers_and_text() {
+        let mut e = event().label("latency", "12.5").label("count", "1000");
+        assert!(matches("labels.latency > 9", &e));
+        assert!(matches("labels.latency < 100.25", &e));
+        assert!(matches("labels.latency > -3", &e));
+        assert!(!matches("labels.count == 1e3", &e));
+        e.name = String::from("nan");
+        assert!(matches("name == nan", &e));
+        e.name = String::from("inf");
+        assert!(matches("name < infinity", &e));
+        assert!(!matches("name == infinity", &e));
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        assert!("level >= LOUD".parse::<Filter>().is_err());
+        assert!("colour == red".parse::<Filter>().is_err());
+        assert!("level >=".parse::<Filter>().is_err());
+        assert!("(level >= WARN".parse::<Filter>().is_err());
+        assert!("name == \"open".parse::<Filter>().is_err());
+        assert!("category =~ \"(\"".parse::<Filter>().is_err());
+        assert!("name == a b".parse::<Filter>().is_err());
+        assert_eq!("level >= WARN", "level >= WARN".parse::<Filter>().unwrap().to_string());
+    }
+}
@@ -1,5 +1,7 @@
 use std::borrow::{Borrow, Cow};
 use std::io::Bytes;
+use std::env;
+use std::path::{Component, Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
 use std::thread;
@@ -17,19 +19,35 @@ use actix_web::HttpResponse;
 use actix_web::HttpServer;
 use actix_web::Result;
 use actix_web_actors::ws;
-use log::info;
+use crossbeam::channel::{bounded, Sender};
+use log::{info, warn};
 use mime_guess::from_path;
 use rust_embed::RustEmbed;
+use serde::{Deserialize, Serialize};
 use serde_json::{Deserializer, Value, json};
 use uuid::Uuid;
 
+use crate::waitmate::agent::Registry;
+use crate::waitmate::alerts::Alerts;
 use crate::waitmate::api::{Event, EventBus, Named, Waiter};
+use crate::waitmate::error;
 use crate::waitmate::log::{Cursor, EventLog};
+use crate::waitmate::metrics::Metrics;
+use crate::waitmate::offsets;
+use crate::waitmate::offsets::Command;
+use crate::waitmate::pipeline::Control;
+use crate::waitmate::query::EventQuery;
+use crate::waitmate::search::{search, SearchQuery};
+use crate::waitmate::silences::{NewSilence, Silences};
 
 /// How often heartbeat pings are sent
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
 /// How long before lack of client response causes a timeout
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a reload request waits for the app to apply the config
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(60);
+/// The environment variable holding the token the CLI presents to the admin API
+pub const TOKEN_VAR: &'static str = "WAITMATE_TOKEN";
 /// ws command
 const CMD_SET_OFFSET: Option<&str> = Some("set_offset");
 
@@ -52,11 +70,85 @@ fn get_embedded_file(path: &str) -> HttpResponse {
     }
 }
 
+/// A 400 for malformed requests, otherwise a 500, describing `e`
+fn error_response(e: &error::Error) -> HttpResponse {
+    warn!("{}", e);
+    let body = json!({"error": e.to_string()});
+    return match e {
+        error::Error::Invalid(_) => HttpResponse::BadRequest().json(body),
+        _ => HttpResponse::InternalServerError().json(body)
+    };
+}
+
+fn json_response<T: Serialize>(result: error::Result<T>) -> HttpResponse {
+    return match result {
+        Ok(value) => HttpResponse::Ok().json(value),
+        Err(e) => error_response(&e)
+    };
+}
+
+/// Hand the events announcing a change to the running app, so that the rules, silences and
+/// notifiers see them, or add them to the log directly when no app is listening
+fn publish(events: Vec<Event>, control: &Option<Sender<Control>>, event_log: &EventLog) -> error::Result<()> {
+    let events = match control {
+        Some(control) => match control.send(Control::Publish(events)) {
+            Ok(()) => return Ok(()),
+            Err(e) => match e.into_inner() {
+                Control::Publish(events) => events,
+                _ => return Ok(())
+            }
+        },
+        None => events
+    };
+    return event_log.add_all(&events);
+}
+
+/// Who may use `/api/v1/admin` and change alerts and silences: callers presenting the configured token
+/// or, when there is none, callers on this machine
+#[derive(Clone, Default)]
+pub struct Admin {
+    token: Option<String>,
+    backup_root: Option<PathBuf>,
+}
+impl Admin {
+    /// A 403 unless `req` may use the admin API
+    fn deny(&self, req: &HttpRequest) -> Option<HttpResponse> {
+        let allowed = match self.token.as_ref() {
+            Some(token) => req.headers().get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .map_or(false, |v| v == format!("Bearer {}", token)),
+            None => req.peer_addr().map_or(false, |a| a.ip().is_loopback())
+        };
+        if allowed {
+            return None;
+        }
+        warn!("Refused {} {} from {:?}", req.method(), req.path(), req.peer_addr());
+        return Some(HttpResponse::Forbidden().json(json!({"error": "Not allowed to use the admin API"})));
+    }
+    /// Where to put the backup named `dir`, which must be inside the backup root
+    fn backup_dir(&self, dir: &str) -> error::Result<PathBuf> {
+        let root = match self.backup_root.as_ref() {
+            Some(root) => root,
+            None => return Err(error::Error::Config(String::from("Backups need admin.backup_root")))
+        };
+        let path = root.join(dir);
+        if !path.starts_with(root) || path == *root || path.components().any(|c| c == Component::ParentDir) {
+            return Err(error::Error::Invalid(format!("{} is not inside {}", dir, root.display())));
+        }
+        return Ok(path);
+    }
+}
+
 #[get("/api/v1/event")]
-async fn get_events(_req: HttpRequest, event_log: web::Data<Arc<EventLog>>) -> impl Responder {
+async fn get_events(_req: HttpRequest,
+                    query: web::Query<EventQuery>,
+                    event_log: web::Data<Arc<EventLog>>) -> impl Responder {
+    let events = match query.select(&event_log) {
+        Ok(events) => events,
+        Err(e) => return error_response(&e)
+    };
     let mut resp = String::from("[");
-    let c = event_log.build_cursor().build();
-    for (key, event) in c {
+    for (_, event) in events {
         if resp.len() > 1 {
             resp.push(',');
         }
@@ -69,6 +161,181 @@ async fn get_events(_req: HttpRequest, event_log: web::Data<Arc<EventLog>>) -> i
         .body(resp);
 }
 
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    from: Option<u64>,
+    to: Option<u64>,
+    limit: Option<usize>,
+}
+
+/// Full text search over event descriptions, e.g. `/api/v1/search?q=order+12345`
+#[get("/api/v1/search")]
+async fn search_events(params: web::Query<SearchParams>, event_log: web::Data<Arc<EventLog>>) -> impl Responder {
+    let query = SearchQuery::parse(params.q.as_str())
+        .between(params.from.map(u128::from), params.to.map(u128::from));
+    let events = search(&event_log, &query, params.limit.unwrap_or(1000))
+        .map(|found| found.into_iter().map(|(_, e)| e).collect::<Vec<Event>>());
+    return json_response(events);
+}
+
+#[get("/api/v1/agents")]
+async fn get_agents(_req: HttpRequest, event_log: web::Data<Arc<EventLog>>) -> impl Responder {
+    let registry = Registry::new(event_log.get_ref().clone());
+    return json_response(registry.list());
+}
+
+/// Every alert, oldest first
+#[get("/api/v1/alerts")]
+async fn get_alerts(event_log: web::Data<Arc<EventLog>>) -> impl Responder {
+    return json_response(Alerts::new(event_log.get_ref().clone()).list());
+}
+
+#[derive(Deserialize)]
+struct AlertParams {
+    by: Option<String>,
+}
+
+/// Acknowledge an open alert by id or fingerprint, e.g. `{"by": "alice"}`
+#[post("/api/v1/alerts/{id}/ack")]
+async fn ack_alert(req: HttpRequest, path: web::Path<(String,)>, params: Option<web::Json<AlertParams>>,
+                   event_log: web::Data<Arc<EventLog>>, control: web::Data<Option<Sender<Control>>>,
+                   admin: web::Data<Admin>) -> impl Responder {
+    match admin.deny(&req) {
+        Some(denied) => return denied,
+        None => {}
+    }
+    let by = params.and_then(|p| p.by.clone()).unwrap_or(String::from("api"));
+    return json_response(Alerts::new(event_log.get_ref().clone()).ack(path.0.as_str(), by.as_str())
+        .and_then(|(alert, transition)| publish(vec![transition], control.get_ref(), &event_log).map(|_| alert)));
+}
+
+/// Resolve an unresolved alert by id or fingerprint, e.g. `{"by": "alice"}`
+#[post("/api/v1/alerts/{id}/resolve")]
+async fn resolve_alert(req: HttpRequest, path: web::Path<(String,)>, params: Option<web::Json<AlertParams>>,
+                       event_log: web::Data<Arc<EventLog>>, control: web::Data<Option<Sender<Control>>>,
+                       admin: web::Data<Admin>) -> impl Responder {
+    match admin.deny(&req) {
+        Some(denied) => return denied,
+        None => {}
+    }
+    let by = params.and_then(|p| p.by.clone()).unwrap_or(String::from("api"));
+    return json_response(Alerts::new(event_log.get_ref().clone()).resolve(path.0.as_str(), by.as_str())
+        .and_then(|(alert, transition)| publish(vec![transition], control.get_ref(), &event_log).map(|_| alert)));
+}
+
+/// Every silence, soonest first
+#[get("/api/v1/silences")]
+async fn get_silences(event_log: web::Data<Arc<EventLog>>) -> impl Responder {
+    return json_response(Silences::new(event_log.get_ref().clone()).list());
+}
+
+/// Silence the events matching an expression, e.g.
+/// `{"matcher": "category == web", "duration_secs": 1800, "created_by": "alice", "comment": "deploy"}`
+#[post("/api/v1/silences")]
+async fn add_silence(req: HttpRequest, params: web::Json<NewSilence>, event_log: web::Data<Arc<EventLog>>,
+                     control: web::Data<Option<Sender<Control>>>, admin: web::Data<Admin>) -> impl Responder {
+    match admin.deny(&req) {
+        Some(denied) => return denied,
+        None => {}
+    }
+    return json_response(Silences::new(event_log.get_ref().clone()).add(&params)
+        .and_then(|(silence, created)| publish(vec![created], control.get_ref(), &event_log).map(|_| silence)));
+}
+
+#[delete("/api/v1/silences/{id}")]
+async fn delete_silence(req: HttpRequest, path: web::Path<(String,)>, event_log: web::Data<Arc<EventLog>>,
+                        control: web::Data<Option<Sender<Control>>>, admin: web::Data<Admin>) -> impl Responder {
+    match admin.deny(&req) {
+        Some(denied) => return denied,
+        None => {}
+    }
+    return json_response(Silences::new(event_log.get_ref().clone()).delete(path.0.as_str())
+        .and_then(|(silence, deleted)| publish(vec![deleted], control.get_ref(), &event_log).map(|_| silence)));
+}
+
+#[derive(Deserialize)]
+struct BackupParams {
+    dir: String,
+}
+
+/// Snapshot the live log to a directory under the backup root, e.g. `{"dir": "waitmate-1"}`
+#[post("/api/v1/admin/backup")]
+async fn backup(req: HttpRequest, params: web::Json<BackupParams>, event_log: web::Data<Arc<EventLog>>,
+                admin: web::Data<Admin>) -> impl Responder {
+    match admin.deny(&req) {
+        Some(denied) => return denied,
+        None => {}
+    }
+    return json_response(admin.backup_dir(params.dir.as_str())
+        .and_then(|dir| event_log.backup(&dir).map(|_| json!({"dir": dir}))));
+}
+
+/// Reload the config as SIGHUP does, replying with what was stopped and started
+#[post("/api/v1/admin/reload")]
+async fn reload(req: HttpRequest, control: web::Data<Option<Sender<Control>>>, admin: web::Data<Admin>) -> impl Responder {
+    match admin.deny(&req) {
+        Some(denied) => return denied,
+        None => {}
+    }
+    let control = match control.get_ref() {
+        Some(c) => c.clone(),
+        None => return error_response(&error::Error::Config(String::from("Reloading is not enabled")))
+    };
+    let (reply, replied) = bounded(1);
+    if control.send(Control::Reload(Some(reply))).is_err() {
+        return error_response(&error::Error::Config(String::from("The app is not running")));
+    }
+    return match web::block(move || replied.recv_timeout(RELOAD_TIMEOUT)).await {
+        Ok(result) => json_response(result),
+        Err(e) => error_response(&error::Error::Http(format!("No reply to reload: {}", e)))
+    };
+}
+
+/// The app's counters, e.g. `{"dropped.stdin": 120}`
+#[get("/api/v1/metrics")]
+async fn get_metrics(metrics: web::Data<Arc<Metrics>>) -> impl Responder {
+    return HttpResponse::Ok().json(metrics.counters());
+}
+
+#[get("/api/v1/admin/offsets")]
+async fn get_offsets(req: HttpRequest, event_log: web::Data<Arc<EventLog>>, admin: web::Data<Admin>) -> impl Responder {
+    match admin.deny(&req) {
+        Some(denied) => return denied,
+        None => {}
+    }
+    return json_response(offsets::list(&event_log));
+}
+
+#[derive(Deserialize)]
+struct OffsetParams {
+    name: String,
+    position: Option<String>,
+}
+
+/// Move a consumer, e.g. `{"name": "stdout", "position": "earliest"}`.  The position is a
+/// key, a timestamp, `earliest` (the default) or `latest`.
+#[put("/api/v1/admin/offsets")]
+async fn set_offset(req: HttpRequest, params: web::Json<OffsetParams>, event_log: web::Data<Arc<EventLog>>,
+                    admin: web::Data<Admin>) -> impl Responder {
+    match admin.deny(&req) {
+        Some(denied) => return denied,
+        None => {}
+    }
+    let position = params.position.clone().unwrap_or(String::from("earliest"));
+    return json_response(offsets::apply(&event_log, &Command::Set(params.name.clone(), position)));
+}
+
+#[delete("/api/v1/admin/offsets")]
+async fn delete_offset(req: HttpRequest, params: web::Json<OffsetParams>, event_log: web::Data<Arc<EventLog>>,
+                       admin: web::Data<Admin>) -> impl Responder {
+    match admin.deny(&req) {
+        Some(denied) => return denied,
+        None => {}
+    }
+    return json_response(offsets::apply(&event_log, &Command::Delete(params.name.clone())));
+}
+
 /// /api/v1/connect
 async fn web_socket_connect(
     req: HttpRequest,
@@ -122,11 +389,17 @@ impl Actor for WebSocketSession {
                 ctx.stop();
             } else if act.last_event_time.is_some() {
                 let mut count = 0;
-                let c = act.event_log.build_cursor()
+                let c = match act.event_log.build_cursor()
                     .starting_after(act.last_event_time.unwrap(),
                                     act.last_event_id)
-                    .build();
-                for (key, event) in c {
+                    .build() {
+                    Ok(c) => c,
+                    Err(e) => {
+                        warn!("{}", e);
+                        return;
+                    }
+                };
+                for (_, event) in c.filter_map(|item| item.map_err(|e| warn!("{}", e)).ok()) {
                     ctx.text(serde_json::to_string(&event).unwrap().as_str());
                     act.last_event_id = Some(event.id);
                     act.last_event_time = Some(event.time);
@@ -146,17 +419,26 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Text(text)) => {
-                let command: Value = serde_json::from_str(&text).unwrap();
+                let command: Value = match serde_json::from_str(&text) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        warn!("Ignoring malformed command from client {}: {}", self.id, e);
+                        return;
+                    }
+                };
                 let name = command["command"].as_str();
                 let args = command["args"].as_object();
                 match name {
                     CMD_SET_OFFSET => {
                         let key = args.map_or(None,
                                               |m| m["key"].as_str());
-                        if key.is_some() {
-                            let (t, i) = EventLog::parse_key(key.unwrap().as_bytes()).unwrap();
-                            self.last_event_time = Some(t);
-                            self.last_event_id = Some(i);
+                        match key.map(|k| EventLog::parse_key(k.as_bytes())) {
+                            Some(Ok((t, i))) => {
+                                self.last_event_time = Some(t);
+                                self.last_event_id = Some(i);
+                            }
+                            Some(Err(e)) => warn!("Ignoring offset from client {}: {}", self.id, e),
+                            None => {}
                         }
                     }
                     _ => {}
@@ -169,25 +451,57 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession
 
 pub struct Server {
     address: String,
-    event_log: Arc<EventLog>
+    event_log: Arc<EventLog>,
+    control: Option<Sender<Control>>,
+    metrics: Arc<Metrics>,
+    admin: Admin
 }
 impl Server {
     pub fn new(address: &str, event_log: Arc<EventLog>) -> Self {
         return Self {
             address: String::from(address),
-            event_log
+            event_log,
+            control: None,
+            metrics: Arc::new(Metrics::new()),
+            admin: Admin::default()
         }
     }
+    /// Open `/api/v1/admin` to callers presenting `token` as a bearer token, wherever they are
+    pub fn admin_token(mut self, token: &str) -> Self {
+        self.admin.token = Some(String::from(token));
+        return self;
+    }
+    /// Serve `/api/v1/admin/backup`, writing backups only inside `root`
+    pub fn backup_root(mut self, root: &Path) -> Self {
+        self.admin.backup_root = Some(root.to_path_buf());
+        return self;
+    }
+    /// Serve `/api/v1/admin/reload` by asking the app through `control`
+    pub fn control(mut self, control: Sender<Control>) -> Self {
+        self.control = Some(control);
+        return self;
+    }
+    /// Serve `/api/v1/metrics` from the app's `metrics`
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        return self;
+    }
 }
 impl Waiter for Server {
-    fn wait(&self, _bus: &dyn EventBus) {
+    fn wait(&self, _bus: &dyn EventBus) -> error::Result<()> {
         let event_log = self.event_log.clone();
+        let control = self.control.clone();
+        let metrics = self.metrics.clone();
+        let admin = self.admin.clone();
         let mut sys = System::new(format!("http://{}", self.address));
         // let el = el.clone();
         // srv is server controller type, `dev::Server`
         let srv = HttpServer::new(move || {
             App::new()
                 .data(event_log.clone())
+                .data(control.clone())
+                .data(metrics.clone())
+                .data(admin.clone())
                 // cookie session middleware
                 .wrap(CookieSession::signed(&[0; 32]).secure(false))
                 // enable logger - always register actix-web Logger middleware last
@@ -197,19 +511,100 @@ impl Waiter for Server {
                 // register favicon
                 // .service(favicon)
                 .service(get_events)
+                .service(get_agents)
+                .service(get_alerts)
+                .service(ack_alert)
+                .service(resolve_alert)
+                .service(get_silences)
+                .service(add_silence)
+                .service(delete_silence)
+                .service(search_events)
+                .service(backup)
+                .service(get_offsets)
+                .service(set_offset)
+                .service(delete_offset)
+                .service(reload)
+                .service(get_metrics)
                 .service(web::resource("/api/v1/connect").to(web_socket_connect))
                 .service(index)
                 .service(static_file)
         })
-            .bind(&self.address).unwrap()
+            .bind(&self.address)?
             .run();
 
         // run future
-        sys.block_on(srv).unwrap();
+        sys.block_on(srv)?;
+        return Ok(());
     }
 }
 impl Named for Server {
     fn name(&self) -> &str {
         return self.address.as_str();
     }
-}
\ No newline at end of file
+}
+
+/// A client presenting the admin token in `$WAITMATE_TOKEN`, if set
+fn client() -> awc::Client {
+    return match env::var(TOKEN_VAR) {
+        Ok(token) => awc::Client::build().bearer_auth(token).finish(),
+        Err(_) => awc::Client::default()
+    };
+}
+
+/// GET `url` and parse the response body as JSON
+pub fn get_json(url: &str) -> error::Result<Value> {
+    let mut sys = System::new("waitmate-cli");
+    return sys.block_on(async {
+        let mut response = client()
+            .get(url)
+            .send().await
+            .map_err(|e| error::Error::Http(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(error::Error::Http(format!("{} returned {}", url, response.status())));
+        }
+        return response.json::<Value>()
+            .limit(1 << 24)
+            .await
+            .map_err(|e| error::Error::Http(e.to_string()));
+    });
+}
+
+/// Send `body` as JSON to `url` and parse the response body as JSON
+pub fn send_json(method: Method, url: &str, body: &Value) -> error::Result<Value> {
+    let mut sys = System::new("waitmate-cli");
+    return sys.block_on(async {
+        let mut response = client()
+            .request(method, url)
+            .send_json(body).await
+            .map_err(|e| error::Error::Http(e.to_string()))?;
+        let status = response.status();
+        let value = response.json::<Value>()
+            .await
+            .map_err(|e| error::Error::Http(e.to_string()))?;
+        if !status.is_success() {
+            return Err(error::Error::Http(format!("{} returned {}: {}", url, status, value)));
+        }
+        return Ok(value);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::waitmate::http::Admin;
+
+    #[test]
+    fn test_backup_dir() {
+        let admin = Admin {
+            token: None,
+            backup_root: Some(PathBuf::from("/backups"))
+        };
+        assert_eq!(Path::new("/backups/w1"), admin.backup_dir("w1").unwrap());
+        assert_eq!(Path::new("/backups/w1"), admin.backup_dir("/backups/w1").unwrap());
+        for dir in &["/etc", "../etc", "w1/../../etc", "/backups", ""] {
+            assert!(admin.backup_dir(dir).is_err(), "{}", dir);
+        }
+        assert!(Admin::default().backup_dir("w1").is_err());
+    }
+}
@@ -1,110 +1,135 @@
+use std::collections::VecDeque;
 use std::path::Path;
 use std::str;
-use log::info;
+use log::warn;
 
-use rocksdb::{ColumnFamily, DB, DBRawIterator, Options, ReadOptions};
 use uuid::Uuid;
 
 use crate::waitmate::api::Event;
-use crate::waitmate::log::SeekOp::{Next, Start};
+use crate::waitmate::codec::{decode, DecodeError, encode};
+use crate::waitmate::error::{Error, Result};
+use crate::waitmate::rocks::RocksStore;
+use crate::waitmate::search::{index_keys, INDEX};
+use crate::waitmate::store::{KeyValue, LOG, MemoryStore, Store};
 use crossbeam::channel::Receiver;
 
-#[derive(PartialEq, Eq)]
-enum SeekOp {
-    Start,
-    Next
-}
+/// How many entries a cursor reads from the store at a time
+const BATCH_SIZE: usize = 256;
 
 pub struct Cursor<'a> {
     position: Option<Vec<u8>>,
-    iter: DBRawIterator<'a>,
-    db: &'a DB,
-    off_cf: Option<&'a ColumnFamily>,
-    off_key: Option<Vec<u8>>,
-    seek_op: SeekOp,
+    end: Option<Vec<u8>>,
+    reverse: bool,
+    remaining: Option<usize>,
+    batch: VecDeque<KeyValue>,
+    store: &'a dyn Store,
+    name: Option<String>,
     tail_block: Option<Receiver<bool>>
 }
 impl<'a> Cursor<'a> {
-    fn start(&mut self) {
-        if self.seek_op == Start {
-            match self.position.as_ref() {
-                Some(k) => {
-                    self.iter.seek(k);
-                    if self.iter.valid() {
-                        self.iter.next();
-                    }
-                }
-                _ => {
-                    self.iter.seek_to_first();
-                }
+    fn fill(&mut self) -> Result<()> {
+        if self.batch.is_empty() {
+            let position = self.position.as_ref().map(|p| p.as_slice());
+            if self.reverse {
+                self.batch.extend(self.store.scan_reverse(LOG, position, BATCH_SIZE)?);
+            } else {
+                self.batch.extend(self.store.scan(LOG, position, BATCH_SIZE)?);
             }
         }
+        return Ok(());
     }
-
-    fn next(&mut self) {
-        if self.seek_op == Next {
-            self.iter.next();
+    /// Whether `key` comes before the end of the range, in the cursor's direction
+    fn before_end(&self, key: &[u8]) -> bool {
+        return match self.end.as_ref() {
+            Some(end) if self.reverse => key > end.as_slice(),
+            Some(end) => key < end.as_slice(),
+            None => true
+        };
+    }
+    /// Follow the named offset when something else has moved it, e.g. `waitmate offsets set`
+    fn sync_offset(&mut self) -> Result<()> {
+        match self.name.as_ref() {
+            Some(name) => {
+                let stored = self.store.offset(name)?;
+                if stored.is_some() && stored != self.position {
+                    self.position = stored;
+                    self.batch.clear();
+                }
+            }
+            None => {}
         }
-        self.seek_op = Next;
+        return Ok(());
     }
 }
 impl<'a> Iterator for Cursor<'a> {
-    type Item = (String, Event);
-    fn next(&mut self) -> Option<(String, Event)> {
-        let mut ret = None;
+    /// Records that fail to decode are returned as `Error::Decode` so the caller can skip
+    /// them.  Any other error ends the cursor.
+    type Item = Result<(String, Event)>;
+    fn next(&mut self) -> Option<Self::Item> {
         loop {
-            self.start();
-
-            if self.iter.valid() {
-                self.next();
-
-                if self.iter.valid() {
-                    let key = self.iter.key().unwrap();
-                    let value = self.iter.value().unwrap();
-
-                    ret = Some((
-                        String::from(str::from_utf8(key).unwrap()),
-                        serde_json::from_slice(value).unwrap()
-                    ));
+            if self.remaining == Some(0) {
+                return None;
+            }
+            match self.sync_offset().and_then(|_| self.fill()) {
+                Ok(()) => {}
+                Err(e) => {
+                    self.remaining = Some(0);
+                    return Some(Err(e));
+                }
+            }
 
-                    if self.tail_block.is_some() {
-                        self.position = Some(key.to_vec());
-                    }
-                    if self.off_cf.is_some() {
-                        let off = self.off_cf.unwrap();
-                        let off_key = self.off_key.as_ref().unwrap();
-                        self.db.put_cf(off, off_key, key).unwrap();
+            match self.batch.pop_front() {
+                Some((key, _)) if !self.before_end(&key) => {
+                    self.batch.clear();
+                    return None;
+                }
+                Some((key, value)) => {
+                    let key_str = String::from_utf8_lossy(&key).into_owned();
+                    let ret = match decode(&value) {
+                        Ok(event) => Ok((key_str, event)),
+                        Err(reason) => Err(Error::Decode(DecodeError { key: key_str, reason }))
+                    };
+                    match self.name.as_ref() {
+                        Some(name) => self.store.set_offset(name, &key)
+                            .unwrap_or_else(|e| warn!("{} could not save its offset: {}", name, e)),
+                        None => {}
                     }
+                    self.position = Some(key);
+                    self.remaining = self.remaining.map(|r| r - 1);
+                    return Some(ret);
                 }
+                None => {}
             }
 
-            if !ret.is_some() && self.tail_block.is_some() {
+            if self.tail_block.is_some() && !self.reverse {
                 let block = self.tail_block.as_ref().unwrap();
                 if block.recv().unwrap_or(false) {
-                    self.seek_op = Start;
                     continue;
                 }
             }
 
-            break;
+            return None;
         }
-        return ret;
     }
 }
 
 pub struct CursorBuilder<'a> {
     start: Option<Vec<u8>>,
+    end: Option<Vec<u8>>,
+    reverse: bool,
+    limit: Option<usize>,
     tail_block: Option<Receiver<bool>>,
     name: String,
     start_time: Option<u128>,
     start_id: Option<Uuid>,
-    tailing: bool,
-    db: &'a DB,
+    store: &'a dyn Store,
 }
 impl<'a> CursorBuilder<'a> {
+    /// Block for more events once the end of the log is reached until `tail_block`
+    /// receives `false` or disconnects.  A tailing cursor with an end stops at the first
+    /// event past it.
     pub fn tailing(mut self, tail_block: Option<Receiver<bool>>) -> Self {
         self.tail_block = tail_block;
-        self.tailing = true;
         return self;
     }
     pub fn named(mut self, name: &str) -> Self {
@@ -116,18 +141,41 @@ impl<'a> CursorBuilder<'a> {
         self.start_id = id;
         return self;
     }
-    pub fn build(mut self) -> Cursor<'a> {
-        let mut off_cf_opt: Option<&ColumnFamily> = None;
+    /// Stop before the event with `id` at `time`, or before every event at `time`
+    pub fn ending_before(mut self, time: u128, id: Option<Uuid>) -> Self {
+        self.end = Some(match id {
+            Some(id) => EventLog::create_key(&time, &id),
+            None => EventLog::create_key_before(&time)
+        });
+        return self;
+    }
+    /// Only events at or after `from` and before `to`
+    pub fn between(mut self, from: Option<u128>, to: Option<u128>) -> Self {
+        self.start = from.map(|f| EventLog::create_key_before(&f));
+        self.end = to.map(|t| EventLog::create_key_before(&t));
+        return self;
+    }
+    /// Newest first, from the end of the range back to its start.  Reverse cursors never tail.
+    pub fn reverse(mut self) -> Self {
+        self.reverse = true;
+        return self;
+    }
+    /// Return at most `n` events
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        return self;
+    }
+    /// Fails for a named cursor read in reverse, since offsets only move forwards
+    pub fn build(mut self) -> Result<Cursor<'a>> {
+        if self.reverse && !self.name.is_empty() {
+            return Err(Error::Invalid(format!("Named cursor {} can only read forwards", self.name)));
+        }
         let mut start_key: Option<Vec<u8>> = None;
-        let off_key = self.name.as_bytes();
-        let log_cf = self.db.cf_handle("log").unwrap();
+        let mut name = None;
 
         if !self.name.is_empty() {
-            let off_cf = self.db.cf_handle("offsets").unwrap();
-            start_key = self.db.get_pinned_cf(off_cf, off_key)
-                .unwrap()
-                .map_or(None, |k| Some(k.to_vec()));
-            off_cf_opt = Some(off_cf);
+            // the stored offset is read by the first call to next
+            name = Some(self.name);
         } else if self.start.is_some() {
             start_key = self.start.take();
         } else if self.start_time.is_some() {
@@ -139,115 +187,176 @@ impl<'a> CursorBuilder<'a> {
             }
         }
 
-        let mut opts = ReadOptions::default();
-        if self.tailing {
-            opts.set_tailing(true);
-        }
-
-        let iter = self.db.raw_iterator_cf_opt(log_cf, opts);
-
-        return Cursor {
-            position: start_key,
-            iter,
-            db: self.db,
-            off_cf: off_cf_opt,
-            off_key: Some(off_key.to_vec()),
-            seek_op: SeekOp::Start,
-            tail_block: self.tail_block
+        let (position, end) = if self.reverse {
+            (self.end, start_key)
+        } else {
+            (start_key, self.end)
         };
+        return Ok(Cursor {
+            position,
+            end,
+            reverse: self.reverse,
+            remaining: self.limit,
+            batch: VecDeque::new(),
+            store: self.store,
+            name,
+            tail_block: self.tail_block
+        });
     }
 }
 
 
 pub struct EventLog {
-    db: DB,
-    path: String
+    store: Box<dyn Store>
 }
 impl EventLog {
-    pub fn new(path: &Path) -> EventLog {
-        let mut opts = Options::default();
-        opts.create_if_missing(true);
-        let mut db;
-
-        if path.exists() {
-            let cf_names = vec!["offsets", "log"];
-            db = DB::open_cf(&opts, &path, cf_names).unwrap();
-            info!("Opened existing log {}", path.to_str().unwrap());
-        } else {
-            db = DB::open(&opts, &path).unwrap();
-            db.create_cf("offsets", &opts).unwrap();
-            db.create_cf("log", &opts).unwrap();
-            info!("Created new log {}", path.to_str().unwrap());
-        }
-
+    /// Open or create a RocksDB backed log at `path`
+    pub fn new(path: &Path) -> Result<EventLog> {
+        return Ok(EventLog::with_store(Box::new(RocksStore::new(path)?)));
+    }
+    /// A RocksDB backed log at `path` that is destroyed when it is dropped
+    pub fn temporary(path: &Path) -> Result<EventLog> {
+        return Ok(EventLog::with_store(Box::new(RocksStore::temporary(path)?)));
+    }
+    /// A log that only lives as long as the process
+    pub fn memory() -> EventLog {
+        return EventLog::with_store(Box::new(MemoryStore::new()));
+    }
+    #[cfg(feature = "sqlite")]
+    pub fn sqlite(path: &Path) -> Result<EventLog> {
+        return Ok(EventLog::with_store(Box::new(crate::waitmate::sqlite::SqliteStore::new(path)?)));
+    }
+    pub fn with_store(store: Box<dyn Store>) -> EventLog {
         return EventLog {
-            db,
-            path: String::from(path.to_str().unwrap())
+            store
         }
     }
+    /// The key sorting after every event at `time`
     fn create_key_after(time: &u128) -> Vec<u8> {
-        let uuid = Uuid::from_u128(0);
+        let uuid = Uuid::from_u128(u128::max_value());
         return Self::create_key(time, &uuid);
     }
+    /// The key sorting before every event at `time` and after those before it
+    pub fn create_key_before(time: &u128) -> Vec<u8> {
+        return format!("{}|", time).into_bytes();
+    }
     pub fn create_key(time: &u128, id: &Uuid) -> Vec<u8> {
         let key = format!("{}|{}", time, id);
         return key.into_bytes();
     }
-    pub fn parse_key(key: &[u8]) -> Result<(u128, Uuid), String> {
-        let key_str = String::from(str::from_utf8(key).unwrap());
+    pub fn parse_key(key: &[u8]) -> Result<(u128, Uuid)> {
+        let key_str = str::from_utf8(key)
+            .map_err(|_| Error::Invalid(String::from("Expected key as time|uuid !")))?;
         let key_parts: Vec<&str> = key_str.split("|").collect();
         if key_parts.len() != 2 {
-            return Err(String::from("Expected key as time|uuid !"));
+            return Err(Error::Invalid(String::from("Expected key as time|uuid !")));
         }
-        let time= key_parts[0].parse::<u128>();
-        if time.is_err() {
-            return Err(time.err().map_or(String::from("?"),
-                                         |e| e.to_string()));
+        let time = key_parts[0].parse::<u128>()
+            .map_err(|e| Error::Invalid(e.to_string()))?;
+        let id = key_parts[1].parse::<Uuid>()
+            .map_err(|e| Error::Invalid(e.to_string()))?;
+        return Ok((time, id));
+    }
+    /// Append an event and index the words of its description
+    pub fn add(&self, event: &Event) -> Result<()> {
+        return self.add_all(std::slice::from_ref(event));
+    }
+    /// Append and index `events` in a single write
+    pub fn add_all(&self, events: &[Event]) -> Result<()> {
+        let mut entries = Vec::new();
+        for event in events {
+            let key = Self::create_key(&event.time, &event.id);
+            for index_key in index_keys(event, &key) {
+                entries.push((INDEX, (index_key, Vec::new())));
+            }
+            entries.push((LOG, (key, encode(event)?)));
         }
-        let id = key_parts[1].parse::<Uuid>();
-        if id.is_err() {
-            return Err(id.err().map_or(String::from("?"),
-                                       |e| e.to_string()));
+        return self.store.put_all(&entries);
+    }
+    /// Index every event in the log, for logs written before the index existed
+    pub fn reindex(&self) -> Result<u64> {
+        let mut count = 0;
+        for item in self.build_cursor().build()? {
+            match item {
+                Ok((key, event)) => {
+                    for index_key in index_keys(&event, key.as_bytes()) {
+                        self.store.put(INDEX, &index_key, &[])?;
+                    }
+                    count += 1;
+                }
+                Err(Error::Decode(e)) => warn!("{}", e),
+                Err(e) => return Err(e)
+            }
         }
-        return Ok((time.unwrap(), id.unwrap()));
+        return Ok(count);
     }
-    pub fn add(&self, event: &Event) {
-        let key = Self::create_key(&event.time, &event.id);
-        let val = serde_json::to_vec(event).unwrap();
-        let cf = self.db.cf_handle("log").unwrap();
-        self.db.put_cf(cf, key, val).unwrap();
+    pub fn get(&self, time: &u128, id: &Uuid) -> Result<Option<Event>> {
+        let key = Self::create_key(time, id);
+        return match self.store.get(LOG, &key)? {
+            Some(d) => decode(&d)
+                .map(Some)
+                .map_err(|reason| Error::Decode(DecodeError {
+                    key: String::from_utf8_lossy(&key).into_owned(),
+                    reason
+                })),
+            None => Ok(None)
+        };
     }
-    pub fn get(&self, time: &u128, id: &Uuid) -> Option<Event> {
-        let key = format!("{}|{}", time, id);
-        let cf = self.db.cf_handle("log").unwrap();
-        return self.db.get_pinned_cf(cf, key.as_bytes())
-            .map_or(None, |d| {
-                return d.map_or(None, |d|  {
-                    let e: Event = serde_json::from_slice(d.as_ref()).unwrap();
-                    return Some(e);
-                });
-            });
+    /// Store a value in one of the tables kept beside the log
+    pub fn put_entry(&self, table: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        return self.store.put(table, key, value);
+    }
+    pub fn get_entry(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        return self.store.get(table, key);
+    }
+    pub fn delete_entry(&self, table: &str, key: &[u8]) -> Result<()> {
+        return self.store.delete(table, key);
+    }
+    /// Up to `limit` entries of a table in key order, after `after` or from the start
+    pub fn scan_entries(&self, table: &str, after: Option<&[u8]>, limit: usize) -> Result<Vec<KeyValue>> {
+        return self.store.scan(table, after, limit);
+    }
+    /// All key/value pairs of a table in key order
+    pub fn entries(&self, table: &str) -> Result<Vec<KeyValue>> {
+        let mut entries: Vec<KeyValue> = Vec::new();
+        loop {
+            let after = entries.last().map(|(k, _)| k.clone());
+            let batch = self.store.scan(table, after.as_ref().map(|k| k.as_slice()), BATCH_SIZE)?;
+            if batch.is_empty() {
+                break;
+            }
+            entries.extend(batch);
+        }
+        return Ok(entries);
+    }
+    /// The key of the newest event
+    pub fn head(&self) -> Result<Option<Vec<u8>>> {
+        return Ok(self.store.scan_reverse(LOG, None, 1)?
+            .pop()
+            .map(|(k, _)| k));
+    }
+    /// The number of events the named cursor has yet to consume
+    pub fn pending(&self, name: &str) -> Result<u64> {
+        let position = self.store.offset(name)?;
+        return self.store.count(LOG, position.as_ref().map(|p| p.as_slice()));
+    }
+    /// Snapshot the log, its offsets and every other table to `path` while it stays open
+    pub fn backup(&self, path: &Path) -> Result<()> {
+        return self.store.backup(path);
     }
     pub fn build_cursor(&self) -> CursorBuilder {
         return CursorBuilder {
             start: None,
+            end: None,
+            reverse: false,
+            limit: None,
             tail_block: None,
             name: "".to_string(),
             start_time: None,
             start_id: None,
-            tailing: false,
-            db: &self.db
+            store: self.store.as_ref()
         }
     }
-    fn close(&self) {
-        let _ = DB::destroy(&Options::default(), &self.path);
-        info!("Closed log {}", self.path);
-    }
-}
-impl Drop for EventLog {
-    fn drop(&mut self) {
-        self.close();
-    }
 }
 
 #[cfg(test)]
@@ -260,40 +369,42 @@ mod tests {
 
     use crossbeam::channel::unbounded;
     use crate::waitmate::api::{EmptyNamed, Event, Level};
-    use crate::waitmate::log::EventLog;
+    use crate::waitmate::error::Error;
+    use crate::waitmate::log::{Cursor, EventLog};
+    use crate::waitmate::store::restore;
     use std::borrow::Borrow;
 
     #[test]
     fn test_log_add_get_iter() {
         let dir = tempdir().unwrap().into_path().join("t.rdb");
-        let event_log = EventLog::new(dir.as_path());
+        let event_log = EventLog::new(dir.as_path()).unwrap();
         let source = EmptyNamed {};
 
         let e1 = Event::new(&source, "a", "b", "c", Level::WARN);
         std::thread::sleep(Duration::from_millis(10));
         let e2 = Event::new(&source, "a", "b", "c", Level::WARN);
-        event_log.add(&e1);
-        event_log.add(&e2);
+        event_log.add(&e1).unwrap();
+        event_log.add(&e2).unwrap();
 
-        assert_eq!(None, event_log.get(&0, &Uuid::new_v4()));
-        assert_eq!(e1, event_log.get(&e1.time, &e1.id).unwrap());
-        assert_eq!(e2, event_log.get(&e2.time, &e2.id).unwrap());
+        assert_eq!(None, event_log.get(&0, &Uuid::new_v4()).unwrap());
+        assert_eq!(e1, event_log.get(&e1.time, &e1.id).unwrap().unwrap());
+        assert_eq!(e2, event_log.get(&e2.time, &e2.id).unwrap().unwrap());
 
         let mut count = 0;
         let cursor = event_log.build_cursor()
             .starting_after(e1.time, None)
-            .build();
-        for (key, event) in cursor {
-            println!("{:?} {:?}", key, event);
+            .build().unwrap();
+        for item in cursor {
+            println!("{:?}", item.unwrap());
             count+=1;
         }
         assert_eq!(1, count);
 
         count = 0;
         let cursor = event_log.build_cursor()
-            .build();
-        for (key, event) in cursor {
-            println!("{:?} {:?}", key, event);
+            .build().unwrap();
+        for item in cursor {
+            println!("{:?}", item.unwrap());
             count+=1;
         }
         assert_eq!(2, count);
@@ -302,7 +413,7 @@ mod tests {
     #[test]
     fn test_named_iter() {
         let dir = tempdir().unwrap().into_path().join("t.rdb");
-        let event_log = EventLog::new(dir.as_path());
+        let event_log = EventLog::new(dir.as_path()).unwrap();
         let source = EmptyNamed {};
 
         let e1 = Event::new(&source, "a", "b", "c", Level::WARN);
@@ -310,15 +421,15 @@ mod tests {
         let e2 = Event::new(&source, "a", "b", "c", Level::WARN);
         std::thread::sleep(Duration::from_millis(10));
         let e3 = Event::new(&source, "a", "b", "c", Level::WARN);
-        event_log.add(&e1);
-        event_log.add(&e2);
+        event_log.add(&e1).unwrap();
+        event_log.add(&e2).unwrap();
 
         let mut count = 0;
         let cursor = event_log.build_cursor()
             .named("markie")
-            .build();
-        for (key, event) in cursor {
-            println!("{:?} {:?}", key, event);
+            .build().unwrap();
+        for item in cursor {
+            println!("{:?}", item.unwrap());
             count+=1;
         }
         assert_eq!(2, count);
@@ -326,20 +437,20 @@ mod tests {
         count = 0;
         let cursor = event_log.build_cursor()
             .named("markie")
-            .build();
-        for (key, event) in cursor {
-            println!("{:?} {:?}", key, event);
+            .build().unwrap();
+        for item in cursor {
+            println!("{:?}", item.unwrap());
             count+=1;
         }
         assert_eq!(0, count);
 
         count = 0;
-        event_log.add(&e3);
+        event_log.add(&e3).unwrap();
         let cursor = event_log.build_cursor()
             .named("markie")
-            .build();
-        for (key, event) in cursor {
-            println!("{:?} {:?}", key, event);
+            .build().unwrap();
+        for item in cursor {
+            println!("{:?}", item.unwrap());
             count+=1;
         }
         assert_eq!(1, count);
@@ -348,7 +459,7 @@ mod tests {
     #[test]
     fn test_threaded_tail() {
         let dir = tempdir().unwrap().into_path().join("t.rdb");
-        let event_log = Arc::new(EventLog::new(dir.as_path()));
+        let event_log = Arc::new(EventLog::new(dir.as_path()).unwrap());
         let (tx, rx) = unbounded();
         let source = EmptyNamed {};
 
@@ -358,17 +469,17 @@ mod tests {
 
             std::thread::sleep(Duration::from_millis(100));
             let e1 = Event::new(&source, "a", "b", "c", Level::WARN);
-            event_log.add(&e1);
+            event_log.add(&e1).unwrap();
             tx.send(true).unwrap();
 
             std::thread::sleep(Duration::from_millis(100));
             let e2 = Event::new(&source, "a", "b", "c", Level::WARN);
-            event_log.add(&e2);
+            event_log.add(&e2).unwrap();
             tx.send(true).unwrap();
 
             std::thread::sleep(Duration::from_millis(100));
             let e3 = Event::new(&source, "a", "b", "c", Level::WARN);
-            event_log.add(&e3);
+            event_log.add(&e3).unwrap();
             tx.send(true).unwrap();
             tx.send(false).unwrap();
         });
@@ -376,14 +487,154 @@ mod tests {
         let mut count = 0;
         let cursor = event_log.build_cursor()
             .tailing(Some(rx))
-            .build();
-        for (key, event) in cursor {
-            println!("{:?} {:?}", key, event);
+            .build().unwrap();
+        for item in cursor {
+            println!("{:?}", item.unwrap());
             count+=1;
         }
         assert_eq!(3, count);
     }
 
+    #[test]
+    fn test_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("t.rdb");
+        let source = EmptyNamed {};
+        let events = vec![Event::new(&source, "a", "b", "c", Level::WARN),
+                          Event::new(&source, "d", "e", "f", Level::ERROR)];
+        let event_log = EventLog::new(path.as_path()).unwrap();
+        event_log.add_all(&events).unwrap();
+        drop(event_log);
+
+        let reopened = EventLog::new(path.as_path()).unwrap();
+        let read = reopened.build_cursor().build().unwrap()
+            .map(|item| item.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(2, read.len());
+        assert!(events.iter().all(|e| read.contains(e)));
+    }
+
+    #[test]
+    fn test_backup_restore() {
+        let dir = tempdir().unwrap().into_path();
+        let event_log = EventLog::new(dir.join("t.rdb").as_path()).unwrap();
+        let source = EmptyNamed {};
+
+        let e1 = Event::new(&source, "a", "b", "c", Level::WARN);
+        std::thread::sleep(Duration::from_millis(10));
+        let e2 = Event::new(&source, "a", "b", "c", Level::WARN);
+        std::thread::sleep(Duration::from_millis(10));
+        let e3 = Event::new(&source, "a", "b", "c", Level::WARN);
+        event_log.add(&e1).unwrap();
+        event_log.add(&e2).unwrap();
+        assert_eq!(1, event_log.build_cursor().named("markie").build().unwrap().take(1).count());
+
+        event_log.backup(dir.join("backup").as_path()).unwrap();
+        event_log.add(&e3).unwrap();
+        // the live log carries on after it was backed up
+        drop(event_log);
+        let reopened = EventLog::new(dir.join("t.rdb").as_path()).unwrap();
+        assert_eq!(e1, reopened.get(&e1.time, &e1.id).unwrap().unwrap());
+        assert_eq!(e3, reopened.get(&e3.time, &e3.id).unwrap().unwrap());
+
+        restore(dir.join("backup").as_path(), dir.join("restored.rdb").as_path()).unwrap();
+        assert!(restore(dir.join("backup").as_path(), dir.join("restored.rdb").as_path()).is_err());
+        drop(EventLog::new(dir.join("restored.rdb").as_path()).unwrap());
+        let restored = EventLog::new(dir.join("restored.rdb").as_path()).unwrap();
+        assert_eq!(e1, restored.get(&e1.time, &e1.id).unwrap().unwrap());
+        assert_eq!(None, restored.get(&e3.time, &e3.id).unwrap());
+        let resumed = restored.build_cursor()
+            .named("markie")
+            .build().unwrap()
+            .map(|item| item.unwrap().1.id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![e2.id], resumed);
+        drop(restored);
+        let restarted = EventLog::new(dir.join("restored.rdb").as_path()).unwrap();
+        assert_eq!(0, restarted.build_cursor().named("markie").build().unwrap().count());
+    }
+
+    #[test]
+    fn test_reverse_and_bounds() {
+        let dir = tempdir().unwrap().into_path().join("t.rdb");
+        let event_log = EventLog::new(dir.as_path()).unwrap();
+        let source = EmptyNamed {};
+        let mut events = Vec::new();
+        for _ in 0..4 {
+            let e = Event::new(&source, "a", "b", "c", Level::WARN);
+            event_log.add(&e).unwrap();
+            events.push(e);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let ids = |cursor: Cursor| cursor.map(|item| item.unwrap().1.id).collect::<Vec<_>>();
+        let id = |i: usize| events[i].id;
+
+        assert_eq!(vec![id(3), id(2), id(1), id(0)], ids(event_log.build_cursor().reverse().build().unwrap()));
+        assert_eq!(vec![id(3), id(2)], ids(event_log.build_cursor().reverse().limit(2).build().unwrap()));
+        assert_eq!(vec![id(0)], ids(event_log.build_cursor().limit(1).build().unwrap()));
+        assert!(ids(event_log.build_cursor().limit(0).build().unwrap()).is_empty());
+
+        // the event at `to` is excluded and the one at `from` included, in both directions
+        let between = || event_log.build_cursor().between(Some(events[1].time), Some(events[3].time));
+        assert_eq!(vec![id(1), id(2)], ids(between().build().unwrap()));
+        assert_eq!(vec![id(2), id(1)], ids(between().reverse().build().unwrap()));
+        assert!(ids(event_log.build_cursor().between(Some(events[1].time), Some(events[1].time)).build().unwrap()).is_empty());
+
+        let before = |i: usize, exact: bool| event_log.build_cursor()
+            .ending_before(events[i].time, if exact { Some(events[i].id) } else { None });
+        assert_eq!(vec![id(0), id(1)], ids(before(2, true).build().unwrap()));
+        assert_eq!(vec![id(1), id(0)], ids(before(2, false).reverse().build().unwrap()));
+        assert!(ids(before(0, true).build().unwrap()).is_empty());
+        assert_eq!(vec![id(2), id(1)], ids(before(3, true)
+            .starting_after(events[0].time, Some(events[0].id))
+            .reverse()
+            .build().unwrap()));
+        assert_eq!(vec![id(3)], ids(event_log.build_cursor()
+            .starting_after(events[2].time, Some(events[2].id))
+            .ending_before(events[3].time + 1, None)
+            .build().unwrap()));
+    }
+
+    #[test]
+    fn test_bounded_tail() {
+        let event_log = Arc::new(EventLog::memory());
+        let (tx, rx) = unbounded();
+        let source = EmptyNamed {};
+        let e1 = Event::new(&source, "a", "b", "c", Level::WARN);
+        event_log.add(&e1).unwrap();
+        let end = e1.time + 60_000;
+
+        let t_event_log = event_log.clone();
+        let writer = std::thread::spawn(move || {
+            let source = EmptyNamed {};
+            std::thread::sleep(Duration::from_millis(20));
+            t_event_log.add(&Event::new(&source, "a", "b", "c", Level::WARN)).unwrap();
+            tx.send(true).unwrap();
+            std::thread::sleep(Duration::from_millis(150));
+            t_event_log.add(&Event::new(&source, "a", "b", "c", Level::WARN)).unwrap();
+            tx.send(true).unwrap();
+            // never sends false, the cursor has to stop by itself
+            std::thread::sleep(Duration::from_millis(500));
+        });
+
+        // stops at the first event past the end instead of waiting for more
+        let cursor = event_log.build_cursor()
+            .ending_before(end, None)
+            .tailing(Some(rx.clone()))
+            .build().unwrap();
+        assert_eq!(2, cursor.count());
+
+        // a limit ends tailing as soon as it is reached, and reverse cursors never block
+        assert_eq!(1, event_log.build_cursor().limit(1).tailing(Some(rx.clone())).build().unwrap().count());
+        assert_eq!(3, event_log.build_cursor().reverse().tailing(Some(rx)).build().unwrap().count());
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_named_reverse() {
+        assert!(EventLog::memory().build_cursor().named("markie").reverse().build().is_err());
+    }
+
     #[test]
     fn test_make_parse_key() {
         let og_id = Uuid::new_v4();
@@ -394,4 +645,53 @@ mod tests {
         assert_eq!(123, time);
         assert_eq!(og_id, id);
     }
+
+    #[test]
+    fn test_entries_and_pending() {
+        let event_log = EventLog::memory();
+        let source = EmptyNamed {};
+
+        event_log.put_entry("agents", b"b", b"2").unwrap();
+        event_log.put_entry("agents", b"a", b"1").unwrap();
+        assert_eq!(Some(b"1".to_vec()), event_log.get_entry("agents", b"a").unwrap());
+        assert_eq!(vec![(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"2".to_vec())],
+                   event_log.entries("agents").unwrap());
+        event_log.delete_entry("agents", b"a").unwrap();
+        assert_eq!(None, event_log.get_entry("agents", b"a").unwrap());
+
+        event_log.add(&Event::new(&source, "a", "b", "c", Level::WARN)).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        event_log.add(&Event::new(&source, "a", "b", "c", Level::WARN)).unwrap();
+        assert_eq!(2, event_log.pending("markie").unwrap());
+        let cursor = event_log.build_cursor()
+            .named("markie")
+            .build().unwrap();
+        assert_eq!(1, cursor.take(1).count());
+        assert_eq!(1, event_log.pending("markie").unwrap());
+    }
+
+    #[test]
+    fn test_skip_corrupt() {
+        let dir = tempdir().unwrap().into_path().join("t.rdb");
+        let event_log = EventLog::new(dir.as_path()).unwrap();
+        let source = EmptyNamed {};
+
+        let e1 = Event::new(&source, "a", "b", "c", Level::WARN);
+        std::thread::sleep(Duration::from_millis(10));
+        let e2 = Event::new(&source, "a", "b", "c", Level::WARN);
+        std::thread::sleep(Duration::from_millis(10));
+        let e3 = Event::new(&source, "a", "b", "c", Level::WARN);
+        event_log.add(&e1).unwrap();
+        event_log.store.append(&EventLog::create_key(&e2.time, &e2.id), b"\x01junk").unwrap();
+        event_log.store.append(&EventLog::create_key(&e3.time, &e3.id),
+                               &serde_json::to_vec(&e3).unwrap()).unwrap();
+
+        let items: Vec<_> = event_log.build_cursor().build().unwrap().collect();
+        assert_eq!(3, items.len());
+        assert_eq!(e1, items[0].as_ref().unwrap().1);
+        assert!(match items[1] { Err(Error::Decode(_)) => true, _ => false });
+        assert_eq!(e3, items[2].as_ref().unwrap().1);
+        assert!(event_log.get(&e2.time, &e2.id).is_err());
+        assert_eq!(e3, event_log.get(&e3.time, &e3.id).unwrap().unwrap());
+    }
 }
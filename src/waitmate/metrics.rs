@@ -0,0 +1,36 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Running totals by name, e.g. `dropped.<waiter>`, served at `/api/v1/metrics`
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<String, u64>>
+}
+impl Metrics {
+    pub fn new() -> Metrics {
+        return Metrics::default();
+    }
+    pub fn add(&self, name: &str, n: u64) {
+        let mut counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        *counters.entry(String::from(name)).or_insert(0) += n;
+    }
+    pub fn counters(&self) -> BTreeMap<String, u64> {
+        return self.counters.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::waitmate::metrics::Metrics;
+
+    #[test]
+    fn test_counters() {
+        let metrics = Metrics::new();
+        metrics.add("dropped.stdin", 3);
+        metrics.add("dropped.stdin", 2);
+        metrics.add("dropped.tcp", 1);
+        let counters = metrics.counters();
+        assert_eq!(Some(&5), counters.get("dropped.stdin"));
+        assert_eq!(2, counters.len());
+    }
+}
@@ -1,7 +1,26 @@
 pub(crate) mod api;
+pub(crate) mod codec;
+pub(crate) mod error;
 pub(crate) mod log;
+pub(crate) mod store;
+mod rocks;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub(crate) mod filter;
+pub(crate) mod query;
+pub(crate) mod search;
+pub(crate) mod export;
+pub(crate) mod offsets;
+pub(crate) mod dedup;
+pub(crate) mod schedule;
+pub(crate) mod rules;
+pub(crate) mod metrics;
+pub(crate) mod pipeline;
 mod thread;
 mod std;
 mod net;
-mod http;
+pub(crate) mod http;
+pub(crate) mod agent;
+pub(crate) mod alerts;
+pub(crate) mod silences;
 pub(crate) mod app;
\ No newline at end of file
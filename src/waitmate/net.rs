@@ -1,41 +1,134 @@
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
 use lazy_static::lazy_static;
+use log::warn;
+use serde::{Deserialize, Serialize};
 
-use crate::waitmate::api::{Event, EventBus, Named, Notifier, Waiter};
+use crate::waitmate::agent::Registry;
+use crate::waitmate::api::{Event, EventBus, hostname, Named, Notifier, Waiter};
+use crate::waitmate::error::{Error, Result};
+use crate::waitmate::filter::Filter;
+use crate::waitmate::log::EventLog;
 
 lazy_static! {
     static ref CTX: zmq::Context = zmq::Context::new();
 }
 
+/// How often, in milliseconds, the server checks whether it should stop
+const POLL_MS: i64 = 200;
+/// How long a client waits to send a request or hear the reply
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// The events a relay forwards upstream unless configured otherwise
+pub const RELAY_FILTER: &'static str = "level >= WARN";
+
+/// Sent periodically by every client so the server knows who is out there
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub host: String,
+    pub pid: u32,
+    pub version: String,
+    pub spool_depth: u64,
+}
+
+/// Everything a client may send.  Bare events are still accepted so that older clients
+/// keep working.  The server replies `OK`, or `ERR` and a reason.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Message {
+    Heartbeat { heartbeat: Heartbeat },
+    Event(Event),
+}
+impl Message {
+    /// A heartbeat if the message has a `heartbeat` key, otherwise an event.  Untagged
+    /// deserialization would buffer the event first, and the buffer can't hold its `u128` time.
+    pub fn parse(bytes: &[u8]) -> Result<Message> {
+        let envelope: Envelope = serde_json::from_slice(bytes)?;
+        return match envelope.heartbeat {
+            Some(heartbeat) => Ok(Message::Heartbeat { heartbeat }),
+            None => Ok(Message::Event(serde_json::from_slice(bytes)?))
+        };
+    }
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    heartbeat: Option<Heartbeat>
+}
+
 pub struct Server {
     skt: zmq::Socket,
     name: String,
+    node: Option<String>,
+    registry: Option<Arc<Registry>>,
     kill_byte: bool
 }
 impl Server {
-    pub fn new(address: &str) -> Self {
+    pub fn new(address: &str) -> Result<Self> {
         return Self::new_test(address, false);
     }
-    pub fn new_test(address: &str, kill_byte: bool) -> Self {
-        let skt = CTX.socket(zmq::REP).unwrap();
-        skt.bind(address).unwrap();
-        return Server {
+    pub fn new_test(address: &str, kill_byte: bool) -> Result<Self> {
+        let skt = CTX.socket(zmq::REP)?;
+        skt.bind(address)?;
+        return Ok(Server {
             skt,
             name: String::from(format!("Server@{}", address)),
+            node: None,
+            registry: None,
             kill_byte
-        }
+        });
+    }
+    /// Record client heartbeats in the given registry
+    pub fn registry(mut self, registry: Arc<Registry>) -> Self {
+        self.registry = Some(registry);
+        return self;
+    }
+    /// Drop events that have already been relayed through this node
+    pub fn node(mut self, node: &str) -> Self {
+        self.node = Some(String::from(node));
+        return self;
     }
 }
 impl Waiter for Server {
-    fn wait(&self, bus: &dyn EventBus) {
+    fn wait(&self, bus: &dyn EventBus) -> Result<()> {
         let mut msg = zmq::Message::new();
         loop {
-            self.skt.recv(&mut msg, 0).unwrap();
+            if self.skt.poll(zmq::POLLIN, POLL_MS)? == 0 {
+                if bus.stopping() {
+                    return Ok(());
+                }
+                continue;
+            }
+            self.skt.recv(&mut msg, 0)?;
             if self.kill_byte && msg.len() == 1 && msg.as_ref()[0] == 0 {
-                break;
+                return Ok(());
+            }
+            let message = match Message::parse(msg.as_ref()) {
+                Ok(m) => m,
+                Err(e) => {
+                    warn!("{} ignoring malformed message: {}", self.name, e);
+                    self.skt.send(format!("ERR {}", e).as_str(), 0)?;
+                    continue;
+                }
+            };
+            match message {
+                Message::Heartbeat { heartbeat } => {
+                    match self.registry.as_ref().map(|r| r.heartbeat(&heartbeat, self, bus)) {
+                        Some(Err(e)) => bus.publish(e.event(self.name.as_str(), "Could not record heartbeat")),
+                        _ => {}
+                    }
+                }
+                Message::Event(event) => {
+                    let looped = self.node.as_ref()
+                        .map_or(false, |n| event.relay_path.contains(n));
+                    if !looped {
+                        bus.publish(event);
+                    }
+                }
             }
-            let event: Event = serde_json::from_slice(msg.as_ref()).unwrap();
-            bus.publish(event);
-            self.skt.send("OK", 0).unwrap();
+            self.skt.send("OK", 0)?;
         }
     }
 }
@@ -46,25 +139,72 @@ impl Named for Server {
 }
 
 
+/// A REQ socket that gives up on a silent server rather than blocking for ever.  A REQ socket
+/// that missed its reply can't send again, so it is replaced with a fresh connection.
+struct Requester {
+    address: String,
+    timeout: Duration,
+    skt: Mutex<zmq::Socket>
+}
+impl Requester {
+    fn new(address: &str, timeout: Duration) -> Result<Self> {
+        return Ok(Requester {
+            address: String::from(address),
+            timeout,
+            skt: Mutex::new(Requester::connect(address, timeout)?)
+        });
+    }
+    fn connect(address: &str, timeout: Duration) -> Result<zmq::Socket> {
+        let skt = CTX.socket(zmq::REQ)?;
+        skt.set_linger(0)?;
+        skt.set_sndtimeo(timeout.as_millis() as i32)?;
+        skt.set_rcvtimeo(timeout.as_millis() as i32)?;
+        skt.connect(address)?;
+        return Ok(skt);
+    }
+    /// Send `msg` and wait for the reply
+    fn request(&self, msg: &[u8]) -> Result<String> {
+        let mut skt = self.skt.lock().unwrap_or_else(|e| e.into_inner());
+        let reply = skt.send(msg, 0).and_then(|_| skt.recv_string(0));
+        return match reply {
+            Ok(reply) => Ok(reply.unwrap_or_else(|b| String::from_utf8_lossy(&b).into_owned())),
+            Err(e) => {
+                *skt = Requester::connect(self.address.as_str(), self.timeout)?;
+                match e {
+                    zmq::Error::EAGAIN => Err(Error::Net(format!("{} did not reply within {}ms",
+                                                                 self.address, self.timeout.as_millis()))),
+                    e => Err(Error::from(e))
+                }
+            }
+        };
+    }
+}
+
 pub struct Client {
-    skt: zmq::Socket,
+    requester: Requester,
     name: String
 }
 impl Client {
-    pub fn new(address: &str) -> Self {
-        let skt = CTX.socket(zmq::REQ).unwrap();
-        skt.connect(address).unwrap();
-        return Client {
-            skt,
+    pub fn new(address: &str) -> Result<Self> {
+        return Self::with_timeout(address, TIMEOUT);
+    }
+    /// A client that fails a notification the server hasn't answered within `timeout`
+    pub fn with_timeout(address: &str, timeout: Duration) -> Result<Self> {
+        return Ok(Client {
+            requester: Requester::new(address, timeout)?,
             name: String::from(format!("Client@{}", address))
-        }
+        });
     }
 }
 impl Notifier for Client {
-    fn notify(&self, event: Event, _event_bus: &dyn EventBus) {
-        let msg = serde_json::to_vec(&event).unwrap();
-        self.skt.send(msg, 0).unwrap();
-        let _ = self.skt.recv_string(0).unwrap();
+    /// Fails with `Error::Net` when the server can't be reached, or `Error::Invalid` when it
+    /// rejects the event
+    fn notify(&self, event: Event, _event_bus: &dyn EventBus) -> Result<()> {
+        let reply = self.requester.request(&serde_json::to_vec(&event)?)?;
+        if reply.starts_with("ERR") {
+            return Err(Error::Invalid(format!("{} rejected event {}: {}", self.name, event.id, reply)));
+        }
+        return Ok(());
     }
 }
 impl Named for Client {
@@ -73,20 +213,111 @@ impl Named for Client {
     }
 }
 
+
+/// Sends a heartbeat to the server on its own socket at a fixed interval until the app stops.
+/// The spool depth is the number of events the named client cursor has yet to deliver.
+pub struct Heartbeater {
+    requester: Requester,
+    event_log: Arc<EventLog>,
+    spool: String,
+    interval: Duration
+}
+impl Heartbeater {
+    pub fn new(address: &str, event_log: Arc<EventLog>, spool: &str, interval: Duration) -> Result<Self> {
+        return Ok(Heartbeater {
+            requester: Requester::new(address, TIMEOUT)?,
+            event_log,
+            spool: String::from(spool),
+            interval
+        });
+    }
+    pub fn heartbeat(&self) -> Result<Heartbeat> {
+        return Ok(Heartbeat {
+            host: hostname(),
+            pid: process::id(),
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            spool_depth: self.event_log.pending(self.spool.as_str())?
+        });
+    }
+    fn beat(&self) -> Result<()> {
+        let message = Message::Heartbeat { heartbeat: self.heartbeat()? };
+        self.requester.request(&serde_json::to_vec(&message)?)?;
+        return Ok(());
+    }
+}
+impl Named for Heartbeater {
+    fn name(&self) -> &str {
+        return "Heartbeater";
+    }
+}
+impl Waiter for Heartbeater {
+    fn wait(&self, bus: &dyn EventBus) -> Result<()> {
+        let mut last_beat: Option<Instant> = None;
+        while !bus.stopping() {
+            if last_beat.map_or(true, |t| t.elapsed() >= self.interval) {
+                match self.beat() {
+                    Ok(()) => {}
+                    Err(e) => warn!("Heartbeat was not acknowledged: {}", e)
+                }
+                last_beat = Some(Instant::now());
+            }
+            thread::sleep(Duration::from_millis(POLL_MS as u64));
+        }
+        return Ok(());
+    }
+}
+
+/// Forwards a filtered subset of events to an upstream server.  Each relayed event
+/// records this node in its relay path so that chained servers never forward it twice.
+pub struct Relay {
+    client: Client,
+    node: String,
+    filter: Filter,
+    name: String
+}
+impl Relay {
+    pub fn new(upstream: &str, node: &str, filter: Filter) -> Result<Self> {
+        return Ok(Relay {
+            client: Client::new(upstream)?,
+            node: String::from(node),
+            filter,
+            name: String::from(format!("Relay@{}", upstream))
+        });
+    }
+}
+impl Notifier for Relay {
+    fn notify(&self, mut event: Event, event_bus: &dyn EventBus) -> Result<()> {
+        if !self.filter.matches(&event) || event.relay_path.contains(&self.node) {
+            return Ok(());
+        }
+        event.relay_path.push(self.node.clone());
+        return self.client.notify(event, event_bus);
+    }
+}
+impl Named for Relay {
+    fn name(&self) -> &str {
+        return self.name.as_str();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{process, thread};
-    use std::time::{Duration, SystemTime};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, SystemTime};
 
-    use crate::waitmate::api::{EmptyEventBus, EmptyNamed, Event, Level, Notifier, Waiter};
-    use crate::waitmate::net::{Client, Server};
+    use crate::waitmate::agent::Registry;
+    use crate::waitmate::api::{EmptyEventBus, EmptyNamed, Event, EventBus, Level, Notifier, Waiter};
+    use crate::waitmate::error::Error;
+    use crate::waitmate::log::EventLog;
+    use crate::waitmate::net::{Client, Heartbeat, Heartbeater, Message, Relay, Server};
     use crate::waitmate::thread::EventChannel;
 
     #[test]
     fn test_event_reqrep() {
         let addr = format!("ipc:///tmp/wmnetrstest.{}", process::id());
-        let server = Server::new_test(addr.as_str(), true);
-        let client = Client::new(addr.as_str());
+        let server = Server::new_test(addr.as_str(), true).unwrap();
+        let client = Client::new(addr.as_str()).unwrap();
         let kill_bytes: [u8;1] = [0];
         let source = EmptyNamed {};
 
@@ -97,8 +328,8 @@ mod tests {
         let e = Event::new(&source, "a", "b", "c", Level::WARN);
 
         thread::spawn(move || server.wait(&test_server_bus));
-        client.notify(e, &test_client_bus);
-        client.skt.send(kill_bytes.as_ref(), 0).unwrap(); // kill the server
+        client.notify(e, &test_client_bus).unwrap();
+        client.requester.skt.lock().unwrap().send(kill_bytes.as_ref(), 0).unwrap(); // kill the server
         let e = receiver.recv_timeout(Duration::from_millis(1000)).unwrap().unwrap();
 
         assert!(e.time >= start);
@@ -107,4 +338,108 @@ mod tests {
         assert_eq!("c", e.category);
         assert_eq!(Level::WARN, e.level);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_message() {
+        let source = EmptyNamed {};
+        let event = Event::new(&source, "a", "b", "c", Level::WARN);
+        match Message::parse(&serde_json::to_vec(&event).unwrap()).unwrap() {
+            Message::Event(e) => assert_eq!(event, e),
+            other => panic!("Expected an event, not {:?}", other)
+        }
+        let heartbeat = Heartbeat {
+            host: String::from("h"),
+            pid: 1,
+            version: String::from("v"),
+            spool_depth: 2
+        };
+        let message = Message::Heartbeat { heartbeat: heartbeat.clone() };
+        match Message::parse(&serde_json::to_vec(&message).unwrap()).unwrap() {
+            Message::Heartbeat { heartbeat: h } => assert_eq!(heartbeat, h),
+            other => panic!("Expected a heartbeat, not {:?}", other)
+        }
+        assert!(Message::parse(b"{\"heartbeat\": {}}").is_err());
+        assert!(Message::parse(b"{\"name\": \"a\"}").is_err());
+    }
+
+    #[test]
+    fn test_client_timeout() {
+        let addr = format!("ipc:///tmp/wmnettimeouttest.{}", process::id());
+        let client = Client::with_timeout(addr.as_str(), Duration::from_millis(100)).unwrap();
+        let source = EmptyNamed {};
+        let test_client_bus = EmptyEventBus {};
+
+        // nobody is listening, and a timed out client can try again
+        for _ in 0..2 {
+            match client.notify(Event::new(&source, "a", "b", "c", Level::WARN), &test_client_bus) {
+                Err(Error::Net(_)) => {}
+                other => panic!("Expected a timeout, not {:?}", other)
+            }
+        }
+
+        let server = Server::new_test(addr.as_str(), true).unwrap();
+        let (test_server_bus, receiver) = EventChannel::new();
+        thread::spawn(move || server.wait(&test_server_bus));
+        // give the client a few goes at reconnecting
+        assert!((0..10).any(|_| client.notify(Event::new(&source, "late", "b", "c", Level::WARN), &test_client_bus).is_ok()));
+        client.requester.skt.lock().unwrap().send([0u8].as_ref(), 0).unwrap(); // kill the server
+        assert_eq!("late", receiver.recv_timeout(Duration::from_millis(1000)).unwrap().unwrap().name);
+    }
+
+    /// Asks publishers to stop once `until` has passed
+    struct StoppingBus {
+        until: Instant
+    }
+    impl EventBus for StoppingBus {
+        fn publish(&self, _event: Event) {}
+        fn stopping(&self) -> bool {
+            return Instant::now() >= self.until;
+        }
+    }
+
+    #[test]
+    fn test_heartbeater_stops() {
+        let addr = format!("ipc:///tmp/wmnetheartbeattest.{}", process::id());
+        let registry = Arc::new(Registry::new(Arc::new(EventLog::memory())));
+        let server = Server::new_test(addr.as_str(), true).unwrap().registry(registry.clone());
+        let (test_server_bus, _receiver) = EventChannel::new();
+        thread::spawn(move || server.wait(&test_server_bus));
+
+        let heartbeater = Heartbeater::new(addr.as_str(), Arc::new(EventLog::memory()), "client",
+                                           Duration::from_secs(3600)).unwrap();
+        let start = Instant::now();
+        heartbeater.wait(&StoppingBus { until: start + Duration::from_millis(500) }).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(1, registry.list().unwrap().len());
+        heartbeater.requester.skt.lock().unwrap().send([0u8].as_ref(), 0).unwrap(); // kill the server
+    }
+
+    #[test]
+    fn test_relay_filter_and_path() {
+        let addr = format!("ipc:///tmp/wmnetrelaytest.{}", process::id());
+        let server = Server::new_test(addr.as_str(), true).unwrap().node("central");
+        let relay = Relay::new(addr.as_str(), "site", "level >= warning && category == \"c\"".parse().unwrap()).unwrap();
+        let killer = Client::new(addr.as_str()).unwrap();
+        let kill_bytes: [u8;1] = [0];
+        let source = EmptyNamed {};
+
+        let (test_server_bus, receiver) = EventChannel::new();
+        let test_client_bus = EmptyEventBus {};
+
+        thread::spawn(move || server.wait(&test_server_bus));
+        relay.notify(Event::new(&source, "info", "b", "c", Level::INFO), &test_client_bus).unwrap();
+        let mut seen = Event::new(&source, "seen", "b", "c", Level::ERROR);
+        seen.relay_path.push(String::from("site"));
+        relay.notify(seen, &test_client_bus).unwrap();
+        let mut looped = Event::new(&source, "looped", "b", "c", Level::ERROR);
+        looped.relay_path.push(String::from("central"));
+        relay.notify(looped, &test_client_bus).unwrap();
+        relay.notify(Event::new(&source, "other", "b", "d", Level::ERROR), &test_client_bus).unwrap();
+        relay.notify(Event::new(&source, "warn", "b", "c", Level::WARN), &test_client_bus).unwrap();
+        killer.requester.skt.lock().unwrap().send(kill_bytes.as_ref(), 0).unwrap(); // kill the server
+
+        let e = receiver.recv_timeout(Duration::from_millis(1000)).unwrap().unwrap();
+        assert_eq!("warn", e.name);
+        assert_eq!(vec![String::from("site")], e.relay_path);
+    }
+}
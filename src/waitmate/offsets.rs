@@ -0,0 +1,192 @@
+use std::str::FromStr;
+
+use actix_web::http::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::waitmate::error::{Error, Result};
+use crate::waitmate::http;
+use crate::waitmate::log::EventLog;
+use crate::waitmate::std::parse_epoch;
+use crate::waitmate::store::OFFSETS;
+
+/// Where to move a named cursor
+#[derive(Debug, PartialEq)]
+pub enum Position {
+    /// Before the first event, replaying the whole log
+    Earliest,
+    /// After the newest event, skipping everything not yet consumed
+    Latest,
+    /// After the event with this key
+    Key(Vec<u8>),
+    /// Before the first event at or after this time in microseconds
+    Time(u128),
+}
+impl FromStr for Position {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        return match s {
+            "earliest" => Ok(Position::Earliest),
+            "latest" => Ok(Position::Latest),
+            s if s.contains('|') => EventLog::parse_key(s.as_bytes())
+                .map(|_| Position::Key(s.as_bytes().to_vec())),
+            s => parse_epoch(s)
+                .map(Position::Time)
+                .ok_or_else(|| Error::Invalid(format!("Expected a key, a timestamp, earliest or latest, not {}", s)))
+        };
+    }
+}
+
+/// A named cursor's last key and how many events it is behind the head of the log
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConsumerOffset {
+    pub name: String,
+    pub key: String,
+    pub lag: u64,
+}
+
+pub enum Command {
+    List,
+    Set(String, String),
+    Delete(String),
+}
+
+pub fn list(event_log: &EventLog) -> Result<Vec<ConsumerOffset>> {
+    let mut offsets = Vec::new();
+    for (name, key) in event_log.entries(OFFSETS)? {
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let lag = event_log.pending(name.as_str())?;
+        offsets.push(ConsumerOffset {
+            name,
+            key: String::from_utf8_lossy(&key).into_owned(),
+            lag
+        });
+    }
+    return Ok(offsets);
+}
+
+/// Move the named cursor.  A running consumer follows with the next event it reads.
+pub fn set(event_log: &EventLog, name: &str, position: &Position) -> Result<()> {
+    let key = match position {
+        Position::Earliest => Vec::new(),
+        Position::Latest => event_log.head()?.unwrap_or_default(),
+        Position::Key(k) => k.clone(),
+        Position::Time(t) => EventLog::create_key_before(t)
+    };
+    return event_log.put_entry(OFFSETS, name.as_bytes(), &key);
+}
+
+/// Forget the named cursor, which starts from the beginning of the log if it is used again
+pub fn delete(event_log: &EventLog, name: &str) -> Result<()> {
+    return event_log.delete_entry(OFFSETS, name.as_bytes());
+}
+
+/// Apply `command` to the local log, returning the offsets afterwards
+pub fn apply(event_log: &EventLog, command: &Command) -> Result<Vec<ConsumerOffset>> {
+    match command {
+        Command::List => {}
+        Command::Set(name, position) => set(event_log, name.as_str(), &position.parse()?)?,
+        Command::Delete(name) => delete(event_log, name.as_str())?
+    }
+    return list(event_log);
+}
+
+/// Apply `command` through the admin API of the server at `server`
+pub fn apply_remote(server: &str, command: &Command) -> Result<Vec<ConsumerOffset>> {
+    let url = format!("{}/api/v1/admin/offsets", server.trim_end_matches('/'));
+    let value = match command {
+        Command::List => http::get_json(url.as_str())?,
+        Command::Set(name, position) => http::send_json(Method::PUT, url.as_str(),
+                                                        &json!({"name": name, "position": position}))?,
+        Command::Delete(name) => http::send_json(Method::DELETE, url.as_str(), &json!({"name": name}))?
+    };
+    return Ok(serde_json::from_value(value)?);
+}
+
+pub fn print_offsets(offsets: Vec<ConsumerOffset>) {
+    for o in offsets {
+        let key = if o.key.is_empty() { "-" } else { o.key.as_str() };
+        println!("{:<30} {:<60} {:>8}", o.name, key, o.lag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::waitmate::api::{EmptyNamed, Event, Level};
+    use crate::waitmate::log::EventLog;
+    use crate::waitmate::offsets::{delete, list, Position, set};
+
+    #[test]
+    fn test_parse_position() {
+        assert_eq!(Position::Earliest, "earliest".parse().unwrap());
+        assert_eq!(Position::Latest, "latest".parse().unwrap());
+        assert_eq!(Position::Time(1_600_000_000_000_000), "1600000000".parse().unwrap());
+        let key = "1600000000000000|67e55044-10b1-426f-9247-bb680e5fe0c8";
+        assert_eq!(Position::Key(key.as_bytes().to_vec()), key.parse().unwrap());
+        assert!("yesterday".parse::<Position>().is_err());
+        assert!("1|x".parse::<Position>().is_err());
+    }
+
+    #[test]
+    fn test_offsets() {
+        let event_log = EventLog::memory();
+        let source = EmptyNamed {};
+        let e1 = Event::new(&source, "a", "b", "c", Level::WARN);
+        std::thread::sleep(Duration::from_millis(2));
+        let e2 = Event::new(&source, "a", "b", "c", Level::WARN);
+        std::thread::sleep(Duration::from_millis(2));
+        let e3 = Event::new(&source, "a", "b", "c", Level::WARN);
+        event_log.add(&e1).unwrap();
+        event_log.add(&e2).unwrap();
+        event_log.add(&e3).unwrap();
+
+        let ids = |n: usize| event_log.build_cursor()
+            .named("markie")
+            .build()
+            .unwrap()
+            .take(n)
+            .map(|item| item.unwrap().1.id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![e1.id, e2.id], ids(2));
+        let offsets = list(&event_log).unwrap();
+        assert_eq!(1, offsets.len());
+        assert_eq!("markie", offsets[0].name);
+        assert_eq!(String::from_utf8(EventLog::create_key(&e2.time, &e2.id)).unwrap(), offsets[0].key);
+        assert_eq!(1, offsets[0].lag);
+
+        set(&event_log, "markie", &Position::Earliest).unwrap();
+        assert_eq!(3, list(&event_log).unwrap()[0].lag);
+        set(&event_log, "markie", &Position::Latest).unwrap();
+        assert_eq!(0, list(&event_log).unwrap()[0].lag);
+        set(&event_log, "markie", &Position::Time(e2.time)).unwrap();
+        assert_eq!(vec![e2.id, e3.id], ids(10));
+        set(&event_log, "markie", &Position::Key(EventLog::create_key(&e1.time, &e1.id))).unwrap();
+        assert_eq!(2, list(&event_log).unwrap()[0].lag);
+
+        delete(&event_log, "markie").unwrap();
+        assert!(list(&event_log).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_running_cursor_follows_set() {
+        let event_log = EventLog::memory();
+        let source = EmptyNamed {};
+        let e1 = Event::new(&source, "a", "b", "c", Level::WARN);
+        std::thread::sleep(Duration::from_millis(2));
+        let e2 = Event::new(&source, "a", "b", "c", Level::WARN);
+        event_log.add(&e1).unwrap();
+        event_log.add(&e2).unwrap();
+
+        let mut cursor = event_log.build_cursor()
+            .named("markie")
+            .build()
+            .unwrap();
+        assert_eq!(e1.id, cursor.next().unwrap().unwrap().1.id);
+        set(&event_log, "markie", &Position::Earliest).unwrap();
+        assert_eq!(e1.id, cursor.next().unwrap().unwrap().1.id);
+        assert_eq!(e2.id, cursor.next().unwrap().unwrap().1.id);
+        assert!(cursor.next().is_none());
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{BTreeMap, HashMap};
+
+use config::{Config, ConfigError, Value};
+use crossbeam::channel::Sender;
+use serde::Serialize;
+
+use crate::waitmate::api::{Event, EventBus, Level, Named, Notifier, Waiter};
+use crate::waitmate::error::{Error, Result};
+use crate::waitmate::filter::Filter;
+use crate::waitmate::net::{Relay, RELAY_FILTER, Server};
+use crate::waitmate::std::{StdinWaiter, StdoutNotifier};
+use crate::waitmate::thread::ChannelPolicy;
+
+/// Tells a running app to stop, to reload its config and reply with what changed, or to add
+/// the events announcing a change made outside it, e.g. an alert acknowledged through the API
+pub enum Control {
+    Stop,
+    Reload(Option<Sender<Result<ReloadSummary>>>),
+    Publish(Vec<Event>),
+}
+
+/// The components a reload stopped and started.  A reconfigured component is in both.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ReloadSummary {
+    pub stopped: Vec<String>,
+    pub started: Vec<String>,
+}
+
+/// A waiter or notifier declared in the config, e.g.
+/// `notifiers: {pager: {type: relay, upstream: "tcp://central:12345", filter: "level >= ERROR"}}`.
+/// Any notifier may also set a `route` such as `labels.env == "prod"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spec {
+    pub kind: String,
+    pub settings: HashMap<String, Value>,
+}
+impl Spec {
+    fn parse(name: &str, value: Value) -> Result<Spec> {
+        let mut settings = value.into_table()?;
+        let kind = settings.remove("type")
+            .ok_or_else(|| Error::Config(format!("{} has no type", name)))?
+            .into_str()?;
+        return Ok(Spec {
+            kind,
+            settings
+        });
+    }
+    pub fn get_str(&self, key: &str) -> Result<Option<String>> {
+        return match self.settings.get(key) {
+            Some(v) => Ok(Some(v.clone().into_str()?)),
+            None => Ok(None)
+        };
+    }
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>> {
+        return match self.settings.get(key) {
+            Some(v) => Ok(Some(v.clone().into_int()?)),
+            None => Ok(None)
+        };
+    }
+    /// A list setting, which may also be given as a single value
+    pub fn get_list(&self, key: &str) -> Result<Vec<String>> {
+        return match self.settings.get(key) {
+            Some(v) => match v.clone().into_array() {
+                Ok(values) => values.into_iter().map(|v| Ok(v.into_str()?)).collect(),
+                Err(_) => Ok(vec![v.clone().into_str()?])
+            },
+            None => Ok(Vec::new())
+        };
+    }
+    pub fn required(&self, name: &str, key: &str) -> Result<String> {
+        return self.get_str(key)?
+            .ok_or_else(|| Error::Config(format!("{} needs {}", name, key)));
+    }
+    /// The notifier's own `min_level`, if it sets one
+    pub fn min_level(&self) -> Result<Option<Level>> {
+        return match self.get_str("min_level")? {
+            Some(l) => l.parse().map(Some).map_err(Error::Config),
+            None => Ok(None)
+        };
+    }
+    /// The notifier's own `route`, a filter expression, if it sets one
+    pub fn route(&self) -> Result<Option<Filter>> {
+        return self.get_filter("route");
+    }
+    /// The events a relay forwards, its `filter` expression or `level >= WARN`
+    pub fn relay_filter(&self) -> Result<Filter> {
+        return match self.get_filter("filter")? {
+            Some(f) => Ok(f),
+            None => RELAY_FILTER.parse()
+        };
+    }
+    pub fn get_filter(&self, key: &str) -> Result<Option<Filter>> {
+        return match self.get_str(key)? {
+            Some(f) => f.parse().map(Some).map_err(|e: Error| Error::Config(e.to_string())),
+            None => Ok(None)
+        };
+    }
+    /// The waiter's channel, with its own `capacity` and `overflow` in place of `default`'s
+    pub fn channel(&self, default: ChannelPolicy) -> Result<ChannelPolicy> {
+        let capacity = match self.settings.get("capacity") {
+            Some(v) => v.clone().into_int()?,
+            None => default.capacity as i64
+        };
+        if capacity < 1 {
+            return Err(Error::Config(format!("capacity must be at least 1, not {}", capacity)));
+        }
+        return Ok(ChannelPolicy {
+            capacity: capacity as usize,
+            overflow: match self.get_str("overflow")? {
+                Some(o) => o.parse()?,
+                None => default.overflow
+            }
+        });
+    }
+}
+
+/// The waiters and notifiers declared under `waiters` and `notifiers`, by name
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Declared {
+    pub waiters: BTreeMap<String, Spec>,
+    pub notifiers: BTreeMap<String, Spec>,
+}
+impl Declared {
+    /// Read and check the declarations without opening anything
+    pub fn load(config: &Config) -> Result<Declared> {
+        let declared = Declared {
+            waiters: section(config, "waiters")?,
+            notifiers: section(config, "notifiers")?
+        };
+        for (name, spec) in &declared.waiters {
+            check_waiter(name, spec)?;
+        }
+        for (name, spec) in &declared.notifiers {
+            check_notifier(name, spec)?;
+        }
+        return Ok(declared);
+    }
+}
+
+/// The specs declared under `section` by name, each with a `type`
+pub fn section(config: &Config, section: &str) -> Result<BTreeMap<String, Spec>> {
+    let table = match config.get_table(section) {
+        Ok(t) => t,
+        Err(ConfigError::NotFound(_)) => return Ok(BTreeMap::new()),
+        Err(e) => return Err(Error::from(e))
+    };
+    let mut specs = BTreeMap::new();
+    for (name, value) in table {
+        let spec = Spec::parse(name.as_str(), value)?;
+        specs.insert(name, spec);
+    }
+    return Ok(specs);
+}
+
+/// The names to stop and to start so that what is `running` becomes what is `wanted`.  A
+/// name whose spec changed is stopped and started again.
+pub fn diff(running: &BTreeMap<String, Spec>, wanted: &BTreeMap<String, Spec>) -> (Vec<String>, Vec<String>) {
+    let stop = running.iter()
+        .filter(|(name, spec)| wanted.get(*name) != Some(spec))
+        .map(|(name, _)| name.clone())
+        .collect();
+    let start = wanted.iter()
+        .filter(|(name, spec)| running.get(*name) != Some(spec))
+        .map(|(name, _)| name.clone())
+        .collect();
+    return (stop, start);
+}
+
+fn check_waiter(name: &str, spec: &Spec) -> Result<()> {
+    spec.channel(ChannelPolicy::default())?;
+    return match spec.kind.as_str() {
+        "stdin" => StdinWaiter::with_pattern(spec.get_str("pattern")?.as_deref()).map(|_| ()),
+        "server" => spec.required(name, "listen").map(|_| ()),
+        kind => Err(Error::Config(format!("{} has unknown waiter type {}", name, kind)))
+    };
+}
+
+fn check_notifier(name: &str, spec: &Spec) -> Result<()> {
+    spec.min_level()?;
+    spec.route()?;
+    return match spec.kind.as_str() {
+        "stdout" => Ok(()),
+        "relay" => {
+            spec.required(name, "upstream")?;
+            spec.relay_filter().map(|_| ())
+        }
+        kind => Err(Error::Config(format!("{} has unknown notifier type {}", name, kind)))
+    };
+}
+
+/// Open the waiter `spec` declares, e.g. `{type: server, listen: "tcp://*:12346"}` or
+/// `{type: stdin, pattern: "ERROR (?P<host>\\w+)"}`
+pub fn build_waiter(name: &str, spec: &Spec, node: &str) -> Result<Box<dyn Waiter>> {
+    check_waiter(name, spec)?;
+    let waiter: Box<dyn Waiter> = match spec.kind.as_str() {
+        "stdin" => Box::new(StdinWaiter::with_pattern(spec.get_str("pattern")?.as_deref())?),
+        _ => Box::new(Server::new(spec.required(name, "listen")?.as_str())?.node(node))
+    };
+    return Ok(Box::new(DeclaredWaiter {
+        name: String::from(name),
+        waiter
+    }));
+}
+
+/// Open the notifier `spec` declares, e.g. `{type: relay, upstream: "tcp://central:12345"}`
+pub fn build_notifier(name: &str, spec: &Spec, node: &str) -> Result<Box<dyn Notifier>> {
+    check_notifier(name, spec)?;
+    let notifier: Box<dyn Notifier> = match spec.kind.as_str() {
+        "stdout" => Box::new(StdoutNotifier::new()),
+        _ => {
+            Box::new(Relay::new(spec.required(name, "upstream")?.as_str(), node, spec.relay_filter()?)?)
+        }
+    };
+    return Ok(Box::new(DeclaredNotifier {
+        name: String::from(name),
+        notifier
+    }));
+}
+
+/// A declared waiter goes by its declared name
+struct DeclaredWaiter {
+    name: String,
+    waiter: Box<dyn Waiter>
+}
+impl Named for DeclaredWaiter {
+    fn name(&self) -> &str {
+        return self.name.as_str();
+    }
+}
+impl Waiter for DeclaredWaiter {
+    fn wait(&self, bus: &dyn EventBus) -> Result<()> {
+        return self.waiter.wait(bus);
+    }
+}
+
+/// A declared notifier goes by its declared name, which also names its offset
+struct DeclaredNotifier {
+    name: String,
+    notifier: Box<dyn Notifier>
+}
+impl Named for DeclaredNotifier {
+    fn name(&self) -> &str {
+        return self.name.as_str();
+    }
+}
+impl Notifier for DeclaredNotifier {
+    fn notify(&self, event: Event, event_bus: &dyn EventBus) -> Result<()> {
+        return self.notifier.notify(event, event_bus);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, File, FileFormat};
+
+    use crate::waitmate::api::Level;
+    use crate::waitmate::pipeline::{build_notifier, Declared, diff};
+    use crate::waitmate::thread::{ChannelPolicy, Overflow};
+
+    fn declare(yaml: &str) -> Config {
+        let mut config = Config::new();
+        config.merge(File::from_str(yaml, FileFormat::Yaml)).unwrap();
+        return config;
+    }
+
+    #[test]
+    fn test_declared_diff() {
+        let old = Declared::load(&declare(r#"
+waiters:
+  bash: {type: stdin, pattern: "bash"}
+notifiers:
+  out: {type: stdout}
+  pager: {type: stdout, min_level: ERROR}
+"#)).unwrap();
+        let new = Declared::load(&declare(r#"
+waiters:
+  bash: {type: stdin, pattern: "bash"}
+notifiers:
+  pager: {type: stdout, min_level: WARN}
+  central: {type: relay, upstream: "tcp://127.0.0.1:1", filter: 'labels.env == "prod"'}
+"#)).unwrap();
+
+        assert_eq!(Some(Level::ERROR), old.notifiers["pager"].min_level().unwrap());
+        assert_eq!(None, old.notifiers["pager"].route().unwrap());
+        let routed = Declared::load(&declare("notifiers: {db: {type: stdout, route: 'category =~ \"db.*\"'}}"))
+            .unwrap();
+        assert_eq!("labels.env == \"prod\"", new.notifiers["central"].relay_filter().unwrap().to_string());
+        assert_eq!("level >= WARN", old.notifiers["out"].relay_filter().unwrap().to_string());
+        assert_eq!("category =~ \"db.*\"", routed.notifiers["db"].route().unwrap().unwrap().to_string());
+        assert_eq!((Vec::<String>::new(), Vec::<String>::new()), diff(&old.waiters, &new.waiters));
+        assert_eq!((vec![String::from("out"), String::from("pager")],
+                    vec![String::from("central"), String::from("pager")]),
+                   diff(&old.notifiers, &new.notifiers));
+        assert_eq!("pager", build_notifier("pager", &new.notifiers["pager"], "node").unwrap().name());
+        assert!(Declared::load(&Config::new()).unwrap().waiters.is_empty());
+
+        let replay = Declared::load(&declare("waiters: {replay: {type: stdin, capacity: 100, overflow: drop_oldest}}"))
+            .unwrap();
+        assert_eq!(ChannelPolicy { capacity: 100, overflow: Overflow::DropOldest },
+                   replay.waiters["replay"].channel(ChannelPolicy::default()).unwrap());
+        assert_eq!(ChannelPolicy::default(), old.waiters["bash"].channel(ChannelPolicy::default()).unwrap());
+    }
+
+    #[test]
+    fn test_rejected_declarations() {
+        assert!(Declared::load(&declare("waiters: {bash: {type: stdin, pattern: \"(\"}}")).is_err());
+        assert!(Declared::load(&declare("waiters: {bash: {pattern: x}}")).is_err());
+        assert!(Declared::load(&declare("waiters: {bash: {type: stdin, overflow: spill}}")).is_err());
+        assert!(Declared::load(&declare("waiters: {bash: {type: stdin, capacity: 0}}")).is_err());
+        assert!(Declared::load(&declare("notifiers: {pager: {type: pager}}")).is_err());
+        assert!(Declared::load(&declare("notifiers: {pager: {type: stdout, min_level: LOUD}}")).is_err());
+        assert!(Declared::load(&declare("notifiers: {central: {type: relay}}")).is_err());
+        assert!(Declared::load(&declare("notifiers: {central: {type: relay, upstream: x, filter: WARN}}")).is_err());
+        assert!(Declared::load(&declare("notifiers: {pager: {type: stdout, route: \"level >=\"}}")).is_err());
+    }
+}
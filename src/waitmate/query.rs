@@ -0,0 +1,180 @@
+use log::warn;
+use serde::Deserialize;
+
+use crate::waitmate::api::{Event, Level};
+use crate::waitmate::error::{Error, Result};
+use crate::waitmate::filter::Filter;
+use crate::waitmate::log::{CursorBuilder, EventLog};
+
+/// Filters accepted by the HTTP and CLI event queries.  `label` is a comma separated list
+/// of `key:value` pairs, or bare keys that only need to be present.  `min_level` accepts
+/// anything `Level` parses and `from`/`to` bound the event time in microseconds.  `filter`
+/// is an expression such as `level >= WARN && labels.env == "prod"`.  `last` keeps only the
+/// newest matching events.
+#[derive(Debug, Default, Deserialize)]
+pub struct EventQuery {
+    pub name: Option<String>,
+    pub category: Option<String>,
+    pub source: Option<String>,
+    pub label: Option<String>,
+    pub min_level: Option<Level>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub filter: Option<Filter>,
+    pub last: Option<usize>,
+}
+impl EventQuery {
+    pub fn labels(&self) -> Vec<(&str, Option<&str>)> {
+        return self.label.as_ref()
+            .map_or(Vec::new(), |l| {
+                l.split(',')
+                    .map(|p| p.trim())
+                    .filter(|p| !p.is_empty())
+                    .map(|p| match p.find(':') {
+                        Some(i) => (&p[..i], Some(&p[i + 1..])),
+                        None => (p, None)
+                    })
+                    .collect()
+            });
+    }
+    pub fn matches(&self, event: &Event) -> bool {
+        if self.name.as_ref().map_or(false, |n| *n != event.name)
+            || self.category.as_ref().map_or(false, |c| *c != event.category)
+            || self.source.as_ref().map_or(false, |s| *s != event.source)
+            || self.min_level.map_or(false, |l| event.level < l)
+            || self.from.map_or(false, |f| event.time < u128::from(f))
+            || self.to.map_or(false, |t| event.time >= u128::from(t))
+            || self.filter.as_ref().map_or(false, |f| !f.matches(event)) {
+            return false;
+        }
+        return self.labels()
+            .iter()
+            .all(|(k, v)| match (event.labels.get(*k), v) {
+                (Some(actual), Some(expected)) => actual == expected,
+                (Some(_), None) => true,
+                _ => false
+            });
+    }
+    /// A cursor over the query's time range
+    pub fn cursor<'a>(&self, event_log: &'a EventLog) -> CursorBuilder<'a> {
+        return event_log.build_cursor()
+            .between(self.from.map(u128::from), self.to.map(u128::from));
+    }
+    /// The matching events in time order, only the newest `last` of them when it is set
+    pub fn select(&self, event_log: &EventLog) -> Result<Vec<(String, Event)>> {
+        let cursor = match self.last {
+            Some(_) => self.cursor(event_log).reverse().build()?,
+            None => self.cursor(event_log).build()?
+        };
+        let mut events: Vec<(String, Event)> = Vec::new();
+        for item in cursor {
+            if self.last.map_or(false, |n| events.len() >= n) {
+                break;
+            }
+            match item {
+                Ok((key, event)) => {
+                    if self.matches(&event) {
+                        events.push((key, event));
+                    }
+                }
+                Err(Error::Decode(e)) => warn!("{}", e),
+                Err(e) => return Err(e)
+            }
+        }
+        if self.last.is_some() {
+            events.reverse();
+        }
+        return Ok(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::waitmate::api::{EmptyNamed, Event, Level};
+    use crate::waitmate::log::EventLog;
+    use crate::waitmate::query::EventQuery;
+
+    #[test]
+    fn test_label_query() {
+        let source = EmptyNamed {};
+        let e = Event::new(&source, "a", "b", "c", Level::WARN)
+            .label("env", "prod")
+            .label("host", "h1");
+
+        assert!(EventQuery::default().matches(&e));
+        let mut q = EventQuery::default();
+        q.label = Some(String::from("env:prod, host"));
+        assert!(q.matches(&e));
+        q.label = Some(String::from("env:dev"));
+        assert!(!q.matches(&e));
+        q.label = Some(String::from("service"));
+        assert!(!q.matches(&e));
+        q.label = None;
+        q.category = Some(String::from("c"));
+        assert!(q.matches(&e));
+        q.name = Some(String::from("x"));
+        assert!(!q.matches(&e));
+    }
+
+    #[test]
+    fn test_min_level_query() {
+        let source = EmptyNamed {};
+        let e = Event::new(&source, "a", "b", "c", Level::WARN);
+        let q: EventQuery = serde_json::from_str(r#"{"min_level": "warning"}"#).unwrap();
+        assert!(q.matches(&e));
+        let q: EventQuery = serde_json::from_str(r#"{"min_level": "err"}"#).unwrap();
+        assert!(!q.matches(&e));
+    }
+
+    #[test]
+    fn test_filter_query() {
+        let source = EmptyNamed {};
+        let e = Event::new(&source, "a", "b", "db.postgres", Level::WARN).label("env", "prod");
+        let q: EventQuery = serde_json::from_str(r#"{"filter": "category =~ \"db.*\" && labels.env == prod"}"#).unwrap();
+        assert!(q.matches(&e));
+        let q: EventQuery = serde_json::from_str(r#"{"filter": "level >= ERROR"}"#).unwrap();
+        assert!(!q.matches(&e));
+        assert!(serde_json::from_str::<EventQuery>(r#"{"filter": "level >="}"#).is_err());
+    }
+
+    #[test]
+    fn test_time_query() {
+        let source = EmptyNamed {};
+        let e = Event::new(&source, "a", "b", "c", Level::WARN);
+        let mut q = EventQuery::default();
+        q.from = Some(e.time as u64);
+        assert!(q.matches(&e));
+        q.to = Some(e.time as u64);
+        assert!(!q.matches(&e));
+        q.to = Some(e.time as u64 + 1);
+        assert!(q.matches(&e));
+    }
+
+    #[test]
+    fn test_select_last() {
+        let event_log = EventLog::memory();
+        let source = EmptyNamed {};
+        let mut events = Vec::new();
+        for level in &[Level::WARN, Level::INFO, Level::WARN, Level::WARN] {
+            let e = Event::new(&source, "a", "b", "c", *level);
+            event_log.add(&e).unwrap();
+            events.push(e);
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let ids = |q: &EventQuery| q.select(&event_log)
+            .unwrap()
+            .into_iter()
+            .map(|(_, e)| e.id)
+            .collect::<Vec<_>>();
+        let mut q = EventQuery::default();
+        q.last = Some(2);
+        assert_eq!(vec![events[2].id, events[3].id], ids(&q));
+        q.to = Some(events[3].time as u64);
+        q.min_level = Some(Level::WARN);
+        assert_eq!(vec![events[0].id, events[2].id], ids(&q));
+        q.last = None;
+        q.from = Some(events[1].time as u64);
+        assert_eq!(vec![events[2].id], ids(&q));
+    }
+}
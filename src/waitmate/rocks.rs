@@ -0,0 +1,207 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use log::info;
+use rocksdb::{BoundColumnFamily, DBRawIteratorWithThreadMode, DBWithThreadMode, MultiThreaded, Options, WriteBatch};
+use rocksdb::checkpoint::Checkpoint;
+
+use crate::waitmate::error::Result;
+use crate::waitmate::store::{KeyValue, Store};
+
+type DB = DBWithThreadMode<MultiThreaded>;
+
+/// Stores each table in its own RocksDB column family, created the first time the table is
+/// written so that components can keep state beside the log without declaring it here
+pub struct RocksStore {
+    db: DB,
+    path: String,
+    /// Whether the store is destroyed when it is dropped
+    temporary: bool,
+    /// Held while creating a column family so two writers don't both try
+    creating: Mutex<()>
+}
+impl RocksStore {
+    /// Open or create the store at `path`, which outlives it
+    pub fn new(path: &Path) -> Result<RocksStore> {
+        return RocksStore::open(path, false);
+    }
+    /// Create a store at `path` that is destroyed when it is dropped
+    pub fn temporary(path: &Path) -> Result<RocksStore> {
+        return RocksStore::open(path, true);
+    }
+    fn open(path: &Path, temporary: bool) -> Result<RocksStore> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let existed = path.exists();
+
+        // reopen every table the log already has, a new log has none
+        let tables = DB::list_cf(&opts, &path).unwrap_or_default();
+        let db = DB::open_cf(&opts, &path, tables)?;
+        if existed {
+            info!("Opened existing log {}", path.to_str().unwrap());
+        } else {
+            info!("Created new log {}", path.to_str().unwrap());
+        }
+
+        return Ok(RocksStore {
+            db,
+            path: String::from(path.to_str().unwrap()),
+            temporary,
+            creating: Mutex::new(())
+        });
+    }
+    /// The column family of `table`, if it has ever been written
+    fn cf(&self, table: &str) -> Option<Arc<BoundColumnFamily>> {
+        return self.db.cf_handle(table);
+    }
+    /// The column family of `table`, creating it if need be
+    fn cf_for_write(&self, table: &str) -> Result<Arc<BoundColumnFamily>> {
+        match self.cf(table) {
+            Some(cf) => return Ok(cf),
+            None => {}
+        }
+        let _guard = self.creating.lock().unwrap_or_else(|e| e.into_inner());
+        if self.cf(table).is_none() {
+            self.db.create_cf(table, &Options::default())?;
+            info!("Created table {} in log {}", table, self.path);
+        }
+        return Ok(self.cf(table).unwrap());
+    }
+    fn close(&self) {
+        if self.temporary {
+            let _ = DB::destroy(&Options::default(), &self.path);
+            info!("Destroyed temporary log {}", self.path);
+        } else {
+            info!("Closed log {}", self.path);
+        }
+    }
+}
+impl Store for RocksStore {
+    fn put(&self, table: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        return Ok(self.db.put_cf(&self.cf_for_write(table)?, key, value)?);
+    }
+    /// A single atomic `WriteBatch`
+    fn put_all(&self, entries: &[(&str, KeyValue)]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for (table, (key, value)) in entries {
+            batch.put_cf(&self.cf_for_write(table)?, key, value);
+        }
+        return Ok(self.db.write(batch)?);
+    }
+    fn get(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        return match self.cf(table) {
+            Some(cf) => Ok(self.db.get_cf(&cf, key)?),
+            None => Ok(None)
+        };
+    }
+    fn delete(&self, table: &str, key: &[u8]) -> Result<()> {
+        return match self.cf(table) {
+            Some(cf) => Ok(self.db.delete_cf(&cf, key)?),
+            None => Ok(())
+        };
+    }
+    fn scan(&self, table: &str, after: Option<&[u8]>, limit: usize) -> Result<Vec<KeyValue>> {
+        let cf = match self.cf(table) {
+            Some(cf) => cf,
+            None => return Ok(Vec::new())
+        };
+        let mut iter = self.db.raw_iterator_cf(&cf);
+        seek_after(&mut iter, after);
+        let mut entries = Vec::new();
+        while iter.valid() && entries.len() < limit {
+            entries.push((iter.key().unwrap().to_vec(), iter.value().unwrap().to_vec()));
+            iter.next();
+        }
+        iter.status()?;
+        return Ok(entries);
+    }
+    fn scan_reverse(&self, table: &str, before: Option<&[u8]>, limit: usize) -> Result<Vec<KeyValue>> {
+        let cf = match self.cf(table) {
+            Some(cf) => cf,
+            None => return Ok(Vec::new())
+        };
+        let mut iter = self.db.raw_iterator_cf(&cf);
+        match before {
+            Some(k) => {
+                iter.seek_for_prev(k);
+                if iter.valid() && iter.key().unwrap() == k {
+                    iter.prev();
+                }
+            }
+            None => iter.seek_to_last()
+        }
+        let mut entries = Vec::new();
+        while iter.valid() && entries.len() < limit {
+            entries.push((iter.key().unwrap().to_vec(), iter.value().unwrap().to_vec()));
+            iter.prev();
+        }
+        iter.status()?;
+        return Ok(entries);
+    }
+    /// Walks the keys, leaving the values where they are
+    fn count(&self, table: &str, after: Option<&[u8]>) -> Result<u64> {
+        let cf = match self.cf(table) {
+            Some(cf) => cf,
+            None => return Ok(0)
+        };
+        let mut iter = self.db.raw_iterator_cf(&cf);
+        seek_after(&mut iter, after);
+        let mut count = 0;
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+        iter.status()?;
+        return Ok(count);
+    }
+    /// A RocksDB checkpoint, which hard links the live SST files so it is cheap to take
+    fn backup(&self, path: &Path) -> Result<()> {
+        Checkpoint::new(&self.db)?.create_checkpoint(path)?;
+        info!("Backed up log {} to {}", self.path, path.display());
+        return Ok(());
+    }
+}
+
+/// Move `iter` to the first key after `after`, or the first key
+fn seek_after(iter: &mut DBRawIteratorWithThreadMode<DB>, after: Option<&[u8]>) {
+    match after {
+        Some(k) => {
+            iter.seek(k);
+            if iter.valid() && iter.key().unwrap() == k {
+                iter.next();
+            }
+        }
+        None => iter.seek_to_first()
+    }
+}
+
+impl Drop for RocksStore {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::waitmate::rocks::RocksStore;
+    use crate::waitmate::store::Store;
+    use crate::waitmate::store::tests::check_store;
+
+    #[test]
+    fn test_rocks_store() {
+        let dir = tempdir().unwrap().into_path().join("t.rdb");
+        check_store(&RocksStore::new(dir.as_path()).unwrap());
+    }
+
+    #[test]
+    fn test_temporary_store() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("t.rdb");
+        let store = RocksStore::temporary(path.as_path()).unwrap();
+        store.put("log", b"a", b"1").unwrap();
+        drop(store);
+        assert_eq!(None, RocksStore::new(path.as_path()).unwrap().get("log", b"a").unwrap());
+    }
+}
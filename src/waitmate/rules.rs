@@ -0,0 +1,864 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use config::Config;
+use log::warn;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::waitmate::api::{Event, Level, now};
+use crate::waitmate::error::{Error, Result};
+use crate::waitmate::filter::{Field, Filter};
+use crate::waitmate::log::EventLog;
+use crate::waitmate::pipeline::{section, Spec};
+use crate::waitmate::schedule::Schedule;
+
+/// Labels every event a rule derives with the rule's name.  Rules never see such events.
+pub const RULE: &'static str = "rule";
+/// The category of derived events
+const CATEGORY: &'static str = "rule";
+/// The table beside the log where rules keep what must survive a restart
+const CF: &'static str = "rules";
+
+/// Derives events from the events on their way into the log
+pub trait Rule: Send {
+    /// Look at an event, returning any events it derives
+    fn observe(&mut self, event: &Event) -> Result<Vec<Event>>;
+    /// Let time pass, returning the events for windows and deadlines that ended by `now`
+    fn tick(&mut self, now: u128) -> Result<Vec<Event>>;
+    /// How far back the rule looks, when it should be primed from the log on start
+    fn history(&self) -> Option<Duration> {
+        return None;
+    }
+}
+
+/// Fill `{placeholders}` from `vars`, then from the fields of `event`, e.g. `{labels.host}`.
+/// Unknown placeholders are left as they are.
+pub fn render(template: &str, vars: &[(&str, String)], event: Option<&Event>) -> String {
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let close = match rest[open..].find('}') {
+            Some(c) => open + c,
+            None => break
+        };
+        text.push_str(&rest[..open]);
+        let key = &rest[open + 1..close];
+        let value = vars.iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.clone())
+            .or_else(|| event.and_then(|e| Field::parse(key).ok().and_then(|f| f.text(e))));
+        match value {
+            Some(v) => text.push_str(v.as_str()),
+            None => text.push_str(&rest[open..=close])
+        }
+        rest = &rest[close + 1..];
+    }
+    text.push_str(rest);
+    return text;
+}
+
+/// What a rule publishes: the templates for its name and description and its level
+#[derive(Debug, Clone)]
+struct Derived {
+    name: String,
+    description: String,
+    level: Level
+}
+impl Derived {
+    fn parse(spec: &Spec, prefix: &str, name: &str, description: &str, level: Level) -> Result<Derived> {
+        let key = |k: &str| format!("{}{}", prefix, k);
+        return Ok(Derived {
+            name: spec.get_str(key("name").as_str())?.unwrap_or(String::from(name)),
+            description: spec.get_str(key("description").as_str())?.unwrap_or(String::from(description)),
+            level: match spec.get_str(key("level").as_str())? {
+                Some(l) => l.parse().map_err(Error::Config)?,
+                None => level
+            }
+        });
+    }
+    fn event(&self, rule: &str, state: &str, vars: &[(&str, String)], event: Option<&Event>) -> Event {
+        let mut derived = Event::builder(render(self.name.as_str(), vars, event).as_str())
+            .description(render(self.description.as_str(), vars, event).as_str())
+            .category(CATEGORY)
+            .level(self.level)
+            .label(RULE, rule)
+            .label("state", state)
+            .build();
+        derived.source = String::from(rule);
+        return derived;
+    }
+}
+
+/// The matches of one group within the window
+#[derive(Default)]
+struct Matches {
+    times: VecDeque<u128>,
+    firing: bool,
+    last: Option<Event>
+}
+
+/// Fires when `count` events matching `filter` arrive within `window`, and recovers once
+/// fewer than `recover_below` remain in it.  Events are counted separately for each value
+/// of the `group_by` fields, e.g.
+/// `{type: threshold, filter: 'description =~ ".*connection refused.*"', count: 50, window_secs: 60}`
+pub struct Threshold {
+    rule: String,
+    filter: Option<Filter>,
+    count: usize,
+    recover_below: usize,
+    window: Duration,
+    group_by: Vec<Field>,
+    fire: Derived,
+    recover: Derived,
+    groups: HashMap<String, Matches>
+}
+impl Threshold {
+    pub fn new(rule: &str, spec: &Spec) -> Result<Threshold> {
+        let positive = |key: &str| -> Result<u64> {
+            return match spec.get_int(key)? {
+                Some(n) if n > 0 => Ok(n as u64),
+                _ => Err(Error::Config(format!("{} needs a {} of at least 1", rule, key)))
+            };
+        };
+        let count = positive("count")? as usize;
+        let recover_below = match spec.get_int("recover_below")? {
+            Some(n) if n > 0 && n as usize <= count => n as usize,
+            Some(_) => return Err(Error::Config(format!("{} needs a recover_below from 1 to count", rule))),
+            None => count
+        };
+        return Ok(Threshold {
+            rule: String::from(rule),
+            filter: spec.get_filter("filter")?,
+            count,
+            recover_below,
+            window: Duration::from_secs(positive("window_secs")?),
+            group_by: spec.get_list("group_by")?.iter()
+                .map(|f| Field::parse(f.as_str()).map_err(|e| Error::Config(e.to_string())))
+                .collect::<Result<Vec<_>>>()?,
+            fire: Derived::parse(spec, "", "{rule}", "{count} matching events in {window_secs}s", Level::WARN)?,
+            recover: Derived::parse(spec, "recovery_", "{rule} recovered",
+                                    "{count} matching events in the last {window_secs}s", Level::INFO)?,
+            groups: HashMap::new()
+        });
+    }
+    fn derive(&self, derived: &Derived, state: &str, group: &str, matches: &Matches) -> Event {
+        let vars = [("rule", self.rule.clone()),
+                    ("count", matches.times.len().to_string()),
+                    ("window_secs", self.window.as_secs().to_string()),
+                    ("group", String::from(group))];
+        let mut event = derived.event(self.rule.as_str(), state, &vars, matches.last.as_ref());
+        if !group.is_empty() {
+            event.labels.insert(String::from("group"), String::from(group));
+        }
+        return event;
+    }
+    /// Forget the matches that slid out of the window by `now`
+    fn slide(&mut self, now: u128) {
+        let start = now.saturating_sub(self.window.as_micros());
+        for matches in self.groups.values_mut() {
+            while matches.times.front().map_or(false, |t| *t < start) {
+                matches.times.pop_front();
+            }
+        }
+    }
+}
+impl Rule for Threshold {
+    fn observe(&mut self, event: &Event) -> Result<Vec<Event>> {
+        if !self.filter.as_ref().map_or(true, |f| f.matches(event)) {
+            return Ok(Vec::new());
+        }
+        let group = self.group_by.iter()
+            .map(|f| f.text(event).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("|");
+        self.slide(event.time);
+        let count = self.count;
+        let matches = self.groups.entry(group.clone()).or_default();
+        matches.times.push_back(event.time);
+        matches.last = Some(event.clone());
+        if matches.firing || matches.times.len() < count {
+            return Ok(Vec::new());
+        }
+        matches.firing = true;
+        let matches = &self.groups[&group];
+        return Ok(vec![self.derive(&self.fire, "firing", group.as_str(), matches)]);
+    }
+    fn tick(&mut self, now: u128) -> Result<Vec<Event>> {
+        self.slide(now);
+        let mut derived = Vec::new();
+        let recovered = self.groups.iter()
+            .filter(|(_, m)| m.firing && m.times.len() < self.recover_below)
+            .map(|(g, _)| g.clone())
+            .collect::<Vec<_>>();
+        for group in recovered {
+            derived.push(self.derive(&self.recover, "recovered", group.as_str(), &self.groups[&group]));
+            match self.groups.get_mut(&group) {
+                Some(m) => m.firing = false,
+                None => {}
+            }
+        }
+        self.groups.retain(|_, m| m.firing || !m.times.is_empty());
+        return Ok(derived);
+    }
+    fn history(&self) -> Option<Duration> {
+        return Some(self.window);
+    }
+}
+
+/// When an absence rule expects its event
+#[derive(Debug, Clone, PartialEq)]
+enum Expect {
+    /// Within this long of the last one
+    Every(Duration),
+    /// Before each time on the schedule
+    On(Schedule)
+}
+impl Display for Expect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        return match self {
+            Expect::Every(interval) => write!(f, "every {}s", interval.as_secs()),
+            Expect::On(schedule) => write!(f, "by {}", schedule)
+        };
+    }
+}
+
+/// The next deadline of an absence rule and whether it was met, kept in the `rules` table
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+struct Deadline {
+    expect: String,
+    deadline: u128,
+    seen: bool,
+    missed: u64
+}
+
+/// Fires when no event matching `filter` arrives `interval_secs` after the last one, or
+/// before each time on the cron `schedule`, allowing `grace_secs` for stragglers, e.g.
+/// `{type: absence, filter: 'name == "backup complete"', schedule: "0 4 * * *", grace_secs: 1800}`.
+/// Recovers with the next match.  Deadlines survive restarts.
+pub struct Absence {
+    rule: String,
+    event_log: Arc<EventLog>,
+    filter: Option<Filter>,
+    expect: Expect,
+    grace: Duration,
+    fire: Derived,
+    recover: Derived,
+    state: Deadline
+}
+impl Absence {
+    pub fn new(rule: &str, spec: &Spec, event_log: Arc<EventLog>, now: u128) -> Result<Absence> {
+        let expect = match (spec.get_int("interval_secs")?, spec.get_str("schedule")?) {
+            (Some(n), None) if n > 0 => Expect::Every(Duration::from_secs(n as u64)),
+            (None, Some(s)) => Expect::On(s.parse().map_err(|e: Error| Error::Config(e.to_string()))?),
+            _ => return Err(Error::Config(format!("{} needs either an interval_secs of at least 1 or a schedule", rule)))
+        };
+        let grace = match spec.get_int("grace_secs")? {
+            Some(n) if n >= 0 => Duration::from_secs(n as u64),
+            Some(_) => return Err(Error::Config(format!("{} has a negative grace_secs", rule))),
+            None => Duration::from_secs(0)
+        };
+        let stored: Option<Deadline> = event_log.get_entry(CF, rule.as_bytes())?
+            .and_then(|v| serde_json::from_slice(&v).ok())
+            .filter(|d: &Deadline| d.expect == expect.to_string());
+        let state = match stored {
+            Some(d) => d,
+            None => Deadline {
+                expect: expect.to_string(),
+                deadline: Absence::next(&expect, now)
+                    .ok_or(Error::Config(format!("{} has a schedule that never comes round", rule)))?,
+                seen: false,
+                missed: 0
+            }
+        };
+        return Ok(Absence {
+            rule: String::from(rule),
+            event_log,
+            filter: spec.get_filter("filter")?,
+            expect,
+            grace,
+            fire: Derived::parse(spec, "", "{rule} missing", "No matching event {expected}", Level::ERROR)?,
+            recover: Derived::parse(spec, "recovery_", "{rule} recovered",
+                                    "Matched again after {missed} missed deadlines", Level::INFO)?,
+            state
+        });
+    }
+    /// The deadline after `time`
+    fn next(expect: &Expect, time: u128) -> Option<u128> {
+        return match expect {
+            Expect::Every(interval) => Some(time + interval.as_micros()),
+            Expect::On(schedule) => schedule.next_after(time)
+        };
+    }
+    fn save(&self) -> Result<()> {
+        return self.event_log.put_entry(CF, self.rule.as_bytes(), &serde_json::to_vec(&self.state)?);
+    }
+    fn derive(&self, derived: &Derived, state: &str, event: Option<&Event>) -> Event {
+        let vars = [("rule", self.rule.clone()),
+                    ("expected", self.expect.to_string()),
+                    ("missed", self.state.missed.to_string())];
+        return derived.event(self.rule.as_str(), state, &vars, event);
+    }
+}
+impl Rule for Absence {
+    fn observe(&mut self, event: &Event) -> Result<Vec<Event>> {
+        if !self.filter.as_ref().map_or(true, |f| f.matches(event)) {
+            return Ok(Vec::new());
+        }
+        let mut derived = Vec::new();
+        if self.state.missed > 0 {
+            derived.push(self.derive(&self.recover, "recovered", Some(event)));
+            self.state.missed = 0;
+        }
+        match self.expect {
+            Expect::Every(interval) => self.state.deadline = event.time + interval.as_micros(),
+            Expect::On(_) => self.state.seen = true
+        }
+        self.save()?;
+        return Ok(derived);
+    }
+    fn tick(&mut self, now: u128) -> Result<Vec<Event>> {
+        if now < self.state.deadline.saturating_add(self.grace.as_micros()) {
+            return Ok(Vec::new());
+        }
+        let mut derived = Vec::new();
+        if !self.state.seen {
+            self.state.missed += 1;
+            derived.push(self.derive(&self.fire, "firing", None));
+        }
+        self.state.seen = false;
+        // a schedule that stops coming round is only checked again after a reload
+        self.state.deadline = Absence::next(&self.expect, now).unwrap_or(u128::MAX);
+        self.save()?;
+        return Ok(derived);
+    }
+}
+
+/// A start still waiting for its end, kept in the `rules` table
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+struct Pending {
+    time: u128,
+    id: Uuid,
+    deadline: u128
+}
+
+/// Pairs an event matching `start` with the next matching `end` that has the same key,
+/// publishing how long it took, or publishes a timeout when no end arrives within
+/// `timeout_secs`.  The key is the text of the `key` field, narrowed to the first group of
+/// `key_regex` when there is one, e.g.
+/// `{type: correlation, start: 'name == "job started"', end: 'name == "job finished"', key: labels.job_id, timeout_secs: 1800}`.
+/// `emit` picks `matched`, `timeout` or both.  Pending starts survive restarts.
+pub struct Correlation {
+    rule: String,
+    event_log: Arc<EventLog>,
+    start: Filter,
+    end: Filter,
+    key: Field,
+    key_regex: Option<Regex>,
+    timeout: Duration,
+    matched: Option<Derived>,
+    timed_out: Option<Derived>,
+    pending: BTreeMap<String, Pending>
+}
+impl Correlation {
+    pub fn new(rule: &str, spec: &Spec, event_log: Arc<EventLog>) -> Result<Correlation> {
+        let filter = |key: &str| spec.get_filter(key)?
+            .ok_or(Error::Config(format!("{} needs a {} filter", rule, key)));
+        let key_regex = match spec.get_str("key_regex")? {
+            Some(r) => Some(Regex::new(r.as_str()).map_err(|e| Error::Config(format!("{} has a bad key_regex: {}", rule, e)))?),
+            None => None
+        };
+        let timeout = match spec.get_int("timeout_secs")? {
+            Some(n) if n > 0 => Duration::from_secs(n as u64),
+            _ => return Err(Error::Config(format!("{} needs a timeout_secs of at least 1", rule)))
+        };
+        let mut emit = spec.get_list("emit")?;
+        if emit.is_empty() {
+            emit = vec![String::from("matched"), String::from("timeout")];
+        }
+        match emit.iter().find(|e| *e != "matched" && *e != "timeout") {
+            Some(e) => return Err(Error::Config(format!("{} cannot emit {}", rule, e))),
+            None => {}
+        }
+        let emits = |what: &str| emit.iter().any(|e| e == what);
+        let mut pending = BTreeMap::new();
+        let prefix = format!("{}/", rule);
+        for (key, value) in event_log.entries(CF)? {
+            let key = String::from_utf8_lossy(&key).into_owned();
+            match (key.strip_prefix(prefix.as_str()), serde_json::from_slice(&value)) {
+                (Some(k), Ok(p)) => { pending.insert(String::from(k), p); }
+                _ => {}
+            }
+        }
+        return Ok(Correlation {
+            rule: String::from(rule),
+            event_log,
+            start: filter("start")?,
+            end: filter("end")?,
+            key: Field::parse(spec.required(rule, "key")?.as_str()).map_err(|e| Error::Config(e.to_string()))?,
+            key_regex,
+            timeout,
+            matched: match emits("matched") {
+                true => Some(Derived::parse(spec, "matched_", "{rule}", "{key} ended after {duration_secs}s", Level::INFO)?),
+                false => None
+            },
+            timed_out: match emits("timeout") {
+                true => Some(Derived::parse(spec, "timeout_", "{rule} timed out",
+                                            "{key} did not end within {timeout_secs}s", Level::WARN)?),
+                false => None
+            },
+            pending
+        });
+    }
+    /// The key of `event`, when it has one
+    fn key(&self, event: &Event) -> Option<String> {
+        let text = self.key.text(event)?;
+        let key = match self.key_regex.as_ref() {
+            Some(r) => String::from(r.captures(text.as_str())?.get(1)?.as_str()),
+            None => text
+        };
+        return if key.is_empty() { None } else { Some(key) };
+    }
+    fn entry(&self, key: &str) -> Vec<u8> {
+        return format!("{}/{}", self.rule, key).into_bytes();
+    }
+    fn derive(&self, derived: &Derived, state: &str, key: &str, pending: &Pending, duration: Option<u128>,
+              event: Option<&Event>) -> Event {
+        let mut vars = vec![("rule", self.rule.clone()),
+                            ("key", String::from(key)),
+                            ("timeout_secs", self.timeout.as_secs().to_string())];
+        match duration {
+            Some(d) => vars.push(("duration_secs", (d / 1_000_000).to_string())),
+            None => {}
+        }
+        let mut derived = derived.event(self.rule.as_str(), state, &vars, event);
+        derived.parent_id = Some(pending.id);
+        derived.labels.insert(String::from("key"), String::from(key));
+        match duration {
+            Some(d) => { derived.labels.insert(String::from("duration_ms"), (d / 1000).to_string()); }
+            None => {}
+        }
+        return derived;
+    }
+}
+impl Rule for Correlation {
+    fn observe(&mut self, event: &Event) -> Result<Vec<Event>> {
+        let key = match self.key(event) {
+            Some(k) => k,
+            None => return Ok(Vec::new())
+        };
+        if self.pending.contains_key(&key) && self.end.matches(event) {
+            let pending = self.pending.remove(&key).unwrap();
+            self.event_log.delete_entry(CF, &self.entry(key.as_str()))?;
+            let duration = event.time.saturating_sub(pending.time);
+            return Ok(self.matched.as_ref()
+                .map(|d| self.derive(d, "matched", key.as_str(), &pending, Some(duration), Some(event)))
+                .into_iter()
+                .collect());
+        }
+        if !self.pending.contains_key(&key) && self.start.matches(event) {
+            let pending = Pending {
+                time: event.time,
+                id: event.id,
+                deadline: event.time + self.timeout.as_micros()
+            };
+            self.event_log.put_entry(CF, &self.entry(key.as_str()), &serde_json::to_vec(&pending)?)?;
+            self.pending.insert(key, pending);
+        }
+        return Ok(Vec::new());
+    }
+    fn tick(&mut self, now: u128) -> Result<Vec<Event>> {
+        let expired = self.pending.iter()
+            .filter(|(_, p)| p.deadline <= now)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        let mut derived = Vec::new();
+        for key in expired {
+            let pending = self.pending.remove(&key).unwrap();
+            self.event_log.delete_entry(CF, &self.entry(key.as_str()))?;
+            match self.timed_out.as_ref() {
+                Some(d) => {
+                    let start = self.event_log.get(&pending.time, &pending.id).unwrap_or(None);
+                    derived.push(self.derive(d, "timeout", key.as_str(), &pending, None, start.as_ref()));
+                }
+                None => {}
+            }
+        }
+        return Ok(derived);
+    }
+}
+
+/// Build the rule `spec` declares
+fn build(name: &str, spec: &Spec, event_log: &Arc<EventLog>) -> Result<Box<dyn Rule>> {
+    return match spec.kind.as_str() {
+        "threshold" => Ok(Box::new(Threshold::new(name, spec)?)),
+        "absence" => Ok(Box::new(Absence::new(name, spec, event_log.clone(), now())?)),
+        "correlation" => Ok(Box::new(Correlation::new(name, spec, event_log.clone())?)),
+        kind => Err(Error::Config(format!("{} has unknown rule type {}", name, kind)))
+    };
+}
+
+/// The rules declared under `rules`, by name
+#[derive(Default)]
+pub struct Rules {
+    rules: BTreeMap<String, (Spec, Box<dyn Rule>)>
+}
+impl Rules {
+    /// Build the declared rules without priming them.  Rules that keep state beside the
+    /// log pick up where they left off.
+    pub fn load(config: &Config, event_log: &Arc<EventLog>) -> Result<Rules> {
+        let mut rules = BTreeMap::new();
+        for (name, spec) in section(config, "rules")? {
+            // the state of a rule is kept under `<rule>` or `<rule>/<key>`
+            if name.contains('/') {
+                return Err(Error::Config(format!("Rule name {} may not contain /", name)));
+            }
+            let rule = build(name.as_str(), &spec, event_log)?;
+            rules.insert(name, (spec, rule));
+        }
+        return Ok(Rules {
+            rules
+        });
+    }
+    /// Take the rules of `loaded`, keeping the running ones whose declaration did not change
+    /// and priming the rest from the log up to `now`.  What rules no longer declared kept
+    /// beside the log is deleted.
+    pub fn replace(&mut self, loaded: Rules, event_log: &EventLog, now: u128) {
+        let mut running = std::mem::take(&mut self.rules);
+        for (name, (spec, rule)) in loaded.rules {
+            let rule = match running.remove(&name) {
+                Some((old_spec, old)) if old_spec == spec => old,
+                _ => Rules::prime(name.as_str(), rule, event_log, now)
+            };
+            self.rules.insert(name, (spec, rule));
+        }
+        match self.retire(event_log) {
+            Ok(()) => {}
+            Err(e) => warn!("Could not delete the state of retired rules: {}", e)
+        }
+    }
+    fn retire(&self, event_log: &EventLog) -> Result<()> {
+        for (key, _) in event_log.entries(CF)? {
+            let entry = String::from_utf8_lossy(&key).into_owned();
+            // correlation rules keep an entry per key under `<rule>/`
+            if !self.rules.contains_key(entry.split('/').next().unwrap_or_default()) {
+                event_log.delete_entry(CF, &key)?;
+            }
+        }
+        return Ok(());
+    }
+    /// Replay what `rule` would have seen so that a restart does not fire again
+    fn prime(name: &str, mut rule: Box<dyn Rule>, event_log: &EventLog, now: u128) -> Box<dyn Rule> {
+        let history = match rule.history() {
+            Some(h) => h,
+            None => return rule
+        };
+        let cursor = match event_log.build_cursor()
+            .between(Some(now.saturating_sub(history.as_micros())), Some(now))
+            .build() {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Could not prime rule {}: {}", name, e);
+                return rule;
+            }
+        };
+        for item in cursor {
+            match item {
+                Ok((_, event)) if !event.labels.contains_key(RULE) => { rule.observe(&event).unwrap_or_default(); }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("Could not prime rule {}: {}", name, e);
+                    break;
+                }
+            }
+        }
+        return rule;
+    }
+    pub fn observe(&mut self, event: &Event) -> Vec<Event> {
+        if event.labels.contains_key(RULE) {
+            return Vec::new();
+        }
+        let mut derived = Vec::new();
+        for (name, (_, rule)) in self.rules.iter_mut() {
+            match rule.observe(event) {
+                Ok(events) => derived.extend(events),
+                Err(e) => warn!("Rule {} failed: {}", name, e)
+            }
+        }
+        return derived;
+    }
+    pub fn tick(&mut self, now: u128) -> Vec<Event> {
+        let mut derived = Vec::new();
+        for (name, (_, rule)) in self.rules.iter_mut() {
+            match rule.tick(now) {
+                Ok(events) => derived.extend(events),
+                Err(e) => warn!("Rule {} failed: {}", name, e)
+            }
+        }
+        return derived;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use config::{Config, File, FileFormat};
+    use tempfile::tempdir;
+
+    use crate::waitmate::api::{EmptyNamed, Event, Level, now};
+    use crate::waitmate::log::EventLog;
+    use crate::waitmate::rules::{CF, render, RULE, Rules};
+
+    fn load(yaml: &str, event_log: &Arc<EventLog>) -> crate::waitmate::error::Result<Rules> {
+        let mut config = Config::new();
+        config.merge(File::from_str(yaml, FileFormat::Yaml)).unwrap();
+        return Rules::load(&config, event_log);
+    }
+
+    fn rules(yaml: &str) -> Rules {
+        return load(yaml, &Arc::new(EventLog::memory())).unwrap();
+    }
+
+    fn refused(host: &str, time: u128) -> Event {
+        let source = EmptyNamed {};
+        let mut event = Event::new(&source, "Log line", "connect: connection refused", "net", Level::INFO)
+            .label("host", host);
+        event.time = time;
+        return event;
+    }
+
+    #[test]
+    fn test_render() {
+        let e = refused("web1", 0);
+        assert_eq!("3 on web1 {nope}", render("{count} on {labels.host} {nope}", &[("count", String::from("3"))], Some(&e)));
+        assert_eq!("{unclosed", render("{unclosed", &[], None));
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mut loaded = rules(r#"
+rules:
+  refused:
+    type: threshold
+    filter: 'description =~ ".*connection refused.*"'
+    count: 3
+    window_secs: 60
+    group_by: labels.host
+    name: "Connections refused on {labels.host}"
+    level: ERROR
+"#);
+        let second = 1_000_000;
+        let mut fired = Vec::new();
+        for i in 0..4 {
+            fired.extend(loaded.observe(&refused("web1", i * second)));
+        }
+        fired.extend(loaded.observe(&refused("web2", 5 * second)));
+        assert_eq!(1, fired.len());
+        assert_eq!("Connections refused on web1", fired[0].name);
+        assert_eq!("3 matching events in 60s", fired[0].description);
+        assert_eq!(Level::ERROR, fired[0].level);
+        assert_eq!(Some(&String::from("refused")), fired[0].labels.get(RULE));
+        assert_eq!(Some(&String::from("web1")), fired[0].labels.get("group"));
+        assert!(loaded.observe(&fired[0]).is_empty());
+
+        assert!(loaded.tick(61 * second).is_empty());
+        let recovered = loaded.tick(63 * second);
+        assert_eq!(1, recovered.len());
+        assert_eq!("refused recovered", recovered[0].name);
+        assert_eq!(Some(&String::from("recovered")), recovered[0].labels.get("state"));
+        assert_eq!(Level::INFO, recovered[0].level);
+        assert!(loaded.tick(200 * second).is_empty());
+    }
+
+    #[test]
+    fn test_threshold_primed_from_log() {
+        let yaml = "rules: {refused: {type: threshold, count: 2, window_secs: 60}}";
+        let event_log = EventLog::memory();
+        let now = now();
+        event_log.add(&refused("web1", now - 2_000_000)).unwrap();
+        event_log.add(&refused("web1", now - 1_000_000)).unwrap();
+        let mut running = Rules::default();
+        running.replace(rules(yaml), &event_log, now);
+        // already over the threshold before the restart, so it does not fire again
+        assert!(running.observe(&refused("web1", now)).is_empty());
+        running.replace(rules(yaml), &event_log, now);
+        assert!(running.observe(&refused("web1", now)).is_empty());
+        running.replace(rules("rules: {refused: {type: threshold, count: 3, window_secs: 60}}"), &event_log, now);
+        assert_eq!(1, running.observe(&refused("web1", now)).len());
+    }
+
+    #[test]
+    fn test_absence() {
+        let yaml = r#"
+rules:
+  heartbeat:
+    type: absence
+    filter: 'name == "Heartbeat"'
+    interval_secs: 60
+    grace_secs: 5
+"#;
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("t.rdb");
+        let event_log = Arc::new(EventLog::new(path.as_path()).unwrap());
+        let second = 1_000_000;
+        let start = now();
+        let mut running = Rules::default();
+        running.replace(load(yaml, &event_log).unwrap(), &event_log, start);
+        let mut heartbeat = refused("web1", start + 30 * second);
+        heartbeat.name = String::from("Heartbeat");
+        assert!(running.observe(&heartbeat).is_empty());
+        assert!(running.observe(&refused("web1", start + 80 * second)).is_empty());
+        assert!(running.tick(start + 92 * second).is_empty());
+
+        let missing = running.tick(start + 96 * second);
+        assert_eq!(1, missing.len());
+        assert_eq!("heartbeat missing", missing[0].name);
+        assert_eq!("No matching event every 60s", missing[0].description);
+        assert_eq!(Level::ERROR, missing[0].level);
+        assert!(running.tick(start + 97 * second).is_empty());
+
+        // the deadline outlives the rules that set it and the process that ran them
+        drop(running);
+        drop(event_log);
+        let event_log = Arc::new(EventLog::new(path.as_path()).unwrap());
+        let mut restarted = Rules::default();
+        restarted.replace(load(yaml, &event_log).unwrap(), &event_log, start);
+        assert_eq!(1, restarted.tick(start + 161 * second).len());
+        heartbeat.time = start + 170 * second;
+        let recovered = restarted.observe(&heartbeat);
+        assert_eq!(1, recovered.len());
+        assert_eq!("Matched again after 2 missed deadlines", recovered[0].description);
+        assert_eq!(Level::INFO, recovered[0].level);
+        assert!(restarted.tick(start + 200 * second).is_empty());
+
+        restarted.replace(Rules::default(), &event_log, start);
+        assert!(event_log.entries(CF).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_absence_schedule() {
+        let event_log = Arc::new(EventLog::memory());
+        let mut hourly = load("rules: {backup: {type: absence, schedule: '@hourly', level: CRITICAL}}", &event_log).unwrap();
+        let hour = 3_600_000_000;
+        let deadline = (now() / hour + 1) * hour;
+        assert!(hourly.tick(deadline - 1).is_empty());
+        hourly.observe(&refused("db1", deadline - 10));
+        assert!(hourly.tick(deadline).is_empty());
+        let missing = hourly.tick(deadline + hour);
+        assert_eq!(1, missing.len());
+        assert_eq!("No matching event by @hourly", missing[0].description);
+        assert_eq!(Level::CRITICAL, missing[0].level);
+    }
+
+    #[test]
+    fn test_correlation() {
+        let yaml = r#"
+rules:
+  jobs:
+    type: correlation
+    start: 'name == "Job started"'
+    end: 'name == "Job finished"'
+    key: labels.job_id
+    timeout_secs: 1800
+    timeout_level: ERROR
+"#;
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("t.rdb");
+        let event_log = Arc::new(EventLog::new(path.as_path()).unwrap());
+        let second = 1_000_000;
+        let start = now();
+        let job = |name: &str, id: &str, time: u128| {
+            let mut event = refused("batch1", start + time * second).label("job_id", id);
+            event.name = String::from(name);
+            return event;
+        };
+        let mut running = load(yaml, &event_log).unwrap();
+        let nightly = job("Job started", "nightly", 0);
+        event_log.add(&nightly).unwrap();
+        assert!(running.observe(&nightly).is_empty());
+        assert!(running.observe(&job("Job started", "nightly", 10)).is_empty());
+        assert!(running.observe(&job("Job started", "hourly", 20)).is_empty());
+        assert!(running.observe(&job("Job finished", "weekly", 30)).is_empty());
+        let finished = running.observe(&job("Job finished", "hourly", 95));
+        assert_eq!(1, finished.len());
+        assert_eq!("hourly ended after 75s", finished[0].description);
+        assert_eq!(Some(&String::from("75000")), finished[0].labels.get("duration_ms"));
+        assert_eq!(Level::INFO, finished[0].level);
+
+        // the pending start outlives the rules that saw it and the process that ran them
+        drop(running);
+        drop(event_log);
+        let event_log = Arc::new(EventLog::new(path.as_path()).unwrap());
+        let mut restarted = load(yaml, &event_log).unwrap();
+        assert!(restarted.tick(start + 1799 * second).is_empty());
+        let timed_out = restarted.tick(start + 1800 * second);
+        assert_eq!(1, timed_out.len());
+        assert_eq!("jobs timed out", timed_out[0].name);
+        assert_eq!("nightly did not end within 1800s", timed_out[0].description);
+        assert_eq!(Level::ERROR, timed_out[0].level);
+        assert_eq!(Some(nightly.id), timed_out[0].parent_id);
+        assert!(restarted.observe(&job("Job finished", "nightly", 1900)).is_empty());
+        assert!(event_log.entries(CF).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_correlation_key_regex() {
+        let yaml = r#"
+rules:
+  brute_force:
+    type: correlation
+    start: 'name == "Login failed"'
+    end: 'name == "Login succeeded"'
+    key: description
+    key_regex: 'from ([0-9.]+)'
+    timeout_secs: 600
+    emit: matched
+    matched_name: "Login from {key} after failures"
+    matched_level: WARN
+"#;
+        let mut running = rules(yaml);
+        let login = |name: &str, description: &str| {
+            let mut event = refused("auth1", now());
+            event.name = String::from(name);
+            event.description = String::from(description);
+            return event;
+        };
+        assert!(running.observe(&login("Login failed", "bad password from 10.0.0.9")).is_empty());
+        assert!(running.observe(&login("Login succeeded", "session from 10.0.0.1")).is_empty());
+        assert!(running.observe(&login("Login failed", "no address")).is_empty());
+        let succeeded = running.observe(&login("Login succeeded", "session from 10.0.0.9"));
+        assert_eq!(1, succeeded.len());
+        assert_eq!("Login from 10.0.0.9 after failures", succeeded[0].name);
+        assert_eq!(Level::WARN, succeeded[0].level);
+        running.observe(&login("Login failed", "bad password from 10.0.0.9"));
+        assert!(running.tick(now() + 601_000_000).is_empty());
+    }
+
+    #[test]
+    fn test_rejected_rules() {
+        let event_log = Arc::new(EventLog::memory());
+        let rejected = |yaml: &str| load(yaml, &event_log).is_err();
+        assert!(rejected("rules: {r: {type: threshold, window_secs: 60}}"));
+        assert!(rejected("rules: {r: {type: threshold, count: 0, window_secs: 60}}"));
+        assert!(rejected("rules: {r: {type: threshold, count: 5, window_secs: 60, recover_below: 6}}"));
+        assert!(rejected("rules: {r: {type: threshold, count: 5, window_secs: 60, filter: 'level >='}}"));
+        assert!(rejected("rules: {r: {type: threshold, count: 5, window_secs: 60, level: LOUD}}"));
+        assert!(rejected("rules: {r: {type: absence}}"));
+        assert!(rejected("rules: {r: {type: absence, interval_secs: 60, schedule: '@daily'}}"));
+        assert!(rejected("rules: {r: {type: absence, schedule: '0 0 30 2 *'}}"));
+        assert!(rejected("rules: {r: {type: absence, schedule: '@daily', grace_secs: -1}}"));
+        let correlation = "rules: {r: {type: correlation, start: 'level >= ERROR', end: 'level < ERROR', key: host";
+        assert!(!rejected(format!("{}, timeout_secs: 60}}}}", correlation).as_str()));
+        assert!(rejected(format!("{}}}}}", correlation).as_str()));
+        assert!(rejected(format!("{}, timeout_secs: 60, emit: sometimes}}}}", correlation).as_str()));
+        assert!(rejected(format!("{}, timeout_secs: 60, key_regex: '('}}}}", correlation).as_str()));
+        assert!(rejected("rules: {r: {type: correlation, start: 'level >= ERROR', key: host, timeout_secs: 60}}"));
+        assert!(rejected("rules: {r: {type: sometimes}}"));
+        assert!(!rejected("rules: {a: {type: absence, interval_secs: 60}}"));
+        assert!(rejected("rules: {a/b: {type: absence, interval_secs: 60}}"));
+    }
+}
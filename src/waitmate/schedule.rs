@@ -0,0 +1,201 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::waitmate::error::{Error, Result};
+
+const MICROS_PER_MINUTE: u128 = 60_000_000;
+const MINUTES_PER_DAY: u128 = 24 * 60;
+/// Steps to look ahead before deciding a schedule never comes round, e.g. `0 0 30 2 *`
+const SEARCH_LIMIT: usize = 100_000;
+
+/// A five field cron schedule, `minute hour day-of-month month day-of-week`, in UTC.  Fields
+/// take `*`, numbers, ranges and lists with an optional `/step`, e.g. `*/15 9-17 * * 1-5`.
+/// `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are shorthands.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month or week was restricted, when either restricted field matches
+    any_day: bool,
+    any_weekday: bool
+}
+impl Schedule {
+    /// The first minute of the schedule strictly after `time`, in microseconds, or `None`
+    /// when it never comes round
+    pub fn next_after(&self, time: u128) -> Option<u128> {
+        let mut minute = time / MICROS_PER_MINUTE + 1;
+        for _ in 0..SEARCH_LIMIT {
+            let day = minute / MINUTES_PER_DAY;
+            if !self.matches_day(day as i64) {
+                minute = (day + 1) * MINUTES_PER_DAY;
+                continue;
+            }
+            let hour = minute % MINUTES_PER_DAY / 60;
+            if !bit(self.hours, hour as u32) {
+                minute = (minute / 60 + 1) * 60;
+                continue;
+            }
+            if !bit(self.minutes, (minute % 60) as u32) {
+                minute += 1;
+                continue;
+            }
+            return Some(minute * MICROS_PER_MINUTE);
+        }
+        return None;
+    }
+    fn matches_day(&self, day: i64) -> bool {
+        let (_, month, day_of_month) = civil(day);
+        if !bit(self.months, month) {
+            return false;
+        }
+        let by_date = bit(self.days, day_of_month);
+        let by_weekday = bit(self.weekdays, (day + 4).rem_euclid(7) as u32);
+        return match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => by_weekday,
+            (false, true) => by_date,
+            (false, false) => by_date || by_weekday
+        };
+    }
+}
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Schedule> {
+        let expr = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other
+        };
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(Error::Invalid(format!("Expected 5 fields in schedule {}", s)));
+        }
+        let field = |i: usize, min: u32, max: u32| parse_field(fields[i], min, max)
+            .map_err(|e| Error::Invalid(format!("{} in schedule {}", e, s)));
+        let mut weekdays = field(4, 0, 7)?;
+        if bit(weekdays, 7) {
+            weekdays |= 1;
+        }
+        return Ok(Schedule {
+            expr: String::from(s.trim()),
+            minutes: field(0, 0, 59)?,
+            hours: field(1, 0, 23)?,
+            days: field(2, 1, 31)?,
+            months: field(3, 1, 12)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*')
+        });
+    }
+}
+impl PartialEq for Schedule {
+    fn eq(&self, other: &Self) -> bool {
+        return self.expr == other.expr;
+    }
+}
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+fn bit(set: u64, n: u32) -> bool {
+    return set & (1 << n) != 0;
+}
+
+/// The values of one field as a bit set
+fn parse_field(field: &str, min: u32, max: u32) -> std::result::Result<u64, String> {
+    let number = |n: &str| n.parse::<u32>()
+        .ok()
+        .filter(|n| *n >= min && *n <= max)
+        .ok_or(format!("{} is not from {} to {}", n, min, max));
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => (&part[..i], part[i + 1..].parse::<u32>().ok().filter(|s| *s > 0)
+                .ok_or(format!("Bad step in {}", part))?),
+            None => (part, 1)
+        };
+        let (from, to) = match range.find('-') {
+            _ if range == "*" => (min, max),
+            Some(i) => (number(&range[..i])?, number(&range[i + 1..])?),
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?)
+        };
+        if from > to {
+            return Err(format!("Backwards range {}", range));
+        }
+        for n in (from..=to).step_by(step as usize) {
+            set |= 1 << n;
+        }
+    }
+    return Ok(set);
+}
+
+/// The year, month and day of the month of a day since the epoch
+fn civil(day: i64) -> (i64, u32, u32) {
+    let z = day + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    return (y, m as u32, d as u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::waitmate::schedule::{civil, Schedule};
+
+    const MINUTE: u128 = 60_000_000;
+
+    #[test]
+    fn test_civil() {
+        assert_eq!((1970, 1, 1), civil(0));
+        assert_eq!((2000, 2, 29), civil(11_016));
+        assert_eq!((2024, 12, 31), civil(20_088));
+    }
+
+    #[test]
+    fn test_schedule() {
+        // 2024-01-01T00:00:00Z, a Monday
+        let monday = 1_704_067_200 * 1_000_000;
+        let daily: Schedule = "30 3 * * *".parse().unwrap();
+        assert_eq!(Some(monday + 210 * MINUTE), daily.next_after(monday));
+        assert_eq!(Some(monday + (1440 + 210) * MINUTE), daily.next_after(monday + 210 * MINUTE));
+
+        let quarter: Schedule = "*/15 9-17 * * 1-5".parse().unwrap();
+        assert_eq!(Some(monday + 540 * MINUTE), quarter.next_after(monday));
+        assert_eq!(Some(monday + 555 * MINUTE), quarter.next_after(monday + 540 * MINUTE));
+        let friday_evening = monday + (4 * 1440 + 17 * 60 + 46) * MINUTE;
+        assert_eq!(Some(monday + (7 * 1440 + 540) * MINUTE), quarter.next_after(friday_evening));
+
+        let sunday: Schedule = "0 0 * * 7".parse().unwrap();
+        assert_eq!(Some(monday + 6 * 1440 * MINUTE), sunday.next_after(monday));
+        let monthly: Schedule = "@monthly".parse().unwrap();
+        assert_eq!(Some(monday + 31 * 1440 * MINUTE), monthly.next_after(monday));
+        // either restricted day field matches
+        let either: Schedule = "0 0 15 * 0".parse().unwrap();
+        assert_eq!(Some(monday + 6 * 1440 * MINUTE), either.next_after(monday));
+        let never: Schedule = "0 0 30 2 *".parse().unwrap();
+        assert_eq!(None, never.next_after(monday));
+    }
+
+    #[test]
+    fn test_schedule_errors() {
+        for bad in &["* * * *", "60 * * * *", "* * 0 * *", "5-1 * * * *", "*/0 * * * *", "x * * * *"] {
+            assert!(bad.parse::<Schedule>().is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeSet;
+
+use crate::waitmate::api::Event;
+use crate::waitmate::codec::decode;
+use crate::waitmate::error::Result;
+use crate::waitmate::log::EventLog;
+use crate::waitmate::store::LOG;
+
+/// The inverted index from description tokens to event keys
+pub const INDEX: &'static str = "index";
+/// Separates the token from the event key in index keys.  It sorts before any token byte
+/// so all keys for one token are contiguous.
+const SEPARATOR: u8 = 0;
+const MAX_TOKEN_LEN: usize = 64;
+
+/// Lower cased alphanumeric words of `text` in order
+pub fn tokenize(text: &str) -> Vec<String> {
+    return text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase().chars().take(MAX_TOKEN_LEN).collect())
+        .collect();
+}
+
+/// The index keys for an event stored under `event_key`
+pub fn index_keys(event: &Event, event_key: &[u8]) -> BTreeSet<Vec<u8>> {
+    return tokenize(event.description.as_str())
+        .into_iter()
+        .map(|t| {
+            let mut key = t.into_bytes();
+            key.push(SEPARATOR);
+            key.extend_from_slice(event_key);
+            key
+        })
+        .collect();
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Clause {
+    Term(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+}
+
+/// Every clause must match.  Terms are plain words, `word*` matches by prefix and
+/// `"some words"` matches the words in sequence.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub clauses: Vec<Clause>,
+    pub from: Option<u128>,
+    pub to: Option<u128>,
+}
+impl SearchQuery {
+    pub fn parse(q: &str) -> SearchQuery {
+        let mut clauses = Vec::new();
+        for (i, part) in q.split('"').enumerate() {
+            if i % 2 == 1 {
+                let words = tokenize(part);
+                if words.len() == 1 {
+                    clauses.push(Clause::Term(words[0].clone()));
+                } else if !words.is_empty() {
+                    clauses.push(Clause::Phrase(words));
+                }
+                continue;
+            }
+            for word in part.split_whitespace() {
+                let prefix = word.ends_with('*');
+                for token in tokenize(word) {
+                    if prefix {
+                        clauses.push(Clause::Prefix(token));
+                    } else {
+                        clauses.push(Clause::Term(token));
+                    }
+                }
+            }
+        }
+        return SearchQuery {
+            clauses,
+            from: None,
+            to: None
+        };
+    }
+    pub fn between(mut self, from: Option<u128>, to: Option<u128>) -> Self {
+        self.from = from;
+        self.to = to;
+        return self;
+    }
+    fn in_range(&self, event_key: &[u8]) -> bool {
+        return match EventLog::parse_key(event_key) {
+            Ok((time, _)) => self.from.map_or(true, |f| time >= f)
+                && self.to.map_or(true, |t| time < t),
+            Err(_) => false
+        };
+    }
+}
+
+/// Keys of events with a token starting with `prefix`, or equal to it when `exact`
+fn lookup(event_log: &EventLog, prefix: &str, exact: bool) -> Result<BTreeSet<Vec<u8>>> {
+    let mut keys = BTreeSet::new();
+    let mut after = prefix.as_bytes().to_vec();
+    loop {
+        let batch = event_log.scan_entries(INDEX, Some(after.as_slice()), 256)?;
+        if batch.is_empty() {
+            return Ok(keys);
+        }
+        for (k, _) in &batch {
+            let split = match k.iter().position(|b| *b == SEPARATOR) {
+                Some(s) => s,
+                None => continue
+            };
+            let token = &k[..split];
+            if !token.starts_with(prefix.as_bytes()) || (exact && token != prefix.as_bytes()) {
+                return Ok(keys);
+            }
+            keys.insert(k[split + 1..].to_vec());
+        }
+        after = batch.last().unwrap().0.clone();
+    }
+}
+
+fn contains_phrase(tokens: &[String], phrase: &[String]) -> bool {
+    return tokens.windows(phrase.len()).any(|w| w == phrase);
+}
+
+/// Events matching `query` in key order, at most `limit` of them
+pub fn search(event_log: &EventLog, query: &SearchQuery, limit: usize) -> Result<Vec<(String, Event)>> {
+    let mut candidates: Option<BTreeSet<Vec<u8>>> = None;
+    for clause in &query.clauses {
+        let keys = match clause {
+            Clause::Term(t) => lookup(event_log, t, true)?,
+            Clause::Prefix(p) => lookup(event_log, p, false)?,
+            Clause::Phrase(words) => {
+                let mut keys = lookup(event_log, words[0].as_str(), true)?;
+                for w in &words[1..] {
+                    let next = lookup(event_log, w.as_str(), true)?;
+                    keys = keys.intersection(&next).cloned().collect();
+                }
+                keys
+            }
+        };
+        candidates = Some(match candidates {
+            Some(c) => c.intersection(&keys).cloned().collect(),
+            None => keys
+        });
+    }
+
+    let mut results = Vec::new();
+    for key in candidates.unwrap_or_default() {
+        if results.len() >= limit {
+            break;
+        }
+        if !query.in_range(&key) {
+            continue;
+        }
+        let event = match event_log.get_entry(LOG, &key)?.and_then(|d| decode(&d).ok()) {
+            Some(e) => e,
+            None => continue
+        };
+        let tokens = tokenize(event.description.as_str());
+        let phrases_match = query.clauses.iter().all(|c| match c {
+            Clause::Phrase(words) => contains_phrase(&tokens, words),
+            _ => true
+        });
+        if phrases_match {
+            results.push((String::from_utf8_lossy(&key).into_owned(), event));
+        }
+    }
+    return Ok(results);
+}
+
+/// Print the events in the local log matching `q`
+pub fn print_search(event_log: &EventLog, q: &str, from: Option<u128>, to: Option<u128>, limit: usize) -> Result<()> {
+    let query = SearchQuery::parse(q).between(from, to);
+    for (key, event) in search(event_log, &query, limit)? {
+        println!("{} {}", key, event);
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::waitmate::api::{EmptyNamed, Event, Level};
+    use crate::waitmate::log::EventLog;
+    use crate::waitmate::search::{Clause, search, SearchQuery, tokenize};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(vec!["order", "12345", "failed"], tokenize("Order #12345 failed!"));
+        let q = SearchQuery::parse(r#"order pay* "card declined" "x""#);
+        assert_eq!(vec![
+            Clause::Term(String::from("order")),
+            Clause::Prefix(String::from("pay")),
+            Clause::Phrase(vec![String::from("card"), String::from("declined")]),
+            Clause::Term(String::from("x")),
+        ], q.clauses);
+    }
+
+    #[test]
+    fn test_search() {
+        let event_log = EventLog::memory();
+        let source = EmptyNamed {};
+        let e1 = Event::new(&source, "a", "Order 12345 payment declined by card", "c", Level::WARN);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let e2 = Event::new(&source, "a", "Order 12346 card declined", "c", Level::WARN);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let e3 = Event::new(&source, "a", "Order 12345 shipped", "c", Level::INFO);
+        event_log.add(&e1).unwrap();
+        event_log.add(&e2).unwrap();
+        event_log.add(&e3).unwrap();
+
+        let names = |q: SearchQuery| search(&event_log, &q, 10)
+            .unwrap()
+            .into_iter()
+            .map(|(_, e)| e.id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![e1.id, e3.id], names(SearchQuery::parse("order 12345")));
+        assert_eq!(vec![e1.id, e2.id, e3.id], names(SearchQuery::parse("1234*")));
+        assert_eq!(vec![e2.id], names(SearchQuery::parse("\"card declined\"")));
+        assert_eq!(vec![e1.id, e2.id], names(SearchQuery::parse("card declined")));
+        assert!(names(SearchQuery::parse("nothing")).is_empty());
+        assert_eq!(vec![e3.id], names(SearchQuery::parse("12345").between(Some(e2.time), None)));
+        assert_eq!(vec![e1.id], names(SearchQuery::parse("12345").between(None, Some(e2.time))));
+        assert_eq!(1, search(&event_log, &SearchQuery::parse("order"), 1).unwrap().len());
+    }
+}
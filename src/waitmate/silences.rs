@@ -0,0 +1,352 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use actix_web::http::Method;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::waitmate::api::{Event, Level, now};
+use crate::waitmate::error::{Error, INTERNAL, Result};
+use crate::waitmate::filter::Filter;
+use crate::waitmate::http;
+use crate::waitmate::log::EventLog;
+use crate::waitmate::std::parse_epoch;
+
+/// Marks an event that was logged but not notified, with the id of the silence that matched it
+pub const SILENCED_BY: &'static str = "silenced_by";
+
+/// Keeps notifiers quiet about the events matching `matcher` from `starts_at` until `ends_at`,
+/// e.g. during a deploy
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Silence {
+    pub id: Uuid,
+    pub matcher: String,
+    pub starts_at: u128,
+    pub ends_at: u128,
+    pub created_by: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+    pub created_at: u128,
+}
+impl Silence {
+    pub fn covers(&self, time: u128) -> bool {
+        return self.starts_at <= time && time < self.ends_at;
+    }
+}
+
+/// What it takes to create a silence.  Times are microseconds since the epoch; it starts now
+/// unless `starts_at` is given and ends at `ends_at` or `duration_secs` after it starts.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct NewSilence {
+    pub matcher: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starts_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ends_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// The silences by id, kept in the `silences` column family of the event log.  Creating,
+/// deleting and expiring a silence is logged as an event labelled `silence`.  Their parsed
+/// matchers are cached for `mark` until this instance changes them or `reload` is called.
+pub struct Silences {
+    event_log: Arc<EventLog>,
+    matchers: RwLock<Option<Vec<(Silence, Filter)>>>
+}
+impl Silences {
+    const CF: &'static str = "silences";
+
+    pub fn new(event_log: Arc<EventLog>) -> Silences {
+        return Silences {
+            event_log,
+            matchers: RwLock::new(None)
+        };
+    }
+    /// Every silence, soonest first
+    pub fn list(&self) -> Result<Vec<Silence>> {
+        let mut silences = self.event_log.entries(Silences::CF)?
+            .iter()
+            .filter_map(|(_, v)| serde_json::from_slice(v).ok())
+            .collect::<Vec<Silence>>();
+        silences.sort_by_key(|s| s.starts_at);
+        return Ok(silences);
+    }
+    /// Create a silence, returning it with the event announcing it for the caller to publish
+    pub fn add(&self, new: &NewSilence) -> Result<(Silence, Event)> {
+        new.matcher.parse::<Filter>()?;
+        let time = now();
+        let starts_at = new.starts_at.map_or(time, u128::from);
+        let ends_at = match (new.ends_at, new.duration_secs) {
+            (Some(t), None) => u128::from(t),
+            (None, Some(d)) => starts_at + Duration::from_secs(d).as_micros(),
+            _ => return Err(Error::Invalid(String::from("A silence needs either an end or a duration")))
+        };
+        if ends_at <= starts_at || ends_at <= time {
+            return Err(Error::Invalid(String::from("A silence must end after it starts and in the future")));
+        }
+        let silence = Silence {
+            id: Uuid::new_v4(),
+            matcher: new.matcher.clone(),
+            starts_at,
+            ends_at,
+            created_by: new.created_by.clone().unwrap_or(String::from("unknown")),
+            comment: new.comment.clone().unwrap_or_default(),
+            created_at: time
+        };
+        self.event_log.put_entry(Silences::CF, silence.id.to_string().as_bytes(), &serde_json::to_vec(&silence)?)?;
+        self.reload();
+        let event = Silences::event(&silence, "Silence created");
+        return Ok((silence, event));
+    }
+    /// Lift a silence before it ends, returning it with the event announcing it for the
+    /// caller to publish
+    pub fn delete(&self, id: &str) -> Result<(Silence, Event)> {
+        let silence: Silence = self.event_log.get_entry(Silences::CF, id.as_bytes())?
+            .and_then(|v| serde_json::from_slice(&v).ok())
+            .ok_or(Error::Invalid(format!("No silence {}", id)))?;
+        self.event_log.delete_entry(Silences::CF, id.as_bytes())?;
+        self.reload();
+        let event = Silences::event(&silence, "Silence deleted");
+        return Ok((silence, event));
+    }
+    /// Read the silences again on the next `mark`, e.g. after another instance changed them
+    pub fn reload(&self) {
+        *self.matchers.write().unwrap() = None;
+    }
+    /// Label the events a silence covers and matches with its id
+    pub fn mark(&self, events: &mut [Event]) -> Result<()> {
+        let cached = self.matchers.read().unwrap().is_some();
+        if !cached {
+            let mut matchers = Vec::new();
+            for silence in self.list()? {
+                match silence.matcher.parse::<Filter>() {
+                    Ok(filter) => matchers.push((silence, filter)),
+                    Err(e) => warn!("Ignoring silence {} with matcher {}: {}", silence.id, silence.matcher, e)
+                }
+            }
+            *self.matchers.write().unwrap() = Some(matchers);
+        }
+        let matchers = self.matchers.read().unwrap();
+        let silences = matchers.as_deref().unwrap_or_default();
+        if silences.is_empty() {
+            return Ok(());
+        }
+        for event in events.iter_mut() {
+            match silences.iter().find(|(s, f)| s.covers(event.time) && f.matches(event)) {
+                Some((s, _)) => { event.labels.insert(String::from(SILENCED_BY), s.id.to_string()); }
+                None => {}
+            }
+        }
+        return Ok(());
+    }
+    /// Delete the silences that ended by `now`, returning the events announcing it
+    pub fn expire(&self, now: u128) -> Result<Vec<Event>> {
+        let mut expired = Vec::new();
+        for silence in self.list()?.iter().filter(|s| s.ends_at <= now) {
+            self.event_log.delete_entry(Silences::CF, silence.id.to_string().as_bytes())?;
+            expired.push(Silences::event(silence, "Silence expired"));
+        }
+        if !expired.is_empty() {
+            self.reload();
+        }
+        return Ok(expired);
+    }
+    fn event(silence: &Silence, name: &str) -> Event {
+        let mut event = Event::builder(name)
+            .description(format!("{} by {} from {} to {}: {}", silence.matcher, silence.created_by,
+                                 silence.starts_at, silence.ends_at, silence.comment).as_str())
+            .category(INTERNAL)
+            .level(Level::INFO)
+            .label("silence", silence.id.to_string().as_str())
+            .build();
+        event.source = String::from(INTERNAL);
+        return event;
+    }
+}
+
+/// `90s`, `30m`, `2h`, `1d` or plain seconds
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let trimmed = text.trim();
+    let (number, unit) = match trimmed.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => (&trimmed[..i], &trimmed[i..]),
+        None => (trimmed, "s")
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        _ => 0
+    };
+    return match number.parse::<u64>() {
+        Ok(n) if scale > 0 => n.checked_mul(scale)
+            .map(Duration::from_secs)
+            .ok_or(Error::Invalid(format!("Duration {} is too long", text))),
+        _ => Err(Error::Invalid(format!("Expected a duration like 30m or 2h, not {}", text)))
+    };
+}
+
+/// An epoch timestamp in seconds, milliseconds or microseconds as microseconds
+pub fn parse_time(text: &str) -> Result<u64> {
+    return parse_epoch(text)
+        .map(|t| t as u64)
+        .ok_or_else(|| Error::Invalid(format!("Expected a timestamp, not {}", text)));
+}
+
+pub enum Command {
+    List,
+    Add(NewSilence),
+    Delete(String),
+}
+
+/// Apply `command` to the local log, returning the silences it concerns and the events to
+/// publish
+pub fn apply(event_log: Arc<EventLog>, command: &Command) -> Result<(Vec<Silence>, Vec<Event>)> {
+    let silences = Silences::new(event_log);
+    return match command {
+        Command::List => silences.list().map(|s| (s, Vec::new())),
+        Command::Add(new) => silences.add(new).map(|(s, e)| (vec![s], vec![e])),
+        Command::Delete(id) => silences.delete(id.as_str()).map(|(s, e)| (vec![s], vec![e]))
+    };
+}
+
+/// Apply `command` through the API of the server at `server`
+pub fn apply_remote(server: &str, command: &Command) -> Result<Vec<Silence>> {
+    let url = format!("{}/api/v1/silences", server.trim_end_matches('/'));
+    return match command {
+        Command::List => Ok(serde_json::from_value(http::get_json(url.as_str())?)?),
+        Command::Add(new) => Ok(vec![serde_json::from_value(
+            http::send_json(Method::POST, url.as_str(), &serde_json::to_value(new)?)?)?]),
+        Command::Delete(id) => Ok(vec![serde_json::from_value(
+            http::send_json(Method::DELETE, format!("{}/{}", url, id).as_str(), &json!({}))?)?])
+    };
+}
+
+pub fn print_silences(silences: Vec<Silence>) {
+    for s in silences {
+        println!("{} {:>16} {:>16} {:<12} {} {}", s.id, s.starts_at / 1_000_000, s.ends_at / 1_000_000,
+                 s.created_by, s.matcher, s.comment);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::waitmate::api::{EmptyNamed, Event, Level, now};
+    use crate::waitmate::log::EventLog;
+    use crate::waitmate::silences::{NewSilence, parse_duration, parse_time, SILENCED_BY, Silences};
+
+    #[test]
+    fn test_silences() {
+        let event_log = Arc::new(EventLog::memory());
+        let silences = Silences::new(event_log.clone());
+        let (deploy, created) = silences.add(&NewSilence {
+            matcher: String::from("category == web && level >= ERROR"),
+            duration_secs: Some(600),
+            created_by: Some(String::from("alice")),
+            comment: Some(String::from("deploying 1.2")),
+            ..NewSilence::default()
+        }).unwrap();
+        let (later, _) = silences.add(&NewSilence {
+            matcher: String::from("category == db"),
+            starts_at: Some((now() + 3_600_000_000) as u64),
+            duration_secs: Some(60),
+            ..NewSilence::default()
+        }).unwrap();
+        assert_eq!(vec![deploy.clone(), later.clone()], silences.list().unwrap());
+        assert_eq!("Silence created", created.name);
+        assert_eq!(Some(&deploy.id.to_string()), created.labels.get("silence"));
+        // the caller publishes the announcements
+        assert_eq!(0, event_log.build_cursor().build().unwrap().count());
+
+        let source = EmptyNamed {};
+        let mut events = vec![Event::new(&source, "a", "b", "web", Level::ERROR),
+                              Event::new(&source, "a", "b", "web", Level::INFO),
+                              Event::new(&source, "a", "b", "db", Level::ERROR)];
+        silences.mark(&mut events).unwrap();
+        assert_eq!(Some(&deploy.id.to_string()), events[0].labels.get(SILENCED_BY));
+        assert!(!events[1].labels.contains_key(SILENCED_BY));
+        assert!(!events[2].labels.contains_key(SILENCED_BY));
+
+        assert!(silences.expire(deploy.ends_at - 1).unwrap().is_empty());
+        let expired = silences.expire(deploy.ends_at).unwrap();
+        assert_eq!(1, expired.len());
+        assert_eq!("Silence expired", expired[0].name);
+        assert_eq!(Some(&deploy.id.to_string()), expired[0].labels.get("silence"));
+        let (deleted, announced) = silences.delete(later.id.to_string().as_str()).unwrap();
+        assert_eq!(later, deleted);
+        assert_eq!("Silence deleted", announced.name);
+        assert!(silences.list().unwrap().is_empty());
+        assert!(silences.delete(later.id.to_string().as_str()).is_err());
+    }
+
+    #[test]
+    fn test_cached_matchers() {
+        let event_log = Arc::new(EventLog::memory());
+        let silences = Silences::new(event_log.clone());
+        let source = EmptyNamed {};
+        let marked = |silences: &Silences| {
+            let mut events = vec![Event::new(&source, "a", "b", "web", Level::ERROR)];
+            silences.mark(&mut events).unwrap();
+            return events[0].labels.get(SILENCED_BY).cloned();
+        };
+        assert_eq!(None, marked(&silences));
+
+        // another instance, e.g. the API's, is only seen after a reload
+        let (web, _) = Silences::new(event_log.clone()).add(&NewSilence {
+            matcher: String::from("category == web"),
+            duration_secs: Some(600),
+            ..NewSilence::default()
+        }).unwrap();
+        assert_eq!(None, marked(&silences));
+        silences.reload();
+        assert_eq!(Some(web.id.to_string()), marked(&silences));
+
+        // this instance's own changes are seen at once
+        silences.delete(web.id.to_string().as_str()).unwrap();
+        assert_eq!(None, marked(&silences));
+    }
+
+    #[test]
+    fn test_rejected_silences() {
+        let silences = Silences::new(Arc::new(EventLog::memory()));
+        let rejected = |matcher: &str, starts_at: Option<u64>, ends_at: Option<u64>, duration_secs: Option<u64>| {
+            return silences.add(&NewSilence {
+                matcher: String::from(matcher),
+                starts_at,
+                ends_at,
+                duration_secs,
+                ..NewSilence::default()
+            }).is_err();
+        };
+        let time = now() as u64;
+        assert!(!rejected("level >= ERROR", None, Some(time + 60_000_000), None));
+        assert!(rejected("level >=", None, None, Some(60)));
+        assert!(rejected("level >= ERROR", None, None, None));
+        assert!(rejected("level >= ERROR", None, Some(time + 60_000_000), Some(60)));
+        assert!(rejected("level >= ERROR", Some(time - 120_000_000), None, Some(60)));
+        assert!(rejected("level >= ERROR", Some(time + 120_000_000), Some(time + 60_000_000), None));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Duration::from_secs(90), parse_duration("90").unwrap());
+        assert_eq!(Duration::from_secs(1800), parse_duration("30m").unwrap());
+        assert_eq!(Duration::from_secs(7200), parse_duration("2h").unwrap());
+        assert_eq!(Duration::from_secs(86_400), parse_duration("1d").unwrap());
+        assert!(parse_duration("2w").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("999999999999999999d").is_err());
+        assert_eq!(1_600_000_000_000_000, parse_time("1600000000").unwrap());
+        assert!(parse_time("tomorrow").is_err());
+    }
+}
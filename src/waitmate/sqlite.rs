@@ -0,0 +1,129 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use log::info;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::waitmate::error::Result;
+use crate::waitmate::store::{KeyValue, Store};
+
+/// Keeps every table in a single SQLite table keyed by table name and key, for simple
+/// deployments that would rather not carry RocksDB around
+pub struct SqliteStore {
+    conn: Mutex<Connection>
+}
+impl SqliteStore {
+    pub fn new(path: &Path) -> Result<SqliteStore> {
+        match path.parent() {
+            Some(dir) => std::fs::create_dir_all(dir)?,
+            None => {}
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS entries (
+                tbl TEXT NOT NULL,
+                key BLOB NOT NULL,
+                value BLOB NOT NULL,
+                PRIMARY KEY (tbl, key)
+            ) WITHOUT ROWID;")?;
+        info!("Opened log {}", path.to_str().unwrap());
+        return Ok(SqliteStore {
+            conn: Mutex::new(conn)
+        });
+    }
+}
+impl Store for SqliteStore {
+    fn put(&self, table: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.conn.lock().unwrap()
+            .execute("INSERT OR REPLACE INTO entries (tbl, key, value) VALUES (?1, ?2, ?3)",
+                     params![table, key, value])?;
+        return Ok(());
+    }
+    /// A single transaction
+    fn put_all(&self, entries: &[(&str, KeyValue)]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached("INSERT OR REPLACE INTO entries (tbl, key, value) VALUES (?1, ?2, ?3)")?;
+            for (table, (key, value)) in entries {
+                stmt.execute(params![table, key, value])?;
+            }
+        }
+        tx.commit()?;
+        return Ok(());
+    }
+    fn get(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        return Ok(self.conn.lock().unwrap()
+            .query_row("SELECT value FROM entries WHERE tbl = ?1 AND key = ?2",
+                       params![table, key],
+                       |row| row.get(0))
+            .optional()?);
+    }
+    fn delete(&self, table: &str, key: &[u8]) -> Result<()> {
+        self.conn.lock().unwrap()
+            .execute("DELETE FROM entries WHERE tbl = ?1 AND key = ?2", params![table, key])?;
+        return Ok(());
+    }
+    fn scan(&self, table: &str, after: Option<&[u8]>, limit: usize) -> Result<Vec<KeyValue>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "SELECT key, value FROM entries WHERE tbl = ?1 AND (?2 IS NULL OR key > ?2)
+             ORDER BY key LIMIT ?3")?;
+        let rows = stmt.query_map(params![table, after, limit as i64],
+                                  |row| Ok((row.get(0)?, row.get(1)?)))?;
+        return Ok(rows.collect::<rusqlite::Result<Vec<KeyValue>>>()?);
+    }
+    fn scan_reverse(&self, table: &str, before: Option<&[u8]>, limit: usize) -> Result<Vec<KeyValue>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "SELECT key, value FROM entries WHERE tbl = ?1 AND (?2 IS NULL OR key < ?2)
+             ORDER BY key DESC LIMIT ?3")?;
+        let rows = stmt.query_map(params![table, before, limit as i64],
+                                  |row| Ok((row.get(0)?, row.get(1)?)))?;
+        return Ok(rows.collect::<rusqlite::Result<Vec<KeyValue>>>()?);
+    }
+    fn count(&self, table: &str, after: Option<&[u8]>) -> Result<u64> {
+        let count: i64 = self.conn.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM entries WHERE tbl = ?1 AND (?2 IS NULL OR key > ?2)",
+                       params![table, after],
+                       |row| row.get(0))?;
+        return Ok(count as u64);
+    }
+    /// A single database file written with `VACUUM INTO`
+    fn backup(&self, path: &Path) -> Result<()> {
+        self.conn.lock().unwrap()
+            .execute("VACUUM INTO ?1", params![path.to_str().unwrap()])?;
+        info!("Backed up log to {}", path.display());
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::waitmate::sqlite::SqliteStore;
+    use crate::waitmate::store::{restore, Store};
+    use crate::waitmate::store::tests::check_store;
+
+    #[test]
+    fn test_sqlite_store() {
+        let dir = tempdir().unwrap().into_path().join("t.sqlite");
+        check_store(&SqliteStore::new(dir.as_path()).unwrap());
+    }
+
+    #[test]
+    fn test_sqlite_backup() {
+        let dir = tempdir().unwrap().into_path();
+        let store = SqliteStore::new(dir.join("t.sqlite").as_path()).unwrap();
+        store.append(b"a", b"1").unwrap();
+        store.set_offset("markie", b"a").unwrap();
+        store.backup(dir.join("backup.sqlite").as_path()).unwrap();
+        store.append(b"b", b"2").unwrap();
+
+        restore(dir.join("backup.sqlite").as_path(), dir.join("restored.sqlite").as_path()).unwrap();
+        let restored = SqliteStore::new(dir.join("restored.sqlite").as_path()).unwrap();
+        assert_eq!(1, restored.scan("log", None, 10).unwrap().len());
+        assert_eq!(Some(b"a".to_vec()), restored.offset("markie").unwrap());
+    }
+}
@@ -1,4 +1,5 @@
 use std::{io, str};
+use std::collections::BTreeMap;
 use std::io::BufRead;
 use std::thread::sleep;
 use std::time::Duration;
@@ -6,37 +7,95 @@ use std::time::Duration;
 use regex::Regex;
 
 use crate::waitmate::api::{Event, EventBus, Level, Named, Notifier, Waiter};
+use crate::waitmate::error::{Error, Result};
+
+/// Read an epoch timestamp in seconds, milliseconds or microseconds as microseconds
+pub fn parse_epoch(text: &str) -> Option<u128> {
+    return text.parse::<u128>().ok()
+        .map(|t| match t {
+            t if t < 100_000_000_000 => t * 1_000_000,
+            t if t < 100_000_000_000_000 => t * 1_000,
+            t => t
+        });
+}
+
+/// Labels from the capture groups of `matcher`, named groups by name and the rest by index
+pub fn capture_labels(matcher: &Regex, text: &str) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    match matcher.captures(text) {
+        Some(captures) => {
+            for (i, name) in matcher.capture_names().enumerate().skip(1) {
+                match captures.get(i) {
+                    Some(m) => {
+                        let key = name.map_or(i.to_string(), String::from);
+                        labels.insert(key, String::from(m.as_str()));
+                    }
+                    None => {}
+                }
+            }
+        }
+        None => {}
+    }
+    return labels;
+}
+
+/// Build an event from a matching line.  The `level`, `host`, `timestamp` and
+/// `correlation_id` capture groups fill in the matching event fields.
+pub fn event_from_line(source: &dyn Named, matcher: &Regex, line: &str) -> Option<Event> {
+    if !matcher.is_match(line) {
+        return None;
+    }
+    let labels = capture_labels(matcher, line);
+    let mut builder = Event::builder("A name")
+        .source(source)
+        .description(line)
+        .category("Cat")
+        .level(labels.get("level").and_then(|v| v.parse().ok()).unwrap_or(Level::WARN));
+    if let Some(host) = labels.get("host") {
+        builder = builder.host(host);
+    }
+    if let Some(time) = labels.get("timestamp").and_then(|t| parse_epoch(t)) {
+        builder = builder.occurred_at(time);
+    }
+    if let Some(id) = labels.get("correlation_id") {
+        builder = builder.correlation_id(id);
+    }
+    return Some(builder.labels(labels).build());
+}
 
 pub struct StdinWaiter {
     matcher: Regex
 }
 impl StdinWaiter {
     const NAME: &'static str = "StdoutNotifier";
+    const PATTERN: &'static str = r"^(.*)bash(.*)$";
     pub fn new() -> StdinWaiter {
         StdinWaiter {
-            matcher: Regex::new(r"^(.*)bash(.*)$").unwrap()
+            matcher: Regex::new(StdinWaiter::PATTERN).unwrap()
         }
     }
+    /// Publish the lines matching `pattern` rather than the default
+    pub fn with_pattern(pattern: Option<&str>) -> Result<StdinWaiter> {
+        return Ok(StdinWaiter {
+            matcher: Regex::new(pattern.unwrap_or(StdinWaiter::PATTERN))
+                .map_err(|e| Error::Config(e.to_string()))?
+        });
+    }
 }
 impl Named for StdinWaiter {
     fn name(&self) -> &str {return StdinWaiter::NAME;}
 }
 impl Waiter for StdinWaiter {
-    fn wait(&self, bus: &dyn EventBus) {
+    fn wait(&self, bus: &dyn EventBus) -> Result<()> {
         let stdin = io::stdin();
         for line in stdin.lock().lines() {
-            let l = line.unwrap();
-            if self.matcher.is_match(l.as_str()) {
-                let e = Event::new(
-                    self,
-                    "A name",
-                    l.as_str(),
-                    "Cat",
-                    Level::WARN
-                );
-                bus.publish(e);
+            let l = line?;
+            match event_from_line(self, &self.matcher, l.as_str()) {
+                Some(e) => bus.publish(e),
+                None => {}
             }
         }
+        return Ok(());
     }
 }
 pub struct SleepyWaiter {
@@ -53,17 +112,20 @@ impl Named for SleepyWaiter {
     }
 }
 impl Waiter for SleepyWaiter {
-    fn wait(&self, bus: &dyn EventBus) {
+    fn wait(&self, bus: &dyn EventBus) -> Result<()> {
         for i in 0..10 {
-            bus.publish(Event::new(
-                self,
-                "A name",
-                format!("EVENT {}", i).as_str(),
-                "Doggo",
-                Level::WARN
-            ));
+            if bus.stopping() {
+                break;
+            }
+            bus.publish(Event::builder("A name")
+                .source(self)
+                .description(format!("EVENT {}", i).as_str())
+                .category("Doggo")
+                .level(Level::WARN)
+                .build());
             sleep(Duration::from_millis(1));
         }
+        return Ok(());
     }
 }
 
@@ -79,7 +141,39 @@ impl Named for StdoutNotifier {
     fn name(&self) -> &str {return StdoutNotifier::NAME;}
 }
 impl Notifier for StdoutNotifier {
-    fn notify(&self, event: Event, _: &dyn EventBus) {
+    fn notify(&self, event: Event, _: &dyn EventBus) -> Result<()> {
         println!("{:?}", event);
+        return Ok(());
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use crate::waitmate::api::{EmptyNamed, Level};
+    use crate::waitmate::std::{capture_labels, event_from_line};
+
+    #[test]
+    fn test_capture_labels() {
+        let matcher = Regex::new(r"^(?P<host>\w+) (\w+)(?: (?P<missing>x))?").unwrap();
+        let labels = capture_labels(&matcher, "web1 started");
+        assert_eq!(2, labels.len());
+        assert_eq!("web1", labels["host"]);
+        assert_eq!("started", labels["2"]);
+        assert!(capture_labels(&matcher, "").is_empty());
+    }
+
+    #[test]
+    fn test_event_from_line() {
+        let matcher = Regex::new(r"^(?P<timestamp>\d+) (?P<host>\w+) (?P<level>\w+) (?P<correlation_id>\S+)").unwrap();
+        let source = EmptyNamed {};
+        let e = event_from_line(&source, &matcher, "1600000000 web1 err req-7 boom").unwrap();
+        assert_eq!(Level::ERROR, e.level);
+        assert_eq!("web1", e.host);
+        assert_eq!(Some(1_600_000_000_000_000), e.occurred_at);
+        assert_eq!(Some(String::from("req-7")), e.correlation_id);
+        assert_eq!("NAMED", e.source);
+        assert!(event_from_line(&source, &matcher, "nope").is_none());
+    }
+}
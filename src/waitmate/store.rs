@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::path::Path;
+use std::sync::RwLock;
+
+use crate::waitmate::error::{Error, Result};
+
+/// `log` holds the events and `offsets` the positions of named cursors.  Components keep
+/// their state beside the log in tables of their own, which stores create on first write.
+pub const LOG: &'static str = "log";
+pub const OFFSETS: &'static str = "offsets";
+
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+
+/// Ordered key/value storage behind the event log.  Scans always see the latest writes so
+/// a cursor tails the log by scanning again from its last position once it is woken.
+pub trait Store: Send + Sync {
+    fn put(&self, table: &str, key: &[u8], value: &[u8]) -> Result<()>;
+    /// Put every entry in one write, which readers see all at once where the store allows
+    fn put_all(&self, entries: &[(&str, KeyValue)]) -> Result<()> {
+        for (table, (key, value)) in entries {
+            self.put(table, key, value)?;
+        }
+        return Ok(());
+    }
+    fn get(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    fn delete(&self, table: &str, key: &[u8]) -> Result<()>;
+    /// Up to `limit` entries in key order, starting after `after` or at the first key
+    fn scan(&self, table: &str, after: Option<&[u8]>, limit: usize) -> Result<Vec<KeyValue>>;
+    /// Up to `limit` entries in reverse key order, starting before `before` or at the last key
+    fn scan_reverse(&self, table: &str, before: Option<&[u8]>, limit: usize) -> Result<Vec<KeyValue>>;
+    /// How many entries come after `after`, or all of them.  Stores should count keys
+    /// without reading the values.
+    fn count(&self, table: &str, after: Option<&[u8]>) -> Result<u64> {
+        let mut position = after.map(|k| k.to_vec());
+        let mut count = 0;
+        loop {
+            let batch = self.scan(table, position.as_ref().map(|p| p.as_slice()), 1024)?;
+            match batch.last() {
+                Some((k, _)) => position = Some(k.clone()),
+                None => break
+            }
+            count += batch.len() as u64;
+        }
+        return Ok(count);
+    }
+
+    fn append(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        return self.put(LOG, key, value);
+    }
+    /// The last key consumed by the named cursor
+    fn offset(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        return self.get(OFFSETS, name.as_bytes());
+    }
+    fn set_offset(&self, name: &str, key: &[u8]) -> Result<()> {
+        return self.put(OFFSETS, name.as_bytes(), key);
+    }
+    /// Write a consistent copy of every table to `path` without stopping writers
+    fn backup(&self, _path: &Path) -> Result<()> {
+        return Err(Error::Store(String::from("This store does not support backups")));
+    }
+}
+
+/// Copy a backup written by `Store::backup`, a directory or a single file, to `path` where
+/// the store can then be opened.  `path` must not exist yet.
+pub fn restore(backup: &Path, path: &Path) -> Result<()> {
+    if !backup.exists() {
+        return Err(Error::Invalid(format!("No backup at {}", backup.display())));
+    }
+    if path.exists() {
+        return Err(Error::Invalid(format!("{} already exists", path.display())));
+    }
+    if backup.is_dir() {
+        fs::create_dir_all(path)?;
+        for entry in fs::read_dir(backup)? {
+            let entry = entry?;
+            fs::copy(entry.path(), path.join(entry.file_name()))?;
+        }
+    } else {
+        match path.parent() {
+            Some(dir) => fs::create_dir_all(dir)?,
+            None => {}
+        }
+        fs::copy(backup, path)?;
+    }
+    return Ok(());
+}
+
+/// Keeps everything in memory, for tests and ephemeral client spools
+pub struct MemoryStore {
+    tables: RwLock<HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>>
+}
+impl MemoryStore {
+    pub fn new() -> Self {
+        return MemoryStore {
+            tables: RwLock::new(HashMap::new())
+        }
+    }
+}
+impl Store for MemoryStore {
+    fn put(&self, table: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.tables.write().unwrap()
+            .entry(String::from(table))
+            .or_insert_with(BTreeMap::new)
+            .insert(key.to_vec(), value.to_vec());
+        return Ok(());
+    }
+    fn put_all(&self, entries: &[(&str, KeyValue)]) -> Result<()> {
+        let mut tables = self.tables.write().unwrap();
+        for (table, (key, value)) in entries {
+            tables.entry(String::from(*table))
+                .or_insert_with(BTreeMap::new)
+                .insert(key.clone(), value.clone());
+        }
+        return Ok(());
+    }
+    fn get(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        return Ok(self.tables.read().unwrap()
+            .get(table)
+            .and_then(|t| t.get(key).cloned()));
+    }
+    fn delete(&self, table: &str, key: &[u8]) -> Result<()> {
+        match self.tables.write().unwrap().get_mut(table) {
+            Some(t) => {
+                t.remove(key);
+            }
+            None => {}
+        }
+        return Ok(());
+    }
+    fn scan(&self, table: &str, after: Option<&[u8]>, limit: usize) -> Result<Vec<KeyValue>> {
+        let tables = self.tables.read().unwrap();
+        let t = match tables.get(table) {
+            Some(t) => t,
+            None => return Ok(Vec::new())
+        };
+        let start = after.map_or(Unbounded, |k| Excluded(k.to_vec()));
+        return Ok(t.range((start, Unbounded))
+            .take(limit)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect());
+    }
+    fn scan_reverse(&self, table: &str, before: Option<&[u8]>, limit: usize) -> Result<Vec<KeyValue>> {
+        let tables = self.tables.read().unwrap();
+        let t = match tables.get(table) {
+            Some(t) => t,
+            None => return Ok(Vec::new())
+        };
+        let end = before.map_or(Unbounded, |k| Excluded(k.to_vec()));
+        return Ok(t.range((Unbounded, end))
+            .rev()
+            .take(limit)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect());
+    }
+    fn count(&self, table: &str, after: Option<&[u8]>) -> Result<u64> {
+        let start = after.map_or(Unbounded, |k| Excluded(k.to_vec()));
+        return Ok(self.tables.read().unwrap()
+            .get(table)
+            .map_or(0, |t| t.range((start, Unbounded)).count() as u64));
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::waitmate::store::{MemoryStore, Store};
+
+    /// Exercise the basic contract of a store; shared by every backend's tests
+    pub fn check_store(store: &dyn Store) {
+        assert_eq!(None, store.get("log", b"a").unwrap());
+        store.append(b"b", b"2").unwrap();
+        store.append(b"a", b"1").unwrap();
+        store.append(b"c", b"3").unwrap();
+        store.put("agents", b"a", b"x").unwrap();
+        assert_eq!(Some(b"1".to_vec()), store.get("log", b"a").unwrap());
+
+        let all = store.scan("log", None, 10).unwrap();
+        assert_eq!(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()],
+                   all.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>());
+        let after = store.scan("log", Some(b"a"), 1).unwrap();
+        assert_eq!(vec![(b"b".to_vec(), b"2".to_vec())], after);
+        assert!(store.scan("log", Some(b"c"), 10).unwrap().is_empty());
+        assert_eq!(1, store.scan("agents", None, 10).unwrap().len());
+        let reverse = store.scan_reverse("log", None, 2).unwrap();
+        assert_eq!(vec![b"c".to_vec(), b"b".to_vec()],
+                   reverse.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>());
+        assert_eq!(vec![(b"a".to_vec(), b"1".to_vec())], store.scan_reverse("log", Some(b"b"), 10).unwrap());
+        assert_eq!(vec![(b"b".to_vec(), b"2".to_vec())], store.scan_reverse("log", Some(b"bb"), 1).unwrap());
+        assert!(store.scan_reverse("log", Some(b"a"), 10).unwrap().is_empty());
+        assert_eq!(3, store.count("log", None).unwrap());
+        assert_eq!(2, store.count("log", Some(b"a")).unwrap());
+        assert_eq!(1, store.count("log", Some(b"bb")).unwrap());
+        assert_eq!(0, store.count("log", Some(b"c")).unwrap());
+
+        store.delete("log", b"b").unwrap();
+        assert_eq!(None, store.get("log", b"b").unwrap());
+        assert_eq!(2, store.scan("log", None, 10).unwrap().len());
+
+        store.put_all(&[("log", (b"d".to_vec(), b"4".to_vec())), ("agents", (b"b".to_vec(), b"y".to_vec()))]).unwrap();
+        assert_eq!(Some(b"4".to_vec()), store.get("log", b"d").unwrap());
+        assert_eq!(2, store.scan("agents", None, 10).unwrap().len());
+        store.delete("log", b"d").unwrap();
+
+        // tables nobody wrote to are empty
+        assert_eq!(None, store.get("unwritten", b"a").unwrap());
+        assert!(store.scan("unwritten", None, 10).unwrap().is_empty());
+        assert!(store.scan_reverse("unwritten", None, 10).unwrap().is_empty());
+        assert_eq!(0, store.count("unwritten", None).unwrap());
+        store.delete("unwritten", b"a").unwrap();
+
+        assert_eq!(None, store.offset("markie").unwrap());
+        store.set_offset("markie", b"a").unwrap();
+        assert_eq!(Some(b"a".to_vec()), store.offset("markie").unwrap());
+    }
+
+    #[test]
+    fn test_memory_store() {
+        check_store(&MemoryStore::new());
+    }
+}
@@ -1,39 +1,151 @@
+use std::any::Any;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::thread;
 use std::thread::JoinHandle;
-use log::info;
+use std::time::{Duration, Instant};
+use log::{error, info, warn};
 
-use crossbeam::channel::{bounded, Receiver, Sender, unbounded};
+use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError, unbounded};
 
-use crate::waitmate::api::{Event, EventBus, Notifier, Waiter};
+use crate::waitmate::api::{Event, EventBus, Level, Notifier, Waiter};
+use crate::waitmate::dedup::DUPLICATE_OF;
+use crate::waitmate::error::{Error, INTERNAL, Result};
+use crate::waitmate::filter::Filter;
 use crate::waitmate::log::EventLog;
+use crate::waitmate::silences::SILENCED_BY;
+use crate::waitmate::store::OFFSETS;
 
 pub trait Producer {
+    fn name(&self) -> &str;
     fn channel(&self) -> &Receiver<Option<Event>>;
+    /// How many events were dropped since the last call
+    fn take_dropped(&self) -> u64 {
+        return 0;
+    }
+}
+
+/// What a full channel does with the next event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Wait for room, holding up the waiter
+    Block,
+    /// Make room by dropping the oldest queued event
+    DropOldest,
+    /// Drop the new event
+    DropNewest,
+    /// Keep every nth event that arrives while full, waiting for room for it, and drop the rest
+    Sample(u32),
+}
+impl FromStr for Overflow {
+    type Err = Error;
+    /// `block`, `drop_oldest`, `drop_newest`, `sample` (one in 10) or `sample:<n>`
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Config(format!("Expected block, drop_oldest, drop_newest or sample:<n>, not {}", s));
+        return match s {
+            "block" => Ok(Overflow::Block),
+            "drop_oldest" => Ok(Overflow::DropOldest),
+            "drop_newest" => Ok(Overflow::DropNewest),
+            "sample" => Ok(Overflow::Sample(10)),
+            s if s.starts_with("sample:") => match s["sample:".len()..].parse() {
+                Ok(n) if n > 0 => Ok(Overflow::Sample(n)),
+                _ => Err(invalid())
+            },
+            _ => Err(invalid())
+        };
+    }
+}
+
+/// How many events a waiter may get ahead of the log, and what happens beyond that
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelPolicy {
+    pub capacity: usize,
+    pub overflow: Overflow
+}
+impl Default for ChannelPolicy {
+    fn default() -> Self {
+        return ChannelPolicy {
+            capacity: 10_000,
+            overflow: Overflow::Block
+        };
+    }
 }
 
 pub struct EventChannel {
     sender: Sender<Option<Event>>,
+    /// Our end of the channel for dropping the oldest event, when that is the policy
+    oldest: Option<Receiver<Option<Event>>>,
+    overflow: Overflow,
+    /// Events that arrived while the channel was full, for sampling
+    overflowed: AtomicU64,
+    dropped: Arc<AtomicU64>,
+    stopping: Arc<AtomicBool>,
     done: bool
 }
 impl EventChannel {
     pub fn new() -> (EventChannel, Receiver<Option<Event>>) {
-        let (sender, receiver): (Sender<Option<Event>>, Receiver<Option<Event>>) = unbounded();
+        return EventChannel::with_sender(unbounded(), Overflow::Block);
+    }
+    /// A channel holding at most `policy.capacity` events
+    pub fn bounded(policy: ChannelPolicy) -> (EventChannel, Receiver<Option<Event>>) {
+        return EventChannel::with_sender(bounded(policy.capacity), policy.overflow);
+    }
+    fn with_sender((sender, receiver): (Sender<Option<Event>>, Receiver<Option<Event>>), overflow: Overflow)
+            -> (EventChannel, Receiver<Option<Event>>) {
+        let oldest = if overflow == Overflow::DropOldest { Some(receiver.clone()) } else { None };
         return (EventChannel {
             sender,
+            oldest,
+            overflow,
+            overflowed: AtomicU64::new(0),
+            dropped: Arc::new(AtomicU64::new(0)),
+            stopping: Arc::new(AtomicBool::new(false)),
             done: false
         }, receiver);
     }
+    fn stopper(&self) -> Arc<AtomicBool> {
+        return self.stopping.clone();
+    }
+    fn dropped(&self) -> Arc<AtomicU64> {
+        return self.dropped.clone();
+    }
     pub fn done(&mut self) {
         if !self.done {
             self.done = true;
+            // without our own receiver a blocked send ends when the app lets go of the channel
+            self.oldest = None;
             self.sender.send(None).unwrap_or(());
         }
     }
 }
 impl EventBus for EventChannel {
     fn publish(&self, event: Event) {
-        self.sender.send(Some(event)).unwrap();
+        let mut event = match self.sender.try_send(Some(event)) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+            Err(TrySendError::Full(event)) => event
+        };
+        match (self.overflow, self.oldest.as_ref()) {
+            (Overflow::DropOldest, Some(oldest)) => loop {
+                match oldest.try_recv() {
+                    Ok(Some(_)) => { self.dropped.fetch_add(1, Ordering::SeqCst); }
+                    _ => {}
+                }
+                event = match self.sender.try_send(event) {
+                    Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+                    Err(TrySendError::Full(event)) => event
+                };
+            },
+            (Overflow::DropNewest, _) => { self.dropped.fetch_add(1, Ordering::SeqCst); }
+            (Overflow::Sample(n), _) if (self.overflowed.fetch_add(1, Ordering::SeqCst) + 1) % u64::from(n.max(1)) != 0 => {
+                self.dropped.fetch_add(1, Ordering::SeqCst);
+            }
+            _ => self.sender.send(event).unwrap_or(())
+        }
+    }
+    fn stopping(&self) -> bool {
+        return self.stopping.load(Ordering::SeqCst);
     }
 }
 impl Drop for EventChannel {
@@ -42,77 +154,460 @@ impl Drop for EventChannel {
     }
 }
 
+/// How a crashed waiter or notifier is restarted: after `initial`, doubling up to `max`,
+/// giving up after `max_restarts` crashes in a row.  A run that lasts longer than `max`
+/// starts the count again.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub max_restarts: u32
+}
+impl Default for Backoff {
+    fn default() -> Self {
+        return Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            max_restarts: 10
+        };
+    }
+}
+impl Backoff {
+    /// How long to wait before restarting after `restarts` restarts in a row
+    pub fn delay(&self, restarts: u32) -> Duration {
+        return self.initial
+            .checked_mul(1 << restarts.min(31))
+            .map_or(self.max, |d| d.min(self.max));
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    return match panic.downcast::<String>() {
+        Ok(s) => *s,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(s) => String::from(*s),
+            Err(_) => String::from("panicked")
+        }
+    };
+}
+
+fn supervisor_event(name: &str, source: &str, description: String, level: Level, restarts: u32) -> Event {
+    let mut event = Event::builder(name)
+        .description(description.as_str())
+        .category(INTERNAL)
+        .level(level)
+        .label("component", source)
+        .label("restarts", restarts.to_string().as_str())
+        .build();
+    event.source = String::from(source);
+    return event;
+}
+
+/// Run `component` until it returns `Ok`, restarting it with `backoff` whenever it panics
+/// or returns an error.  Each crash, restart and the final give up are published on `bus`.
+/// Nothing is restarted once `bus` is stopping, and waiting to restart ends early when
+/// `stop` receives `false` or disconnects.
+pub fn supervise<F>(name: &str, backoff: &Backoff, bus: &dyn EventBus, stop: Option<&Receiver<bool>>, mut component: F)
+    where F: FnMut() -> Result<()> {
+    let mut restarts = 0;
+    loop {
+        let started = Instant::now();
+        let reason = match catch_unwind(AssertUnwindSafe(|| component())) {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => e.to_string(),
+            Err(panic) => panic_message(panic)
+        };
+        if started.elapsed() > backoff.max {
+            restarts = 0;
+        }
+        error!("{} crashed: {}", name, reason);
+        bus.publish(supervisor_event("Crashed", name, format!("{} crashed: {}", name, reason),
+                                     Level::ERROR, restarts));
+        if bus.stopping() {
+            return;
+        }
+        if restarts >= backoff.max_restarts {
+            error!("{} crashed {} times in a row, giving up", name, restarts + 1);
+            bus.publish(supervisor_event("Gave up", name,
+                                         format!("{} crashed {} times in a row and was not restarted", name, restarts + 1),
+                                         Level::CRITICAL, restarts));
+            return;
+        }
+
+        let deadline = Instant::now() + backoff.delay(restarts);
+        match stop {
+            Some(stop) => {
+                loop {
+                    match stop.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                        Ok(true) => continue,
+                        Ok(false) | Err(RecvTimeoutError::Disconnected) => return,
+                        Err(RecvTimeoutError::Timeout) => break
+                    }
+                }
+            }
+            None => thread::sleep(deadline.saturating_duration_since(Instant::now()))
+        }
+        restarts += 1;
+        info!("{} restarting, attempt {}", name, restarts);
+        bus.publish(supervisor_event("Restarted", name, format!("{} restarted after {} crashes", name, restarts),
+                                     Level::INFO, restarts));
+    }
+}
+
+/// Which events a notifier is given: those at or above `min_level` that match `filter`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub min_level: Level,
+    pub filter: Option<Filter>
+}
+impl Route {
+    pub fn new(min_level: Level) -> Route {
+        return Route {
+            min_level,
+            filter: None
+        };
+    }
+    pub fn matches(&self, event: &Event) -> bool {
+        return event.level >= self.min_level && self.filter.as_ref().map_or(true, |f| f.matches(event));
+    }
+}
+
 pub struct NotifierThread {
-    handle: Option<JoinHandle<()>>,
+    name: String,
+    handle: JoinHandle<Box<dyn Notifier>>,
     receiver: Receiver<Option<Event>>,
-    tickler: Sender<bool>
+    tickler: Sender<bool>,
+    stopping: Arc<AtomicBool>
 }
 impl NotifierThread {
-    pub fn new(notifier: Box<dyn Notifier>, event_log: Arc<EventLog>) -> NotifierThread {
-        let (tickler, ticklee): (Sender<bool>, Receiver<bool>) = bounded(1);
+    /// Run `notifier` against the log, skipping events off its `route`.  Skipped events still
+    /// move its offset on.  A notifier that panics is restarted from its stored offset, past
+    /// the event that crashed it.
+    pub fn new(notifier: Box<dyn Notifier>, event_log: Arc<EventLog>, route: Route, backoff: Backoff) -> NotifierThread {
+        // room for a pending tickle and the stop behind it
+        let (tickler, ticklee): (Sender<bool>, Receiver<bool>) = bounded(2);
         let (event_bus, receiver) = EventChannel::new();
+        let stopping = event_bus.stopper();
+        let name = String::from(notifier.name());
         let handle = thread::Builder::new()
-            .name(String::from(notifier.name()))
+            .name(name.clone())
             .spawn(move || {
-                let cursor = event_log.build_cursor()
-                    .named(notifier.name())
-                    .tailing(Some(ticklee))
-                    .build();
-                for (_, event) in cursor {
-                    notifier.notify(event, &event_bus);
-                }
+                supervise(notifier.name(), &backoff, &event_bus, Some(&ticklee), || {
+                    NotifierThread::deliver(notifier.as_ref(), &event_log, &ticklee, &route, &event_bus)
+                });
+                return notifier;
             }).unwrap();
 
         return NotifierThread {
-            handle: Some(handle),
+            name,
+            handle,
             receiver,
-            tickler
+            tickler,
+            stopping
         };
     }
+    fn deliver(notifier: &dyn Notifier, event_log: &EventLog, ticklee: &Receiver<bool>, route: &Route,
+               event_bus: &dyn EventBus) -> Result<()> {
+        let cursor = event_log.build_cursor()
+            .named(notifier.name())
+            .tailing(Some(ticklee.clone()))
+            .build()?;
+        let mut delivered = event_log.get_entry(OFFSETS, notifier.name().as_bytes())?.unwrap_or_default();
+        for item in cursor {
+            match item {
+                Ok((key, event)) => {
+                    let previous = std::mem::replace(&mut delivered, key.into_bytes());
+                    // duplicates are summarised later rather than notified, silenced events never
+                    if !route.matches(&event) || event.labels.contains_key(DUPLICATE_OF)
+                        || event.labels.contains_key(SILENCED_BY) {
+                        continue;
+                    }
+                    // never report a failure to deliver our own failure report
+                    let own = event.category == INTERNAL && event.source == notifier.name();
+                    match notifier.notify(event, event_bus) {
+                        Ok(()) => {}
+                        Err(Error::Net(e)) => {
+                            // the other end is unreachable, so back off and retry from this event
+                            event_log.put_entry(OFFSETS, notifier.name().as_bytes(), &previous)?;
+                            return Err(Error::Net(e));
+                        }
+                        Err(e) => {
+                            error!("{} failed: {}", notifier.name(), e);
+                            if !own {
+                                event_bus.publish(e.event(notifier.name(), "Notification failed"));
+                            }
+                        }
+                    }
+                }
+                Err(Error::Decode(e)) => warn!("{} skipping: {}", notifier.name(), e),
+                Err(e) => return Err(e)
+            }
+        }
+        return Ok(());
+    }
     pub fn tickle(&self) {
         if self.tickler.is_empty() {
-            self.tickler.send(true).unwrap();
+            self.tickler.try_send(true).unwrap_or(());
         }
     }
-}
-impl Drop for NotifierThread {
-    fn drop(&mut self) {
-        self.tickler.send(false).unwrap();
-        self.handle
-            .take().unwrap()
-            .join().unwrap();
+    /// Deliver what is already in the log, then finish
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.tickler.try_send(false).unwrap_or(());
+    }
+    /// Wait for the thread to finish, which it does soon after its channel disconnects,
+    /// and take back the notifier so that it can be started again
+    pub fn join(self) -> Option<Box<dyn Notifier>> {
+        return self.handle.join().ok();
     }
 }
 impl Producer for NotifierThread {
+    fn name(&self) -> &str {
+        return self.name.as_str();
+    }
     fn channel(&self) -> &Receiver<Option<Event>> {
         return &self.receiver;
     }
 }
 
 pub struct WaiterThread {
+    name: String,
     handle: JoinHandle<()>,
-    receiver: Receiver<Option<Event>>
+    receiver: Receiver<Option<Event>>,
+    stopping: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>
 }
 impl WaiterThread {
-    pub fn new(waiter: Box<dyn Waiter>) -> WaiterThread {
-        let (event_bus, receiver) = EventChannel::new();
+    /// Run `waiter`, calling `wait` again with `backoff` whenever it panics or fails.  What
+    /// it publishes queues in a channel bounded by `channel`.
+    pub fn new(waiter: Box<dyn Waiter>, backoff: Backoff, channel: ChannelPolicy) -> WaiterThread {
+        let (event_bus, receiver) = EventChannel::bounded(channel);
+        let stopping = event_bus.stopper();
+        let dropped = event_bus.dropped();
+        let name = String::from(waiter.name());
 
         let handle = thread::Builder::new()
-            .name(String::from(waiter.name()))
+            .name(name.clone())
             .spawn(move || {
                 info!("{} starting", waiter.name());
-                waiter.wait(&event_bus);
+                supervise(waiter.name(), &backoff, &event_bus, None, || waiter.wait(&event_bus));
                 info!("{} finished", waiter.name());
             }).unwrap();
 
         return WaiterThread {
+            name,
             handle,
-            receiver
+            receiver,
+            stopping,
+            dropped
         };
     }
+    /// Ask the waiter to stop accepting input.  Waiters that block on input they cannot
+    /// interrupt, such as stdin, only notice once that input arrives.
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+    /// Wait for the thread to finish, which it does soon after its channel disconnects
+    pub fn join(self) {
+        self.handle.join().unwrap_or(());
+    }
 }
 impl Producer for WaiterThread {
+    fn name(&self) -> &str {
+        return self.name.as_str();
+    }
     fn channel(&self) -> &Receiver<Option<Event>> {
         return &self.receiver;
     }
-}
\ No newline at end of file
+    fn take_dropped(&self) -> u64 {
+        return self.dropped.swap(0, Ordering::SeqCst);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use crossbeam::channel::{Sender, unbounded};
+
+    use crate::waitmate::api::{EmptyNamed, Event, EventBus, Level, Named, Notifier};
+    use crate::waitmate::dedup::DUPLICATE_OF;
+    use crate::waitmate::error::{Error, Result};
+    use crate::waitmate::log::EventLog;
+    use crate::waitmate::silences::SILENCED_BY;
+    use crate::waitmate::thread::{Backoff, ChannelPolicy, EventChannel, NotifierThread, Overflow, Producer, Route,
+                                  supervise};
+
+    struct ChannelNotifier {
+        sender: Sender<Event>
+    }
+    impl Named for ChannelNotifier {
+        fn name(&self) -> &str {
+            return "ChannelNotifier";
+        }
+    }
+    impl Notifier for ChannelNotifier {
+        fn notify(&self, event: Event, _: &dyn EventBus) -> Result<()> {
+            self.sender.send(event).unwrap();
+            return Ok(());
+        }
+    }
+
+    /// Can't reach the other end the first time it sees an ERROR
+    struct UnreachableNotifier {
+        sender: Sender<Event>,
+        failed: AtomicBool
+    }
+    impl Named for UnreachableNotifier {
+        fn name(&self) -> &str {
+            return "UnreachableNotifier";
+        }
+    }
+    impl Notifier for UnreachableNotifier {
+        fn notify(&self, event: Event, _: &dyn EventBus) -> Result<()> {
+            if event.level == Level::ERROR && !self.failed.swap(true, Ordering::SeqCst) {
+                return Err(Error::Net(String::from("timed out")));
+            }
+            self.sender.send(event).unwrap();
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            max_restarts: 3
+        };
+        assert_eq!(Duration::from_millis(100), backoff.delay(0));
+        assert_eq!(Duration::from_millis(400), backoff.delay(2));
+        assert_eq!(Duration::from_secs(1), backoff.delay(4));
+        assert_eq!(Duration::from_secs(1), backoff.delay(100));
+    }
+
+    #[test]
+    fn test_supervise_restarts() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_secs(10),
+            max_restarts: 3
+        };
+        let (bus, receiver) = EventChannel::new();
+        let mut runs = 0;
+        supervise("flaky", &backoff, &bus, None, || {
+            runs += 1;
+            match runs {
+                1 => panic!("boom"),
+                2 => Err(Error::Invalid(String::from("bad"))),
+                _ => Ok(())
+            }
+        });
+        assert_eq!(3, runs);
+        let events = receiver.try_iter().map(|e| e.unwrap()).collect::<Vec<_>>();
+        let names = events.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["Crashed", "Restarted", "Crashed", "Restarted"], names);
+        assert!(events[0].description.contains("boom"));
+        assert_eq!("flaky", events[2].source);
+        assert_eq!(Level::ERROR, events[2].level);
+        assert_eq!(Some(&String::from("2")), events[3].labels.get("restarts"));
+
+        let mut runs = 0;
+        supervise("broken", &backoff, &bus, None, || {
+            runs += 1;
+            return Err(Error::Invalid(String::from("bad")));
+        });
+        assert_eq!(4, runs);
+        let last = receiver.try_iter().last().unwrap().unwrap();
+        assert_eq!("Gave up", last.name);
+        assert_eq!(Level::CRITICAL, last.level);
+    }
+
+    #[test]
+    fn test_notifier_drains_on_stop() {
+        let event_log = Arc::new(EventLog::memory());
+        let source = EmptyNamed {};
+        for _ in 0..3 {
+            event_log.add(&Event::new(&source, "a", "b", "c", Level::WARN)).unwrap();
+        }
+        let (sender, delivered) = unbounded();
+        let notifier = NotifierThread::new(Box::new(ChannelNotifier { sender }), event_log.clone(),
+                                           Route::new(Level::TRACE), Backoff::default());
+        notifier.stop();
+        assert!(notifier.channel().recv_timeout(Duration::from_secs(1)).unwrap().is_none());
+        notifier.join();
+        assert_eq!(3, delivered.try_iter().count());
+        assert_eq!(0, event_log.pending("ChannelNotifier").unwrap());
+    }
+
+    #[test]
+    fn test_notifier_retries_unreachable() {
+        let event_log = Arc::new(EventLog::memory());
+        let source = EmptyNamed {};
+        for level in &[Level::WARN, Level::ERROR, Level::WARN] {
+            event_log.add(&Event::new(&source, "a", "b", "c", *level)).unwrap();
+        }
+        let (sender, delivered) = unbounded();
+        let backoff = Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(100),
+            max_restarts: 3
+        };
+        let notifier = NotifierThread::new(Box::new(UnreachableNotifier { sender, failed: AtomicBool::new(false) }),
+                                           event_log.clone(), Route::new(Level::TRACE), backoff);
+        let levels = (0..3)
+            .map(|_| delivered.recv_timeout(Duration::from_secs(1)).unwrap().level)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Level::WARN, Level::ERROR, Level::WARN], levels);
+        notifier.stop();
+        notifier.join();
+        assert_eq!(0, event_log.pending("UnreachableNotifier").unwrap());
+    }
+
+    #[test]
+    fn test_notifier_route() {
+        let event_log = Arc::new(EventLog::memory());
+        let source = EmptyNamed {};
+        event_log.add(&Event::new(&source, "a", "b", "db", Level::ERROR)).unwrap();
+        event_log.add(&Event::new(&source, "a", "b", "web", Level::ERROR)).unwrap();
+        event_log.add(&Event::new(&source, "a", "b", "db", Level::INFO)).unwrap();
+        event_log.add(&Event::new(&source, "a", "b", "db", Level::ERROR).label(DUPLICATE_OF, "x")).unwrap();
+        event_log.add(&Event::new(&source, "a", "b", "db", Level::ERROR).label(SILENCED_BY, "y")).unwrap();
+        let (sender, delivered) = unbounded();
+        let route = Route {
+            min_level: Level::WARN,
+            filter: Some("category == db".parse().unwrap())
+        };
+        let notifier = NotifierThread::new(Box::new(ChannelNotifier { sender }), event_log.clone(), route,
+                                           Backoff::default());
+        notifier.stop();
+        assert!(notifier.channel().recv_timeout(Duration::from_secs(1)).unwrap().is_none());
+        notifier.join();
+        assert_eq!(1, delivered.try_iter().count());
+        assert_eq!(0, event_log.pending("ChannelNotifier").unwrap());
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(Overflow::DropOldest, "drop_oldest".parse().unwrap());
+        assert_eq!(Overflow::Sample(10), "sample".parse().unwrap());
+        assert_eq!(Overflow::Sample(3), "sample:3".parse().unwrap());
+        assert!("sample:0".parse::<Overflow>().is_err());
+        assert!("spill".parse::<Overflow>().is_err());
+
+        let source = EmptyNamed {};
+        let published = |overflow: Overflow, count: usize| {
+            let (bus, receiver) = EventChannel::bounded(ChannelPolicy { capacity: 2, overflow });
+            for i in 0..count {
+                bus.publish(Event::new(&source, i.to_string().as_str(), "b", "c", Level::WARN));
+            }
+            let names = receiver.try_iter().map(|e| e.unwrap().name).collect::<Vec<_>>();
+            return (names, bus.dropped().load(std::sync::atomic::Ordering::SeqCst));
+        };
+        assert_eq!((vec![String::from("4"), String::from("5")], 4), published(Overflow::DropOldest, 6));
+        assert_eq!((vec![String::from("0"), String::from("1")], 4), published(Overflow::DropNewest, 6));
+        // the third event to find the channel full would wait for room
+        assert_eq!((vec![String::from("0"), String::from("1")], 2), published(Overflow::Sample(3), 4));
+    }
+}
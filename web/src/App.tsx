@@ -6,11 +6,17 @@ import Api, {Socket} from './Api';
 const api: Api = new Api();
 const socket: Socket = api.getSocket();
 
-interface State {}
+interface State {
+    labelFilter: string
+}
 
 
 class App extends React.Component<{}, State> {
 
+    state: State = {
+        labelFilter: ''
+    }
+
     componentDidMount(): void {
         socket.addListener((e) => {
             console.log("Event: ", e);
@@ -20,6 +26,10 @@ class App extends React.Component<{}, State> {
     handleEventSelect(eventId: string) {
     }
 
+    handleLabelFilter(evt: React.ChangeEvent<HTMLInputElement>) {
+        this.setState({labelFilter: evt.target.value});
+    }
+
     render() {
         return (
             <div>
@@ -27,6 +37,12 @@ class App extends React.Component<{}, State> {
                     <div className="flex items-center flex-shrink-0 text-white mr-6">
                         <span className="font-semibold text-xl tracking-tight">waitmate</span>
                     </div>
+                    <div className="flex-grow">
+                        <input className="rounded px-2 py-1 text-sm w-64"
+                               placeholder="env:prod,host"
+                               value={this.state.labelFilter}
+                               onChange={this.handleLabelFilter.bind(this)}/>
+                    </div>
                     <div className="block lg:hidden">
                         <button
                             className="flex items-center px-3 py-2 border rounded text-teal-200 border-teal-400 hover:text-white hover:border-white">
@@ -40,6 +56,7 @@ class App extends React.Component<{}, State> {
 
                 <EventList selected=""
                            onSelect={this.handleEventSelect.bind(this)}
+                           labelFilter={this.state.labelFilter}
                            messenger={socket}
                            loader={api.getEvents.bind(api)} />
             </div>
@@ -29,6 +29,14 @@ export default class EventCard extends React.Component<Props, State> {
                         <p className="mt-3 text-gray-700 text-sm">
                             {event.description}
                         </p>
+                        <div className="mt-2 flex flex-wrap">
+                            {Object.entries(event.labels || {}).map(([k, v]) => (
+                                <span key={k}
+                                      className="mr-1 mb-1 px-2 rounded-full bg-gray-200 text-gray-700 text-xs">
+                                    {k}:{v}
+                                </span>
+                            ))}
+                        </div>
                         <div className="mt-4 flex items-center">
                             <div className="flex mr-2 text-gray-700 text-sm mr-3">
                                 <svg fill="none" viewBox="0 0 24 24" className="w-4 h-4 mr-1" stroke="currentColor">
@@ -1,5 +1,5 @@
 import * as React from 'react';
-import {Event} from './Model';
+import {Event, matchesLabels} from './Model';
 import {LoadableComponent, LoadableProps, LoadableState} from './LoadableComponent';
 import EventCard from "./EventCard";
 import {Socket, SocketState} from "./Api";
@@ -7,6 +7,7 @@ import {Socket, SocketState} from "./Api";
 interface Props extends LoadableProps<Event[]> {
     selected: string,
     onSelect: (id: string) => void,
+    labelFilter: string,
     messenger: Socket
 }
 
@@ -43,13 +44,15 @@ export default class EventList extends LoadableComponent<Event[], Props, Loadabl
             return (<div>No current events</div>)
         }
         let byCat: {[name: string]: Event[]} = {};
-        this.state.data.forEach((e) => {
-            let events = byCat[e.category];
-            if (!events) {
-                byCat[e.category] = events = [];
-            }
-            events.push(e);
-        });
+        this.state.data
+            .filter((e) => matchesLabels(e, this.props.labelFilter))
+            .forEach((e) => {
+                let events = byCat[e.category];
+                if (!events) {
+                    byCat[e.category] = events = [];
+                }
+                events.push(e);
+            });
         return (<div className="flex flex-wrap">{Object.values(byCat).map(e => (
             <EventCard key={e[0].category} event={e[e.length-1]} count={e.length}/>
         ))}</div>);
@@ -7,4 +7,28 @@ export class Event {
     category: string = '';
     level: string = '';
     source: string = '';
+    occurred_at?: number;
+    host: string = '';
+    correlation_id?: string;
+    parent_id?: string;
+    fingerprint?: string;
+    labels: {[key: string]: string} = {};
+}
+
+/**
+ * Match an event against a comma separated list of key:value label pairs.  A bare key
+ * only needs to be present.
+ */
+export function matchesLabels(event: Event, filter: string): boolean {
+    const labels = event.labels || {};
+    return filter.split(',')
+        .map(p => p.trim())
+        .filter(p => p.length > 0)
+        .every(p => {
+            const i = p.indexOf(':');
+            if (i < 0) {
+                return labels[p] !== undefined;
+            }
+            return labels[p.substr(0, i)] === p.substr(i + 1);
+        });
 }
\ No newline at end of file